@@ -0,0 +1,96 @@
+// Timings of the renderer's hot paths, run with `cargo bench`. Each benchmark is run a few
+// times to warm up and then timed over a fixed number of runs; the fastest and the average
+// run are printed, the fastest being the one least disturbed by whatever else the machine
+// is doing. Scenes are built in memory, no window is opened.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use nalgebra_glm::Vec3;
+use raytracing_animal::framebuffer::Framebuffer;
+use raytracing_animal::ray_intersect::{Material, Ray, RayIntersect, RayPacket};
+use raytracing_animal::render::{render, RenderSettings};
+use raytracing_animal::scene::{Scene, DEFAULT_SCENE};
+use raytracing_animal::sphere::Sphere;
+
+const WARMUP_RUNS: u32 = 3;
+
+// Rays shot at the sphere per run of the intersection benchmarks
+const SPHERE_RAYS: usize = 100_000;
+// Rays per packet in the batched one, as many as the renderer's 8 x 8 packets hold
+const PACKET_RAYS: usize = 64;
+
+fn main() {
+    // `cargo test --benches` runs this with `--bench` missing, only to check that it works
+    let quick = !std::env::args().any(|arg| arg == "--bench");
+    let runs = if quick { 1 } else { 20 };
+
+    let sphere = Sphere {
+        center: Vec3::new(0.0, 0.0, -5.0),
+        radius: 1.0,
+        material: Material::default(),
+        velocity: Vec3::zeros(),
+    };
+    // A fan of rays from the origin over a square twice as wide as the sphere, so about
+    // three quarters of them hit it
+    let side = (SPHERE_RAYS as f32).sqrt() as usize;
+    let rays: Vec<Ray> = (0..side * side)
+        .map(|i| {
+            let (x, y) = ((i % side) as f32 / side as f32, (i / side) as f32 / side as f32);
+            Ray::new(Vec3::zeros(), Vec3::new(0.4 * x - 0.2, 0.4 * y - 0.2, -1.0).normalize())
+        })
+        .collect();
+    let time = bench("sphere intersection", runs, || {
+        rays.iter().filter(|ray| sphere.ray_intersect(black_box(ray), 1e-4, f32::INFINITY).is_intersecting).count()
+    });
+    println!("{:>24}  {:.1} million rays per second", "", rays.len() as f64 / time.as_secs_f64() / 1e6);
+
+    // The same rays a packet at a time; putting them into packets isn't timed
+    let packets: Vec<RayPacket> = rays.chunks(PACKET_RAYS).map(RayPacket::new).collect();
+    let time = bench("sphere batch", runs, || {
+        packets
+            .iter()
+            .map(|packet| sphere.ray_intersect_batch(black_box(packet), 1e-4, f32::INFINITY).iter().filter(|hit| hit.is_intersecting).count())
+            .sum::<usize>()
+    });
+    println!("{:>24}  {:.1} million rays per second", "", rays.len() as f64 / time.as_secs_f64() / 1e6);
+
+    let teddy = Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").expect("the built-in scene parses");
+    bench_frame("teddy frame 200x150", &teddy, runs);
+
+    // Every hit casts a shadow ray at each of the 24 lanterns
+    let lanterns = Scene::parse(include_str!("../scenes/teddy_lanterns.ron"), "scenes/teddy_lanterns.ron")
+        .expect("the lanterns scene parses");
+    bench_frame("lanterns frame 200x150", &lanterns, runs);
+}
+
+// Renders `scene` at 200 x 150 with the settings the binary starts with
+fn bench_frame(name: &str, scene: &Scene, runs: u32) {
+    let settings = RenderSettings::default();
+    let mut framebuffer = Framebuffer::new(200, 150);
+    bench(name, runs, || render(&mut framebuffer, black_box(scene), &scene.camera, &settings));
+}
+
+// Times `run` and prints how long it took, returning the fastest run
+fn bench<T>(name: &str, runs: u32, mut run: impl FnMut() -> T) -> Duration {
+    for _ in 0..WARMUP_RUNS.min(runs) {
+        black_box(run());
+    }
+    let mut fastest = Duration::MAX;
+    let mut total = Duration::ZERO;
+    for _ in 0..runs {
+        let start = Instant::now();
+        black_box(run());
+        let elapsed = start.elapsed();
+        fastest = fastest.min(elapsed);
+        total += elapsed;
+    }
+    println!(
+        "{:>24}  fastest {:>8.3} ms, average {:>8.3} ms over {} runs",
+        name,
+        fastest.as_secs_f64() * 1e3,
+        total.as_secs_f64() * 1e3 / runs as f64,
+        runs
+    );
+    fastest
+}
@@ -0,0 +1,83 @@
+use nalgebra_glm::Vec3;
+
+use crate::ray_intersect::Ray;
+
+// An axis-aligned bounding box. The empty box has min above max, so growing it by any
+// other box gives that box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vec3::repeat(f32::INFINITY),
+            max: Vec3::repeat(f32::NEG_INFINITY),
+        }
+    }
+
+    // The box reaching `half_size` from `center` along each axis
+    pub fn around(center: &Vec3, half_size: &Vec3) -> Self {
+        Aabb {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    // The part the two boxes share, empty when they don't overlap
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.sup(&other.min),
+            max: self.max.inf(&other.max),
+        }
+    }
+
+    // The stretch of the ray between `t_min` and `t_max` that's inside the box, by the slab
+    // method, or `None` when the ray misses it there
+    pub fn ray_interval(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut t_near = t_min;
+        let mut t_far = t_max;
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+        (t_near <= t_far).then_some((t_near, t_far))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3::new(a.x, a.y, a.z),
+            Vec3::new(b.x, a.y, a.z),
+            Vec3::new(a.x, b.y, a.z),
+            Vec3::new(b.x, b.y, a.z),
+            Vec3::new(a.x, a.y, b.z),
+            Vec3::new(b.x, a.y, b.z),
+            Vec3::new(a.x, b.y, b.z),
+            Vec3::new(b.x, b.y, b.z),
+        ]
+    }
+}
@@ -0,0 +1,627 @@
+use nalgebra_glm::{self as glm, Vec3};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::color::Color;
+use crate::light::Light;
+use crate::rng::Rng;
+use crate::scene::{GroupMut, Object, Scene};
+use crate::transform::Transform;
+
+// The scene's first group, like the bear, bobs this far above and below where the scene
+// file put it, once every 2π seconds
+const BOB_HEIGHT: f32 = 0.1;
+
+// Groups squashed top to bottom to blink, when the scene has them
+const EYES: [&str; 2] = ["left eye", "right eye"];
+// A blink closes and reopens the eyes over this many seconds, and the next one starts
+// between these many seconds after it
+const BLINK_DURATION: f32 = 0.15;
+const BLINK_INTERVAL: (f32, f32) = (3.0, 5.0);
+// How much of their height the eyes keep when closed, so they never turn flat
+const CLOSED_EYE_SCALE: f32 = 0.05;
+
+// Keyframes for a named group of the scene, each property a list of (seconds, value) pairs
+// in increasing time. Positions are offsets from where the scene file puts the group,
+// scales stretch it along its own axes, and diffuse colors and emission strengths change
+// everything in it, so e.g. glowing eyes can pulse.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Track {
+    pub group: String,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub repeat: Repeat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Vec<(f32, Vec3)>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Vec<(f32, Vec3)>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diffuse: Option<Vec<(f32, Color)>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission_strength: Option<Vec<(f32, f32)>>,
+}
+
+// How a track gets from one keyframe to the next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    Smooth, // Eases out of each keyframe and into the next, with a smoothstep
+}
+
+// What a track does after its last keyframe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Repeat {
+    #[default]
+    Clamp, // Holds the last values
+    Loop,  // Starts over from the first keyframe, so the last should match it
+}
+
+impl Track {
+    // Why the keyframes can't be played, if they can't
+    pub fn check(&self) -> Result<(), &'static str> {
+        let times: [Option<Vec<f32>>; 4] = [
+            self.position.as_ref().map(|keys| keys.iter().map(|key| key.0).collect()),
+            self.scale.as_ref().map(|keys| keys.iter().map(|key| key.0).collect()),
+            self.diffuse.as_ref().map(|keys| keys.iter().map(|key| key.0).collect()),
+            self.emission_strength.as_ref().map(|keys| keys.iter().map(|key| key.0).collect()),
+        ];
+        for times in times.iter().flatten() {
+            if times.is_empty() {
+                return Err("has a property without keyframes");
+            }
+            if times.iter().any(|time| !time.is_finite()) || times.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err("has keyframe times that don't increase");
+            }
+        }
+        Ok(())
+    }
+
+    // Time of the last keyframe of any property
+    fn duration(&self) -> f32 {
+        let last = |times: Option<f32>| times.unwrap_or(0.0);
+        last(self.position.as_ref().and_then(|keys| keys.last()).map(|key| key.0))
+            .max(last(self.scale.as_ref().and_then(|keys| keys.last()).map(|key| key.0)))
+            .max(last(self.diffuse.as_ref().and_then(|keys| keys.last()).map(|key| key.0)))
+            .max(last(self.emission_strength.as_ref().and_then(|keys| keys.last()).map(|key| key.0)))
+    }
+
+    // The group's transform at `t` seconds, given where the scene file put it, or `None`
+    // when the track doesn't move it
+    pub fn transform_at(&self, t: f32, rest: &Transform) -> Option<Transform> {
+        if self.position.is_none() && self.scale.is_none() {
+            return None;
+        }
+        let scale = self
+            .scale
+            .as_ref()
+            .map_or(Vec3::repeat(1.0), |keys| sample(keys, self.local_time(t), self.interpolation, lerp_vec3));
+        Transform::new(glm::translation(&self.position_at(t)) * rest.matrix * glm::scaling(&scale))
+    }
+
+    // The group's offset from where the scene file put it at `t` seconds
+    pub fn position_at(&self, t: f32) -> Vec3 {
+        self.position
+            .as_ref()
+            .map_or(Vec3::zeros(), |keys| sample(keys, self.local_time(t), self.interpolation, lerp_vec3))
+    }
+
+    pub fn diffuse_at(&self, t: f32) -> Option<Color> {
+        let keys = self.diffuse.as_ref()?;
+        Some(sample(keys, self.local_time(t), self.interpolation, Color::lerp))
+    }
+
+    pub fn emission_strength_at(&self, t: f32) -> Option<f32> {
+        let keys = self.emission_strength.as_ref()?;
+        Some(sample(keys, self.local_time(t), self.interpolation, |a, b, f| a + (b - a) * f))
+    }
+
+    fn local_time(&self, t: f32) -> f32 {
+        let duration = self.duration();
+        match self.repeat {
+            Repeat::Loop if duration > 0.0 => t.rem_euclid(duration),
+            _ => t,
+        }
+    }
+}
+
+// Turns one of the scene's lights around an axis through `center`, once every `period`
+// seconds, starting from where the scene file puts it. The axis points straight up unless
+// set, so the light circles level around the center.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightOrbit {
+    pub light: usize, // Index among the scene's lights, 0 for the first
+    pub center: Vec3,
+    #[serde(default = "Vec3::y")]
+    pub axis: Vec3,
+    pub period: f32,
+}
+
+impl LightOrbit {
+    // Why the light can't go round, if it can't
+    pub fn check(&self) -> Result<(), &'static str> {
+        if !self.period.is_finite() || self.period <= 0.0 {
+            return Err("needs a period of more than 0 seconds");
+        }
+        if !self.axis.iter().all(|c| c.is_finite()) || self.axis == Vec3::zeros() {
+            return Err("needs an axis that isn't zero");
+        }
+        Ok(())
+    }
+
+    // Where a light the scene file puts at `rest` is at `t` seconds. Whole turns are taken
+    // off first, so it comes back to the same spot every period however long it plays.
+    pub fn position_at(&self, t: f32, rest: &Vec3) -> Vec3 {
+        let angle = 2.0 * PI * (t / self.period).rem_euclid(1.0);
+        self.center + glm::rotate_vec3(&(rest - self.center), angle, &self.axis.normalize())
+    }
+}
+
+fn lerp_vec3(a: Vec3, b: Vec3, f: f32) -> Vec3 {
+    a + (b - a) * f
+}
+
+// The value at `t` between the keyframes around it, or of the first or last keyframe
+// outside of them
+fn sample<T: Copy>(keys: &[(f32, T)], t: f32, interpolation: Interpolation, lerp: impl Fn(T, T, f32) -> T) -> T {
+    let next = keys.partition_point(|(time, _)| *time <= t);
+    if next == 0 {
+        return keys[0].1;
+    }
+    if next == keys.len() {
+        return keys[next - 1].1;
+    }
+    let (t0, a) = keys[next - 1];
+    let (t1, b) = keys[next];
+    let f = (t - t0) / (t1 - t0);
+    let f = match interpolation {
+        Interpolation::Linear => f,
+        Interpolation::Smooth => f * f * (3.0 - 2.0 * f),
+    };
+    lerp(a, b, f)
+}
+
+// A track being played, with the transform and velocity its group had in the scene file,
+// its objects too when the track paints them, and the last color and emission strength it
+// set
+struct Playing {
+    track: Track,
+    rest: (Transform, Vec3),
+    rest_objects: Option<Vec<Object>>,
+    diffuse: Option<Color>,
+    emission_strength: Option<f32>,
+}
+
+// What moves in a scene over time. The transforms the scene file gives the bobbing group,
+// the eyes and the tracks' groups, and the positions it gives orbiting lights, are kept, so
+// each frame places them anew rather than moving them a little further.
+pub struct Animation {
+    rng: Rng,
+    bob: Option<(Transform, Vec3)>,
+    eyes: Vec<(&'static str, Transform)>,
+    // Start of the current or next blink, in seconds since the animation started playing
+    blink_start: f32,
+    eye_scale: f32,
+    tracks: Vec<Playing>,
+    orbits: Vec<(LightOrbit, Vec3)>,
+}
+
+impl Animation {
+    // `seed` picks the times between blinks, the same seed always blinks the same way
+    pub fn new(scene: &mut Scene, seed: u64) -> Self {
+        let bob = scene.first_group().map(|group| (group.transform.clone(), *group.velocity));
+        let eyes = EYES
+            .iter()
+            .filter_map(|&name| Some((name, scene.named_group(name)?.transform.clone())))
+            .collect();
+        let tracks = scene
+            .tracks
+            .clone()
+            .into_iter()
+            .filter_map(|track| {
+                let group = scene.named_group(&track.group)?;
+                let rest = (group.transform.clone(), *group.velocity);
+                let paints = track.diffuse.is_some() || track.emission_strength.is_some();
+                let rest_objects = paints.then(|| group.objects.to_vec());
+                Some(Playing { track, rest, rest_objects, diffuse: None, emission_strength: None })
+            })
+            .collect();
+        let orbits = scene
+            .light_orbits
+            .iter()
+            .filter_map(|orbit| Some((orbit.clone(), scene.lights.get(orbit.light)?.position()?)))
+            .collect();
+        let mut rng = Rng::new(seed);
+        let blink_start = next_interval(&mut rng);
+        Animation {
+            rng,
+            bob,
+            eyes,
+            blink_start,
+            eye_scale: 1.0,
+            tracks,
+            orbits,
+        }
+    }
+
+    // Sets the scene's animation to how it is `t` seconds after it started playing. Groups
+    // that move get the velocity that takes them to where they'll be `shutter` seconds
+    // later, over the shutter interval of the frame, so they blur along their path; 0 keeps
+    // every frame sharp. Returns whether anything moved.
+    pub fn update(&mut self, scene: &mut Scene, t: f32, shutter: f32) -> bool {
+        let height = |t: f32| BOB_HEIGHT * t.sin();
+        let mut bobbed = false;
+        if let (Some((rest, _)), Some(GroupMut { transform, velocity, .. })) = (&self.bob, scene.first_group()) {
+            let bobbing = rest.moved(Vec3::new(0.0, height(t), 0.0), 0.0);
+            let smear = Vec3::new(0.0, height(t + shutter) - height(t), 0.0);
+            bobbed = *transform != bobbing || *velocity != smear;
+            *transform = bobbing;
+            *velocity = smear;
+        }
+
+        // Clamping the phase means a frame that takes longer than the blink finds the eyes
+        // open again instead of stuck partway
+        let phase = ((t - self.blink_start) / BLINK_DURATION).clamp(0.0, 1.0);
+        let closed = 1.0 - (2.0 * phase - 1.0).abs();
+        let eye_scale = 1.0 - (1.0 - CLOSED_EYE_SCALE) * closed;
+        if phase == 1.0 {
+            // After a long enough stall the next blink would already be over, so it starts now
+            self.blink_start = (self.blink_start + BLINK_DURATION + next_interval(&mut self.rng)).max(t);
+        }
+
+        let blinked = eye_scale != self.eye_scale;
+        if blinked {
+            self.eye_scale = eye_scale;
+            let squash = glm::scaling(&Vec3::new(1.0, eye_scale, 1.0));
+            for (name, open) in &self.eyes {
+                // Squashing keeps an invertible matrix invertible, since the scale isn't zero
+                if let (Some(group), Some(squashed)) = (scene.named_group(name), Transform::new(open.matrix * squash)) {
+                    *group.transform = squashed;
+                }
+            }
+        }
+
+        let mut tracked = false;
+        for playing in &mut self.tracks {
+            let Some(group) = scene.named_group(&playing.track.group) else {
+                continue;
+            };
+            // A track scaling to zero has no inverse, which leaves the group where it was
+            if let Some(moved) = playing.track.transform_at(t, &playing.rest.0) {
+                let smear = playing.track.position_at(t + shutter) - playing.track.position_at(t);
+                if *group.transform != moved || *group.velocity != smear {
+                    *group.transform = moved;
+                    *group.velocity = smear;
+                    tracked = true;
+                }
+            }
+            let diffuse = playing.track.diffuse_at(t);
+            if let (Some(color), true) = (diffuse, diffuse != playing.diffuse) {
+                group.objects.iter_mut().for_each(|object| object.set_diffuse(color));
+                playing.diffuse = diffuse;
+                tracked = true;
+            }
+            let strength = playing.track.emission_strength_at(t);
+            if let (Some(strength), true) = (strength, strength != playing.emission_strength) {
+                group.objects.iter_mut().for_each(|object| object.set_emission_strength(strength));
+                playing.emission_strength = Some(strength);
+                tracked = true;
+            }
+        }
+
+        let mut orbited = false;
+        for (orbit, rest) in &self.orbits {
+            if let Some(position) = scene.lights.get_mut(orbit.light).and_then(Light::position_mut) {
+                let moved = orbit.position_at(t, rest);
+                orbited |= *position != moved;
+                *position = moved;
+            }
+        }
+        bobbed || (blinked && !self.eyes.is_empty()) || tracked || orbited
+    }
+
+    // Moves and turns the scene's first group like `Scene::move_group`, taking the place it
+    // bobs around along with it. Returns false when the scene has no group to move.
+    pub fn move_group(&mut self, scene: &mut Scene, offset: Vec3, degrees: f32) -> bool {
+        if let Some((rest, _)) = &mut self.bob {
+            *rest = rest.moved(offset, degrees);
+        }
+        scene.move_group(offset, degrees)
+    }
+
+    // A copy of the scene with everything the animation moves or paints back the way the
+    // scene file has it, and everything else as it is, for saving edits made while it
+    // plays without saving the frame it was at
+    pub fn at_rest(&self, scene: &Scene) -> Scene {
+        let mut rest = scene.clone();
+        for playing in &self.tracks {
+            let Some(group) = rest.named_group(&playing.track.group) else {
+                continue;
+            };
+            (*group.transform, *group.velocity) = playing.rest.clone();
+            if let Some(objects) = &playing.rest_objects {
+                if objects.len() == group.objects.len() {
+                    group.objects.clone_from_slice(objects);
+                }
+            }
+        }
+        for (name, open) in &self.eyes {
+            if let Some(group) = rest.named_group(name) {
+                *group.transform = open.clone();
+            }
+        }
+        if let (Some(bob), Some(group)) = (&self.bob, rest.first_group()) {
+            (*group.transform, *group.velocity) = bob.clone();
+        }
+        for (orbit, position) in &self.orbits {
+            if let Some(light) = rest.lights.get_mut(orbit.light).and_then(Light::position_mut) {
+                *light = *position;
+            }
+        }
+        rest
+    }
+}
+
+fn next_interval(rng: &mut Rng) -> f32 {
+    let (shortest, longest) = BLINK_INTERVAL;
+    shortest + (longest - shortest) * rng.next_f32()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::DEFAULT_SCENE;
+
+    fn teddy() -> Scene {
+        Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap()
+    }
+
+    fn first_group_transform(scene: &mut Scene) -> Transform {
+        scene.first_group().unwrap().transform.clone()
+    }
+
+    #[test]
+    fn bob_places_the_group_the_same_however_the_frames_fall() {
+        let (mut stepped, mut jumped) = (teddy(), teddy());
+        let mut animation = Animation::new(&mut stepped, 0);
+        for frame in 1..=1000 {
+            animation.update(&mut stepped, frame as f32 * 0.0123, 0.0);
+        }
+        Animation::new(&mut jumped, 0).update(&mut jumped, 1000.0 * 0.0123, 0.0);
+        assert_eq!(first_group_transform(&mut stepped), first_group_transform(&mut jumped));
+    }
+
+    #[test]
+    fn bob_goes_back_to_rest_every_half_turn() {
+        let mut scene = teddy();
+        let rest = first_group_transform(&mut scene);
+        let mut animation = Animation::new(&mut scene, 0);
+        animation.update(&mut scene, PI / 2.0, 0.0);
+        let top = first_group_transform(&mut scene).point_to_world(&Vec3::zeros());
+        assert!((top - rest.point_to_world(&Vec3::zeros()) - Vec3::new(0.0, BOB_HEIGHT, 0.0)).magnitude() < 1e-6);
+        animation.update(&mut scene, 100.0 * PI, 0.0);
+        let middle = first_group_transform(&mut scene).point_to_world(&Vec3::zeros());
+        assert!((middle - rest.point_to_world(&Vec3::zeros())).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn moving_the_group_moves_where_it_bobs() {
+        let mut scene = teddy();
+        let mut animation = Animation::new(&mut scene, 0);
+        animation.update(&mut scene, 1.0, 0.0);
+        let before = first_group_transform(&mut scene).point_to_world(&Vec3::zeros());
+        assert!(animation.move_group(&mut scene, Vec3::new(0.5, 0.0, 0.0), 0.0));
+        animation.update(&mut scene, 1.0, 0.0);
+        let after = first_group_transform(&mut scene).point_to_world(&Vec3::zeros());
+        assert!((after - before - Vec3::new(0.5, 0.0, 0.0)).magnitude() < 1e-6);
+    }
+
+    // Plays the animation through a blink, stopping halfway into it with the eyes shut
+    fn play_into_a_blink(scene: &mut Scene, animation: &mut Animation) {
+        let mut t = 0.0;
+        while animation.eye_scale > 0.5 {
+            t += 0.01;
+            animation.update(scene, t, 0.1);
+        }
+    }
+
+    #[test]
+    fn at_rest_undoes_everything_the_animation_did() {
+        for (source, name) in [(DEFAULT_SCENE, "scenes/teddy.ron"), (include_str!("../scenes/teddy_glow.ron"), "scenes/teddy_glow.ron")] {
+            let loaded = Scene::parse(source, name).unwrap();
+            let mut scene = loaded.clone();
+            let mut animation = Animation::new(&mut scene, 0);
+            play_into_a_blink(&mut scene, &mut animation);
+            assert_ne!(scene, loaded);
+            assert_eq!(animation.at_rest(&scene), loaded, "{}", name);
+        }
+    }
+
+    #[test]
+    fn at_rest_keeps_edits() {
+        let loaded = teddy();
+        let mut scene = loaded.clone();
+        let mut animation = Animation::new(&mut scene, 0);
+        animation.move_group(&mut scene, Vec3::new(0.0, 0.0, -1.0), 0.0);
+        scene.fog = None;
+        scene.lights.clear();
+        play_into_a_blink(&mut scene, &mut animation);
+
+        let mut expected = loaded.clone();
+        expected.move_group(Vec3::new(0.0, 0.0, -1.0), 0.0);
+        expected.fog = None;
+        expected.lights.clear();
+        assert_eq!(animation.at_rest(&scene), expected);
+    }
+
+    #[test]
+    fn a_frame_longer_than_a_blink_finds_the_eyes_open() {
+        let mut scene = teddy();
+        let open: Vec<Transform> = EYES.iter().map(|name| scene.named_group(name).unwrap().transform.clone()).collect();
+        let mut animation = Animation::new(&mut scene, 0);
+        let start = animation.blink_start;
+        assert!(animation.update(&mut scene, start + BLINK_DURATION / 2.0, 0.0));
+        assert!((animation.eye_scale - CLOSED_EYE_SCALE).abs() < 1e-4, "{}", animation.eye_scale);
+
+        // The next frame comes long after the blink would have ended, but before the next one
+        animation.update(&mut scene, start + 1.0, 0.0);
+        assert_eq!(animation.eye_scale, 1.0);
+        for (name, open) in EYES.iter().zip(&open) {
+            assert_eq!(scene.named_group(name).unwrap().transform, open, "{}", name);
+        }
+        assert!(animation.blink_start >= start + BLINK_DURATION + BLINK_INTERVAL.0);
+    }
+
+    // Up and along to (2, 4, 0) over two seconds and back over the next two, while the
+    // glow and the color rise over the first two
+    fn wiggle(interpolation: Interpolation, repeat: Repeat) -> Track {
+        Track {
+            group: "nose".to_string(),
+            interpolation,
+            repeat,
+            position: Some(vec![(0.0, Vec3::zeros()), (2.0, Vec3::new(2.0, 4.0, 0.0)), (4.0, Vec3::zeros())]),
+            scale: Some(vec![(0.0, Vec3::repeat(1.0)), (4.0, Vec3::new(3.0, 1.0, 1.0))]),
+            diffuse: Some(vec![(0.0, Color::black()), (2.0, Color::new(200, 100, 50))]),
+            emission_strength: Some(vec![(0.0, 1.0), (2.0, 3.0)]),
+        }
+    }
+
+    #[test]
+    fn tracks_hit_their_keyframes_exactly() {
+        for interpolation in [Interpolation::Linear, Interpolation::Smooth] {
+            let track = wiggle(interpolation, Repeat::Clamp);
+            assert!(track.check().is_ok());
+            assert_eq!(track.position_at(0.0), Vec3::zeros());
+            assert_eq!(track.position_at(2.0), Vec3::new(2.0, 4.0, 0.0));
+            assert_eq!(track.position_at(4.0), Vec3::zeros());
+            assert_eq!(track.diffuse_at(2.0), Some(Color::new(200, 100, 50)));
+            assert_eq!(track.emission_strength_at(0.0), Some(1.0));
+            assert_eq!(track.emission_strength_at(2.0), Some(3.0));
+
+            let moved = track.transform_at(4.0, &Transform::identity()).unwrap();
+            assert_eq!(moved.point_to_world(&Vec3::new(1.0, 1.0, 1.0)), Vec3::new(3.0, 1.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn tracks_interpolate_between_keyframes() {
+        let linear = wiggle(Interpolation::Linear, Repeat::Clamp);
+        assert_eq!(linear.position_at(1.0), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(linear.position_at(0.5), Vec3::new(0.5, 1.0, 0.0));
+        assert_eq!(linear.position_at(3.0), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(linear.diffuse_at(1.0), Some(Color::new(100, 50, 25)));
+        assert_eq!(linear.emission_strength_at(1.0), Some(2.0));
+
+        // Smoothstep meets the straight line halfway but starts off slower
+        let smooth = wiggle(Interpolation::Smooth, Repeat::Clamp);
+        assert_eq!(smooth.position_at(1.0), Vec3::new(1.0, 2.0, 0.0));
+        let eased = 0.25 * 0.25 * (3.0 - 2.0 * 0.25);
+        assert!((smooth.position_at(0.5) - Vec3::new(2.0, 4.0, 0.0) * eased).magnitude() < 1e-6);
+        assert_eq!(smooth.emission_strength_at(0.5), Some(1.0 + 2.0 * eased));
+    }
+
+    #[test]
+    fn tracks_clamp_or_loop_past_their_ends() {
+        let clamped = wiggle(Interpolation::Linear, Repeat::Clamp);
+        assert_eq!(clamped.position_at(-1.0), Vec3::zeros());
+        assert_eq!(clamped.emission_strength_at(10.0), Some(3.0));
+        assert_eq!(clamped.position_at(5.0), Vec3::zeros());
+
+        // The track is four seconds long, the longest of its properties
+        let looped = wiggle(Interpolation::Linear, Repeat::Loop);
+        assert_eq!(looped.position_at(5.0), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(looped.position_at(-3.0), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(looped.emission_strength_at(9.0), Some(2.0));
+    }
+
+    fn orbit() -> LightOrbit {
+        LightOrbit { light: 0, center: Vec3::new(0.0, 1.0, -5.0), axis: Vec3::new(0.2, 1.0, 0.0), period: 3.0 }
+    }
+
+    #[test]
+    fn orbiting_light_is_back_after_each_period() {
+        let (orbit, rest) = (orbit(), Vec3::new(3.0, 2.0, -5.0));
+        assert!(orbit.check().is_ok());
+        assert!((orbit.position_at(0.0, &rest) - rest).magnitude() < 1e-5);
+        for t in [0.0, 0.4, 1.7, 2.9, 100.3] {
+            let now = orbit.position_at(t, &rest);
+            for periods in [1.0, 2.0, 50.0] {
+                let later = orbit.position_at(t + periods * orbit.period, &rest);
+                assert!((later - now).magnitude() < 1e-3, "t = {} + {} periods: {:?} and {:?}", t, periods, later, now);
+            }
+            // It keeps its distance from the center on the way round
+            assert!(((now - orbit.center).magnitude() - (rest - orbit.center).magnitude()).abs() < 1e-4);
+        }
+        // Halfway round it is on the other side of the axis
+        let half = orbit.position_at(1.5, &rest);
+        assert!((half - rest).magnitude() > 1.0);
+    }
+
+    #[test]
+    fn playing_an_orbit_moves_the_scene_light() {
+        let mut scene = teddy();
+        scene.lights = vec![Light::point(Vec3::new(3.0, 2.0, -5.0))];
+        scene.light_orbits.push(orbit());
+        let rest = scene.lights[0].position().unwrap();
+        let mut animation = Animation::new(&mut scene, 0);
+        assert!(animation.update(&mut scene, 1.0, 0.0));
+        let moved = scene.lights[0].position().unwrap();
+        assert_eq!(moved, orbit().position_at(1.0, &rest));
+        assert_ne!(moved, rest);
+        animation.update(&mut scene, 1.0 + orbit().period, 0.0);
+        assert!((scene.lights[0].position().unwrap() - moved).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn orbits_need_a_period_and_an_axis() {
+        assert!(LightOrbit { period: 0.0, ..orbit() }.check().is_err());
+        assert!(LightOrbit { period: f32::NAN, ..orbit() }.check().is_err());
+        assert!(LightOrbit { axis: Vec3::zeros(), ..orbit() }.check().is_err());
+    }
+
+    // Stops every group, within groups too, smearing over the shutter interval
+    fn stop_groups(objects: &mut [crate::scene::Object]) {
+        for object in objects {
+            if let crate::scene::Object::Group { velocity, objects, .. } = object {
+                *velocity = Vec3::zeros();
+                stop_groups(objects);
+            }
+        }
+    }
+
+    // The bear `t` seconds into its animation with the shutter open for `shutter` seconds,
+    // rendered with several samples per pixel so any motion blurs
+    fn animated_frame(t: f32, shutter: f32, still: bool) -> (Vec<u32>, Vec3) {
+        use crate::framebuffer::Framebuffer;
+        use crate::render::{render, RenderSettings};
+
+        let mut scene = teddy();
+        Animation::new(&mut scene, 0).update(&mut scene, t, shutter);
+        let velocity = *scene.first_group().unwrap().velocity;
+        if still {
+            stop_groups(&mut scene.objects);
+        }
+        let settings = RenderSettings { samples: 4, shutter, ..RenderSettings::default() };
+        let mut framebuffer = Framebuffer::new(40, 30);
+        render(&mut framebuffer, &scene, &scene.camera, &settings);
+        (framebuffer.buffer, velocity)
+    }
+
+    #[test]
+    fn a_closed_shutter_renders_without_motion_blur() {
+        // Halfway up the bob, where the bear moves fastest, with the ears and nose moving too
+        let (closed, velocity) = animated_frame(0.0, 0.0, false);
+        assert_eq!(velocity, Vec3::zeros());
+
+        // An open shutter smears the bear along its way up, and without the smear it's the
+        // same still frame again
+        let (blurred, velocity) = animated_frame(0.0, 0.2, false);
+        assert!(velocity.y > 0.0 && velocity.x == 0.0 && velocity.z == 0.0, "{:?}", velocity);
+        assert_ne!(blurred, closed);
+        let (unblurred, _) = animated_frame(0.0, 0.2, true);
+        assert_eq!(unblurred, closed);
+    }
+}
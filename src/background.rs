@@ -0,0 +1,92 @@
+use nalgebra_glm::{Vec3, dot};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::texture::Texture;
+
+// What rays that miss every object see
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Background {
+    Solid(Color),
+    // Blends from `bottom` when looking straight down to `top` when looking straight up
+    Gradient { top: Color, bottom: Color },
+    // A photo behind the scene. By default it is pinned to the screen like a backplate,
+    // filling the vertical field of view; world-locked images are wrapped around the
+    // scene as a latitude-longitude panorama instead.
+    Image {
+        texture: Texture,
+        #[serde(default)]
+        world_locked: bool,
+    },
+}
+
+impl Background {
+    pub fn sample(&self, direction: &Vec3, camera: &Camera, fov: f32) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { top, bottom } => {
+                let t = 0.5 * (direction.normalize().y + 1.0);
+                Color::lerp(*bottom, *top, t)
+            }
+            Background::Image { texture, world_locked: false } => {
+                let (u, v) = screen_uv(direction, camera, fov, texture.width as f32 / texture.height as f32);
+                texture.sample(u, v)
+            }
+            Background::Image { texture, world_locked: true } => {
+                let direction = direction.normalize();
+                let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+                let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+                texture.sample(u, v)
+            }
+        }
+    }
+}
+
+// Projects a direction onto the camera's image plane. The image spans the vertical field
+// of view and keeps its own aspect ratio horizontally, so the central ray lands on (0.5, 0.5).
+fn screen_uv(direction: &Vec3, camera: &Camera, fov: f32, image_aspect: f32) -> (f32, f32) {
+    let forward = (camera.center - camera.eye).normalize();
+    let right = forward.cross(&camera.up).normalize();
+    let up = right.cross(&forward).normalize();
+
+    // Directions behind the camera only come from secondary rays, push them to the edge
+    let depth = dot(direction, &forward).max(1e-6);
+    let perspective_scale = (fov * 0.5).tan();
+    let screen_x = dot(direction, &right) / depth / perspective_scale;
+    let screen_y = dot(direction, &up) / depth / perspective_scale;
+
+    (0.5 + 0.5 * screen_x / image_aspect, 0.5 - 0.5 * screen_y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+
+    // A 3x3 image that is blue all round a red middle texel
+    fn backplate() -> Background {
+        let mut image = Framebuffer::new(3, 3);
+        image.clear(0x0000ff);
+        image.buffer[4] = 0xff0000;
+        let path = std::env::temp_dir().join("raytracing_animal_backplate.png").to_string_lossy().into_owned();
+        image.save_png(&path).unwrap();
+        Background::Image { texture: Texture::load(&path).unwrap(), world_locked: false }
+    }
+
+    #[test]
+    fn central_ray_samples_the_middle_of_the_backplate() {
+        let background = backplate();
+        let fov = PI / 3.0;
+        let mut camera = Camera::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -5.0), Vec3::y());
+        let forward = camera.center - camera.eye;
+        assert_eq!(background.sample(&forward, &camera, fov), Color::new(255, 0, 0));
+
+        // The backplate turns with the camera
+        camera.orbit(1.0, 0.3);
+        let forward = camera.center - camera.eye;
+        assert_eq!(background.sample(&forward, &camera, fov), Color::new(255, 0, 0));
+        assert_eq!(background.sample(&-camera.up, &camera, fov), Color::new(0, 0, 255));
+    }
+}
@@ -0,0 +1,44 @@
+use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::ray_intersect::{Intersect, Material, Ray, RayIntersect};
+use crate::sdf::{smooth_min, sphere_trace, Sdf};
+
+// Spheres, given as (center, radius), that melt into each other where they meet instead
+// of leaving a crease: the surface is where the smooth minimum of their distance fields is
+// zero. `smoothness` is how far from where two spheres meet the blending reaches, 0 for
+// the plain union.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Blob {
+    pub spheres: Vec<(Vec3, f32)>,
+    pub smoothness: f32,
+    pub material: Material,
+}
+
+impl Sdf for Blob {
+    // The blend pulls the surface out by at most a quarter of the smoothness and never
+    // pushes it in, so this never overestimates how far a ray can safely step
+    fn distance(&self, point: &Vec3) -> f32 {
+        self.spheres
+            .iter()
+            .map(|(center, radius)| (point - center).magnitude() - radius)
+            .reduce(|a, b| smooth_min(a, b, self.smoothness))
+            .unwrap_or(f32::INFINITY)
+    }
+
+    // The box around every sphere, grown by the most the blend can add
+    fn bounding_box(&self) -> Aabb {
+        let margin = Vec3::repeat(self.smoothness.max(0.0) * 0.25);
+        self.spheres.iter().fold(Aabb::empty(), |bounds, (center, radius)| {
+            bounds.union(&Aabb::around(center, &(Vec3::repeat(*radius) + margin)))
+        })
+    }
+}
+
+impl RayIntersect for Blob {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        sphere_trace(self, ray, t_min, t_max, &self.material)
+    }
+}
@@ -1,10 +1,27 @@
 use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
+use crate::ray_intersect::Ray;
+use crate::scene::Scene;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Camera {
     pub eye: Vec3,    // Camera position in world space
     pub center: Vec3, // Point the camera is looking at
     pub up: Vec3,     // Up vector
+    // Width of the lens, 0 for a pinhole camera that keeps everything sharp. Wider lenses
+    // blur whatever is nearer or farther than the focus distance, measured from the eye
+    // along the view direction; without one of its own the camera focuses on `center`.
+    #[serde(default, skip_serializing_if = "is_pinhole")]
+    pub aperture: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+}
+
+fn is_pinhole(aperture: &f32) -> bool {
+    *aperture == 0.0
 }
 
 impl Camera {
@@ -13,7 +30,63 @@ impl Camera {
             eye,
             center,
             up,
+            aperture: 0.0,
+            focus_distance: None,
+        }
+    }
+
+    // Linear blend between two viewpoints, t = 0 gives self and t = 1 gives other
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        let up = self.up + (other.up - self.up) * t;
+        Camera {
+            eye: self.eye + (other.eye - self.eye) * t,
+            center: self.center + (other.center - self.center) * t,
+            // Opposite up vectors would cancel out halfway, keep the start one in that case
+            up: if up.magnitude() > 1e-6 { up.normalize() } else { self.up },
+            aperture: self.aperture + (other.aperture - self.aperture) * t,
+            focus_distance: match (self.focus_distance, other.focus_distance) {
+                (None, None) => None,
+                _ => Some(self.focus() + (other.focus() - self.focus()) * t),
+            },
+        }
+    }
+
+    pub fn focus(&self) -> f32 {
+        self.focus_distance.unwrap_or_else(|| (self.center - self.eye).magnitude())
+    }
+
+    // The ray through a point of the lens, given as a point of the unit disk, that crosses
+    // the pinhole ray from the eye where it reaches the focus distance. A pinhole camera
+    // keeps the pinhole ray.
+    pub fn lens_ray(&self, ray: &Ray, lens: (f32, f32)) -> Ray {
+        if self.aperture <= 0.0 {
+            return *ray;
         }
+        let forward = (self.center - self.eye).normalize();
+        let right = forward.cross(&self.up).normalize();
+        let up = right.cross(&forward);
+
+        let focused = ray.at(self.focus() / ray.direction.dot(&forward));
+        let radius = self.aperture * 0.5;
+        let origin = self.eye + (right * lens.0 + up * lens.1) * radius;
+        Ray::new(origin, focused - origin).with_time(ray.time)
+    }
+
+    // The origin and unit direction of the pinhole ray through a point of a `width` x
+    // `height` image, in pixels from its top left corner, for a vertical field of view of
+    // `fov` radians. The middle of the image looks straight at `center`.
+    pub fn ray_for_pixel(&self, x: f32, y: f32, width: usize, height: usize, fov: f32) -> (Vec3, Vec3) {
+        let aspect_ratio = width as f32 / height as f32;
+        let perspective_scale = (fov * 0.5).tan();
+
+        let screen_x = (2.0 * x) / width as f32 - 1.0;
+        let screen_y = -(2.0 * y) / height as f32 + 1.0;
+
+        let screen_x = screen_x * aspect_ratio * perspective_scale;
+        let screen_y = screen_y * perspective_scale;
+
+        let direction = Vec3::new(screen_x, screen_y, -1.0).normalize();
+        (self.eye, self.basis_change(&direction))
     }
 
     pub fn basis_change(&self, vector: &Vec3) -> Vec3 {
@@ -29,6 +102,72 @@ impl Camera {
         rotated.normalize()
     }
 
+    // A world point relative to the eye in the camera's own axes: x to the right, y up and
+    // the view direction along -z, the space `basis_change` maps out of
+    pub fn view_space(&self, point: &Vec3) -> Vec3 {
+        let forward = (self.center - self.eye).normalize();
+        let right = forward.cross(&self.up).normalize();
+        let up = right.cross(&forward).normalize();
+
+        let offset = point - self.eye;
+        Vec3::new(offset.dot(&right), offset.dot(&up), -offset.dot(&forward))
+    }
+
+    // Tilts the horizon by rotating the up vector around the viewing direction. Positive
+    // angles lean the camera to the left, so the scene appears to turn clockwise.
+    pub fn roll(&mut self, angle: f32) {
+        let forward = (self.center - self.eye).normalize();
+        // Start from the part of up that's perpendicular to the view, as basis_change does
+        let up = (self.up - forward * self.up.dot(&forward)).normalize();
+        let right = forward.cross(&up);
+
+        self.up = (up * angle.cos() - right * angle.sin()).normalize();
+    }
+
+    // Turns the camera to look at `target` from where it is, which also makes it the point
+    // the camera orbits around. Up is tilted along to stay perpendicular to the new view;
+    // looking straight along the old up, the old view direction turned back takes its place,
+    // as if the camera had pitched round to it.
+    pub fn set_center(&mut self, target: Vec3) {
+        let forward = target - self.eye;
+        // From the target itself there's no direction to look in
+        if forward.magnitude() < 1e-6 {
+            return;
+        }
+        let forward = forward.normalize();
+        let old_forward = (self.center - self.eye).normalize();
+
+        // Through the sides of the view, which is as long as the sine of the angle between
+        // the new view and up. Close to along up that's mostly rounding error, and a
+        // direction made from it could point anywhere.
+        let side = forward.cross(&self.up.normalize());
+        let side = if side.magnitude() > 1e-3 { side } else { forward.cross(&-old_forward) };
+        if side.magnitude() > 1e-6 {
+            self.up = side.cross(&forward).normalize();
+        }
+        self.center = target;
+    }
+
+    // Moves the camera so the whole scene fits in view, looking at the middle of its bounding
+    // box from the same direction as before. `fov` is the vertical field of view in radians.
+    pub fn frame_scene(&mut self, scene: &Scene, fov: f32, aspect_ratio: f32) {
+        let bounds = scene.bounding_box();
+        if bounds.is_empty() {
+            return;
+        }
+        let center = bounds.center();
+        let radius = ((bounds.max - bounds.min).magnitude() * 0.5).max(1e-3);
+
+        // The sphere around the box fits when it's within the narrower of the two fields of view
+        let half_fov = (fov * 0.5).min(((fov * 0.5).tan() * aspect_ratio).atan());
+        let distance = radius / half_fov.sin();
+
+        let forward = self.center - self.eye;
+        let forward = if forward.magnitude() > 1e-6 { forward.normalize() } else { Vec3::new(0.0, 0.0, -1.0) };
+        self.center = center;
+        self.eye = center - forward * distance;
+    }
+
     pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
         // Calculate the vector from the center to the eye (radius vector) and measure the distance
         let radius_vector = self.eye - self.center;
@@ -49,8 +188,11 @@ impl Camera {
         // Apply delta rotations
         // Keep yaw in range [0, 2π] for consistency
         let new_yaw = (current_yaw + delta_yaw) % (2.0 * PI);
-        // Clamp pitch to slightly less than [-π/2, π/2] to prevent gimbal lock
-        let new_pitch = (current_pitch + delta_pitch).clamp(-PI / 2.0 + 0.1, PI / 2.0 - 0.1);
+        // Clamp pitch to slightly less than [-π/2, π/2] to prevent gimbal lock. A camera
+        // turned to look at something steeper than that may already be past it, and is only
+        // kept from going further, so the first step doesn't jump it back.
+        let limit = PI / 2.0 - 0.1;
+        let new_pitch = (current_pitch + delta_pitch).clamp(current_pitch.min(-limit), current_pitch.max(limit));
 
         // Calculate new eye position
         // We use spherical coordinates to cartesian conversion:
@@ -66,4 +208,170 @@ impl Camera {
 
         self.eye = new_eye;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn front() -> Camera {
+        Camera::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -5.0), Vec3::y())
+    }
+
+    fn side() -> Camera {
+        Camera {
+            aperture: 0.2,
+            focus_distance: Some(3.0),
+            ..Camera::new(Vec3::new(3.5, 1.0, -1.5), Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 1.0, 0.2).normalize())
+        }
+    }
+
+    #[test]
+    fn lerp_starts_at_the_first_camera_and_ends_at_the_second() {
+        assert_eq!(front().lerp(&side(), 0.0).eye, front().eye);
+        assert_eq!(front().lerp(&side(), 0.0).center, front().center);
+        assert!((front().lerp(&side(), 0.0).up - front().up).magnitude() < 1e-6);
+
+        let end = front().lerp(&side(), 1.0);
+        assert!((end.eye - side().eye).magnitude() < 1e-6);
+        assert!((end.center - side().center).magnitude() < 1e-6);
+        assert!((end.up - side().up).magnitude() < 1e-6);
+        assert!((end.aperture - side().aperture).abs() < 1e-6);
+        assert!((end.focus() - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn lerp_halfway_is_between_the_two() {
+        let middle = front().lerp(&side(), 0.5);
+        assert!((middle.eye - Vec3::new(1.75, 0.5, -0.75)).magnitude() < 1e-6);
+        assert!((middle.up.magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn after_a_quarter_roll_up_goes_sideways() {
+        let mut camera = front();
+        assert!((camera.basis_change(&Vec3::y()) - Vec3::y()).magnitude() < 1e-6);
+
+        camera.roll(PI / 2.0);
+        // Leaning left turns the camera's up towards the world's left
+        assert!((camera.basis_change(&Vec3::y()) - Vec3::new(-1.0, 0.0, 0.0)).magnitude() < 1e-6);
+        assert!((camera.basis_change(&Vec3::new(0.0, 0.0, -1.0)) - Vec3::new(0.0, 0.0, -1.0)).magnitude() < 1e-6);
+
+        // Rolling back undoes it
+        camera.roll(-PI / 2.0);
+        assert!((camera.up - Vec3::y()).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn ray_through_the_middle_looks_forward() {
+        for camera in [front(), side()] {
+            let forward = (camera.center - camera.eye).normalize();
+            let (origin, direction) = camera.ray_for_pixel(100.0, 50.0, 200, 100, PI / 3.0);
+            assert_eq!(origin, camera.eye);
+            assert!((direction - forward).magnitude() < 1e-6, "{:?} is not {:?}", direction, forward);
+        }
+    }
+
+    #[test]
+    fn rays_through_the_edges_spread_by_the_field_of_view() {
+        let camera = side();
+        let forward = (camera.center - camera.eye).normalize();
+        let right = forward.cross(&camera.up).normalize();
+        let up = right.cross(&forward);
+        let fov = PI / 3.0;
+        let half = (fov / 2.0).tan();
+
+        // Halfway along the top edge is half the field of view above the middle
+        let (_, top) = camera.ray_for_pixel(100.0, 0.0, 200, 100, fov);
+        assert!((top.dot(&forward).acos() - fov / 2.0).abs() < 1e-5);
+        assert!(top.dot(&up) > 0.0 && top.dot(&right).abs() < 1e-6);
+
+        // The corners reach as far again sideways as the image is wider than it's tall
+        for (x, y, sideways, upwards) in [(0.0, 0.0, -2.0, 1.0), (200.0, 0.0, 2.0, 1.0), (0.0, 100.0, -2.0, -1.0), (200.0, 100.0, 2.0, -1.0)] {
+            let (_, corner) = camera.ray_for_pixel(x, y, 200, 100, fov);
+            assert!((corner.magnitude() - 1.0).abs() < 1e-6);
+            let ahead = corner.dot(&forward);
+            assert!((corner.dot(&right) / ahead - sideways * half).abs() < 1e-5, "at {}, {}", x, y);
+            assert!((corner.dot(&up) / ahead - upwards * half).abs() < 1e-5, "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn framed_box_fits_in_the_image() {
+        use crate::background::Background;
+        use crate::color::Color;
+        use crate::cube::Cube;
+        use crate::ray_intersect::Material;
+        use crate::scene::Object;
+
+        let mut scene = Scene::new(Background::Solid(Color::black()), front());
+        let cube = Cube { center: Vec3::new(7.0, -2.0, 3.0), size: Vec3::new(4.0, 1.0, 2.0), material: Material::default() };
+        let bounds = cube.bounding_box();
+        scene.add_object(Object::Cube(cube));
+
+        let (fov, width, height) = (PI / 3.0, 80.0, 45.0);
+        let mut camera = front();
+        camera.frame_scene(&scene, fov, width / height);
+        assert!((camera.center - bounds.center()).magnitude() < 1e-5);
+
+        let scale = (fov * 0.5).tan();
+        for corner in bounds.corners() {
+            let view = camera.view_space(&corner);
+            assert!(view.z < 0.0, "{:?} is behind the camera", corner);
+            // Where the corner lands on the image, in pixels
+            let x = (view.x / -view.z / (scale * width / height) + 1.0) * 0.5 * width;
+            let y = (1.0 - view.y / -view.z / scale) * 0.5 * height;
+            assert!((0.0..=width).contains(&x) && (0.0..=height).contains(&y), "{:?} lands at {}, {}", corner, x, y);
+        }
+    }
+
+    // Points to turn the front camera to: off to the side, behind it, and straight along
+    // its up vector either way
+    const TARGETS: [Vec3; 5] = [
+        Vec3::new(3.0, 1.0, -2.0),
+        Vec3::new(-1.0, -2.0, 4.0),
+        Vec3::new(0.0, 5.0, 0.0),
+        Vec3::new(0.0, -2.0, 0.0),
+        Vec3::new(1e-4, 3.0, 0.0),
+    ];
+
+    #[test]
+    fn retargeting_turns_the_camera_where_it_stands() {
+        for target in TARGETS {
+            let mut camera = front();
+            camera.set_center(target);
+            assert_eq!(camera.eye, front().eye);
+            assert_eq!(camera.center, target);
+            let forward = (target - camera.eye).normalize();
+            assert!((camera.up.magnitude() - 1.0).abs() < 1e-5, "{:?}: {:?}", target, camera.up);
+            assert!(camera.up.dot(&forward).abs() < 1e-5, "{:?}: {:?}", target, camera.up);
+        }
+        // Looking straight up, the way it looked before is now behind the top of the image
+        let mut camera = front();
+        camera.set_center(Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(camera.up, Vec3::z());
+        // And the same for nearly straight up, rather than whichever way rounding tips it
+        let mut camera = front();
+        camera.set_center(Vec3::new(1e-4, 3.0, 0.0));
+        assert!((camera.up - Vec3::z()).magnitude() < 1e-4, "{:?}", camera.up);
+        // The eye itself is no direction to look in
+        let mut camera = front();
+        camera.set_center(camera.eye);
+        assert_eq!(camera, front());
+    }
+
+    #[test]
+    fn orbiting_after_a_retarget_revolves_around_the_new_point() {
+        for target in TARGETS {
+            let mut camera = front();
+            camera.set_center(target);
+            camera.orbit(0.0, 0.0);
+            assert!((camera.eye - front().eye).magnitude() < 1e-5, "{:?}: {:?}", target, camera.eye);
+
+            let radius = (camera.eye - target).magnitude();
+            camera.orbit(0.3, 0.0);
+            assert!(((camera.eye - target).magnitude() - radius).abs() < 1e-4, "{:?}", target);
+            assert_eq!(camera.center, target);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use nalgebra_glm::Vec3;
+
+use crate::color::Color;
+use crate::ray_intersect::Intersect;
+
+// What the image shows: the rendered scene, or a debug view of the first surface seen
+// through each pixel. Debug views are displayed as they are, without exposure or tone
+// mapping, and show the background black.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Channel {
+    #[default]
+    Beauty,
+    Normals,  // The normal the surface reports, its x, y and z from -1..1 as red, green and blue
+    Depth,    // Distance along the ray, white at the near end of the depth range, black at the far one
+    Uv,       // u as red and v as green, black where the surface has no texture coordinates
+    ObjectId, // A color of its own for each of the scene's objects
+}
+
+impl Channel {
+    pub fn from_name(name: &str) -> Option<Channel> {
+        match name.to_ascii_lowercase().as_str() {
+            "beauty" => Some(Channel::Beauty),
+            "normals" => Some(Channel::Normals),
+            "depth" => Some(Channel::Depth),
+            "uv" => Some(Channel::Uv),
+            "object-id" => Some(Channel::ObjectId),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Beauty => "beauty",
+            Channel::Normals => "normals",
+            Channel::Depth => "depth",
+            Channel::Uv => "uv",
+            Channel::ObjectId => "object-id",
+        }
+    }
+
+    // Cycles through the channels, for switching between them at runtime
+    pub fn next(self) -> Channel {
+        match self {
+            Channel::Beauty => Channel::Normals,
+            Channel::Normals => Channel::Depth,
+            Channel::Depth => Channel::Uv,
+            Channel::Uv => Channel::ObjectId,
+            Channel::ObjectId => Channel::Beauty,
+        }
+    }
+
+    // The debug view's color for a hit on the scene's `object`th object, in 0..1. Only
+    // meaningful for the debug views, the beauty channel is shaded instead.
+    pub fn value(self, intersect: &Intersect, object: Option<usize>, depth_range: (f32, f32)) -> Vec3 {
+        if !intersect.is_intersecting {
+            return Vec3::zeros();
+        }
+        match self {
+            Channel::Beauty => Vec3::zeros(),
+            Channel::Normals => (intersect.normal + Vec3::repeat(1.0)) * 0.5,
+            Channel::Depth => {
+                let (near, far) = depth_range;
+                Vec3::repeat(1.0 - ((intersect.distance - near) / (far - near)).clamp(0.0, 1.0))
+            }
+            Channel::Uv => intersect.uv.map_or(Vec3::zeros(), |(u, v)| Vec3::new(u, v, 0.0)),
+            Channel::ObjectId => {
+                // Golden ratio steps around the hue circle keep neighboring indices far apart
+                let hue = (object.unwrap_or(0) as f32 * 0.618_034).fract() * 360.0;
+                Color::from_hsv(hue, 0.7, 0.9).to_vec3()
+            }
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use std::fmt;
+
+use raytracing_animal::channel::Channel;
+use raytracing_animal::denoise::{DenoiseFilter, Denoiser};
+use raytracing_animal::render::Tile;
+use raytracing_animal::tonemap::ToneMap;
+
+pub const USAGE: &str = "\
+Usage: Raytracing_Animal [OPTIONS] [SCENE]
+
+Arguments:
+  [SCENE]             RON scene file to render (default: the built-in teddy bear)
+
+Options:
+  --width <PIXELS>    Framebuffer width (default: 800)
+  --height <PIXELS>   Framebuffer height (default: 600)
+  --fov <DEGREES>     Vertical field of view, between 0 and 180 (default: 60)
+  --samples <N>       Samples per pixel (default: 1)
+  --adaptive <T>      Sample pixel corners and subdivide only where colors differ by more than T (0-1)
+  --shadow-samples <N> Shadow rays per light with a radius, for soft shadows (default: 16)
+  --reflection-depth <N> Reflections seen in reflections before mirrors go dark, 0 for
+                      none (default: 4)
+  --light-samples <N> Light each hit with N lights picked at random, favoring the brightest,
+                      instead of all of them; faster with many lights but noisier
+  --path-trace <N>    Path trace with N samples per pixel, lit only by emissive materials
+                      and the background
+  --seed <N>          Seed for the random sampling; the same seed gives the same image (default: 0)
+  --clamp <L>         Cap each path-traced sample at luminance L to suppress fireflies
+  --exposure <X>      Radiance multiplier applied before tone mapping (default: 1)
+  --tonemap <NAME>    Tone mapping operator: none, reinhard or aces (default: none)
+  --denoise [FILTER]  Start with the denoiser on; it only filters the displayed image.
+                      FILTER is atrous, edge-aware over several passes, or bilateral, a
+                      slower single pass guided by depth alone (default: atrous)
+  --denoise-radius <PIXELS> How far the denoiser reaches (default: 8)
+  --denoise-sigma <S> Color differences the denoiser smooths over, 0-1 (default: 0.2)
+  --outline <T>       With --headless, draw black outlines where the distance to the first
+                      surface jumps by more than a fraction T of it (e.g. 0.5)
+  --vignette <S>      Darken the image towards its corners, by a fraction S of their
+                      brightness at the very corner (0-1, e.g. 0.4)
+  --time <SECONDS>    With --headless or --turntable, render the animation as it is that long after it starts
+  --shutter <SECONDS> How long the shutter stays open, blurring what the animation moves
+                      over that time (default: 0)
+  --no-animate        Start with the animation paused, for stills; P plays and pauses it
+  --progressive       Show each new view at a quarter of the resolution first, then at half,
+                      then sharpen it at full resolution while the view stays still
+  --clay              Shade every object in the same matte gray
+  --channel <NAME>    Show beauty (the render), normals, depth, uv or object-id (default: beauty)
+  --depth-range <NEAR> <FAR> Distances the depth channel shows as white and black (default: 0 10)
+  --region <X0> <Y0> <X1> <Y1> Only render the pixels from (X0, Y0) up to but not including
+                      (X1, Y1), counted from the top left; the rest of the image stays black
+  --bounds            Draw the bounding box of every object and a cross at every light
+                      over the image
+  --output <FILE>     Write the rendered frame to a PNG file
+  --thumbnail <N>     With --headless, also write a preview N times smaller next to the
+                      output, with _thumb added to its name
+  --save-scene <FILE> Where Ctrl+S saves the scene and current camera (default: the loaded
+                      scene file, or scene.ron); with --headless the scene is saved right away
+  --turntable <N>     Render N frames orbiting the scene into the --output directory
+                      (default: turntable/) as numbered PNGs, without opening a window
+  --frame             Move the camera back until the whole scene is in view
+  --cornell-box       Render the built-in Cornell box reference scene instead of a scene file
+  --headless          Render a single frame to the output file without opening a window
+  --terminal          Draw the scene in the terminal with colored characters instead of a
+                      window, orbiting it until interrupted; sized to fit the terminal
+  --help              Print this message";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub width: usize,
+    pub height: usize,
+    pub fov: f32, // In degrees, converted to radians by the renderer settings
+    pub samples: u32,
+    pub adaptive_threshold: Option<f32>,
+    pub path_samples: Option<u32>, // Path tracing instead of direct lighting when set
+    pub sample_clamp: Option<f32>,
+    pub shadow_samples: u32,
+    pub reflection_depth: u32,
+    pub light_samples: Option<u32>,
+    pub seed: u64,
+    pub time: Option<f32>,
+    pub shutter: f32,
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    pub denoise: bool,
+    pub denoiser: Denoiser,
+    pub outline: Option<f32>, // Edge threshold
+    pub vignette: Option<f32>, // Strength
+    pub bounds: bool,
+    pub clay: bool,
+    pub channel: Channel,
+    pub depth_range: (f32, f32),
+    pub region: Option<Tile>,
+    pub no_animate: bool,
+    pub progressive: bool,
+    pub output: Option<String>,
+    pub thumbnail: Option<u32>, // Downsampling factor
+    pub headless: bool,
+    pub terminal: bool,
+    pub turntable: Option<u32>,
+    pub scene: Option<String>,
+    pub cornell_box: bool,
+    pub frame: bool,
+    pub save_scene: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 800,
+            height: 600,
+            fov: 60.0,
+            samples: 1,
+            adaptive_threshold: None,
+            path_samples: None,
+            sample_clamp: None,
+            shadow_samples: 16,
+            reflection_depth: 4,
+            light_samples: None,
+            seed: 0,
+            time: None,
+            shutter: 0.0,
+            exposure: 1.0,
+            tone_map: ToneMap::None,
+            denoise: false,
+            denoiser: Denoiser::default(),
+            outline: None,
+            vignette: None,
+            bounds: false,
+            clay: false,
+            channel: Channel::Beauty,
+            depth_range: (0.0, 10.0),
+            region: None,
+            no_animate: false,
+            progressive: false,
+            output: None,
+            thumbnail: None,
+            headless: false,
+            terminal: false,
+            turntable: None,
+            scene: None,
+            cornell_box: false,
+            frame: false,
+            save_scene: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    Help,
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String, reason: &'static str },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::UnknownArgument(arg) => write!(f, "unknown argument '{}'", arg),
+            ArgsError::MissingValue(flag) => write!(f, "missing value for '{}'", flag),
+            ArgsError::InvalidValue { flag, value, reason } => {
+                write!(f, "invalid value '{}' for '{}': {}", value, flag, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, ArgsError> {
+    let mut options = Options::default();
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Err(ArgsError::Help),
+            "--headless" => options.headless = true,
+            "--terminal" => options.terminal = true,
+            "--cornell-box" => options.cornell_box = true,
+            "--frame" => options.frame = true,
+            "--denoise" => {
+                options.denoise = true;
+                // The filter is optional, so anything else after the flag is left alone
+                if let Some(filter) = args.peek().and_then(|name| DenoiseFilter::from_name(name)) {
+                    options.denoiser.filter = filter;
+                    args.next();
+                }
+            }
+            "--denoise-radius" => options.denoiser.radius = parse_value(&arg, args.next())?,
+            "--denoise-sigma" => options.denoiser.sigma = parse_value(&arg, args.next())?,
+            "--width" => options.width = parse_value(&arg, args.next())?,
+            "--height" => options.height = parse_value(&arg, args.next())?,
+            "--fov" => options.fov = parse_value(&arg, args.next())?,
+            "--samples" => options.samples = parse_value(&arg, args.next())?,
+            "--adaptive" => options.adaptive_threshold = Some(parse_value(&arg, args.next())?),
+            "--clamp" => options.sample_clamp = Some(parse_value(&arg, args.next())?),
+            "--shadow-samples" => options.shadow_samples = parse_value(&arg, args.next())?,
+            "--reflection-depth" => options.reflection_depth = parse_value(&arg, args.next())?,
+            "--light-samples" => options.light_samples = Some(parse_value(&arg, args.next())?),
+            "--seed" => options.seed = parse_value(&arg, args.next())?,
+            "--time" => options.time = Some(parse_value(&arg, args.next())?),
+            "--shutter" => options.shutter = parse_value(&arg, args.next())?,
+            "--path-trace" => options.path_samples = Some(parse_value(&arg, args.next())?),
+            "--exposure" => options.exposure = parse_value(&arg, args.next())?,
+            "--tonemap" => {
+                let name = args.next().ok_or_else(|| ArgsError::MissingValue(arg.clone()))?;
+                options.tone_map = ToneMap::from_name(&name).ok_or_else(|| ArgsError::InvalidValue {
+                    flag: arg.clone(),
+                    value: name,
+                    reason: "expected none, reinhard or aces",
+                })?;
+            }
+            "--bounds" => options.bounds = true,
+            "--clay" => options.clay = true,
+            "--channel" => {
+                let name = args.next().ok_or_else(|| ArgsError::MissingValue(arg.clone()))?;
+                options.channel = Channel::from_name(&name).ok_or_else(|| ArgsError::InvalidValue {
+                    flag: arg.clone(),
+                    value: name,
+                    reason: "expected beauty, normals, depth, uv or object-id",
+                })?;
+            }
+            "--depth-range" => {
+                options.depth_range = (parse_value(&arg, args.next())?, parse_value(&arg, args.next())?);
+            }
+            "--region" => {
+                options.region = Some(Tile {
+                    x0: parse_value(&arg, args.next())?,
+                    y0: parse_value(&arg, args.next())?,
+                    x1: parse_value(&arg, args.next())?,
+                    y1: parse_value(&arg, args.next())?,
+                });
+            }
+            "--progressive" => options.progressive = true,
+            "--no-animate" => options.no_animate = true,
+            "--outline" => options.outline = Some(parse_value(&arg, args.next())?),
+            "--vignette" => options.vignette = Some(parse_value(&arg, args.next())?),
+            "--thumbnail" => options.thumbnail = Some(parse_value(&arg, args.next())?),
+            "--turntable" => options.turntable = Some(parse_value(&arg, args.next())?),
+            "--output" => {
+                options.output = Some(args.next().ok_or_else(|| ArgsError::MissingValue(arg.clone()))?);
+            }
+            "--save-scene" => {
+                options.save_scene = Some(args.next().ok_or_else(|| ArgsError::MissingValue(arg.clone()))?);
+            }
+            _ if arg.starts_with('-') || options.scene.is_some() => {
+                return Err(ArgsError::UnknownArgument(arg));
+            }
+            _ => options.scene = Some(arg),
+        }
+    }
+
+    validate(&options)?;
+    Ok(options)
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, ArgsError> {
+    let value = value.ok_or_else(|| ArgsError::MissingValue(flag.to_string()))?;
+    value.parse().map_err(|_| ArgsError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+        reason: "not a number",
+    })
+}
+
+fn validate(options: &Options) -> Result<(), ArgsError> {
+    // Catch the values that would otherwise panic or produce NaNs deep inside the renderer
+    if options.width == 0 {
+        return Err(invalid("--width", options.width, "must be greater than zero"));
+    }
+    if options.height == 0 {
+        return Err(invalid("--height", options.height, "must be greater than zero"));
+    }
+    if !(options.fov > 0.0 && options.fov < 180.0) {
+        return Err(invalid("--fov", options.fov, "must be between 0 and 180 degrees"));
+    }
+    if options.samples == 0 {
+        return Err(invalid("--samples", options.samples, "must be at least 1"));
+    }
+    if let Some(clamp) = options.sample_clamp {
+        if !(clamp > 0.0 && clamp.is_finite()) {
+            return Err(invalid("--clamp", clamp, "must be a positive number"));
+        }
+    }
+    if options.shadow_samples == 0 {
+        return Err(invalid("--shadow-samples", 0, "must be at least 1"));
+    }
+    if options.light_samples == Some(0) {
+        return Err(invalid("--light-samples", 0, "must be at least 1"));
+    }
+    if options.path_samples == Some(0) {
+        return Err(invalid("--path-trace", 0, "must be at least 1 sample"));
+    }
+    if !(options.exposure > 0.0 && options.exposure.is_finite()) {
+        return Err(invalid("--exposure", options.exposure, "must be a positive number"));
+    }
+    if let Some(time) = options.time {
+        if !time.is_finite() {
+            return Err(invalid("--time", time, "must be a number of seconds"));
+        }
+    }
+    if !(options.shutter >= 0.0 && options.shutter.is_finite()) {
+        return Err(invalid("--shutter", options.shutter, "must be zero or a positive number of seconds"));
+    }
+    let (near, far) = options.depth_range;
+    if !(near >= 0.0 && far > near && far.is_finite()) {
+        return Err(invalid("--depth-range", format!("{} {}", near, far), "must be a near distance of at least 0 and a farther one"));
+    }
+    if let Some(Tile { x0, y0, x1, y1 }) = options.region {
+        if !(x0 < x1 && y0 < y1 && x1 <= options.width && y1 <= options.height) {
+            return Err(invalid(
+                "--region",
+                format!("{} {} {} {}", x0, y0, x1, y1),
+                "must be a rectangle inside the image, from its top left corner to its bottom right one",
+            ));
+        }
+    }
+    if options.denoiser.radius == 0 {
+        return Err(invalid("--denoise-radius", 0, "must be at least 1 pixel"));
+    }
+    if !(options.denoiser.sigma > 0.0 && options.denoiser.sigma.is_finite()) {
+        return Err(invalid("--denoise-sigma", options.denoiser.sigma, "must be a positive number"));
+    }
+    if let Some(threshold) = options.outline {
+        if !(threshold > 0.0 && threshold.is_finite()) {
+            return Err(invalid("--outline", threshold, "must be a positive number"));
+        }
+    }
+    if let Some(strength) = options.vignette {
+        if !(0.0..=1.0).contains(&strength) {
+            return Err(invalid("--vignette", strength, "must be between 0 and 1"));
+        }
+    }
+    if options.thumbnail == Some(0) {
+        return Err(invalid("--thumbnail", 0, "must be a factor of at least 1"));
+    }
+    if options.turntable == Some(0) {
+        return Err(invalid("--turntable", 0, "must be at least 1 frame"));
+    }
+    if let (true, Some(scene)) = (options.cornell_box, &options.scene) {
+        return Err(invalid("--cornell-box", scene, "can't be combined with a scene file"));
+    }
+    if let Some(threshold) = options.adaptive_threshold {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(invalid("--adaptive", threshold, "must be between 0 and 1"));
+        }
+    }
+    Ok(())
+}
+
+fn invalid<T: fmt::Display>(flag: &str, value: T, reason: &'static str) -> ArgsError {
+    ArgsError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, ArgsError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // The flag an invalid value was rejected for
+    fn rejected(args: &[&str]) -> String {
+        match parse(args) {
+            Err(ArgsError::InvalidValue { flag, .. }) => flag,
+            other => panic!("{:?} gave {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn no_arguments_are_the_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!((options.width, options.height, options.fov), (800, 600, 60.0));
+        assert!(options.scene.is_none() && !options.headless);
+    }
+
+    #[test]
+    fn values_and_the_scene_are_read() {
+        let options = parse(&["--width", "320", "--fov", "45.5", "--region", "1", "2", "30", "40", "scene.ron", "--headless"]).unwrap();
+        assert_eq!((options.width, options.fov), (320, 45.5));
+        assert_eq!(options.region, Some(Tile { x0: 1, y0: 2, x1: 30, y1: 40 }));
+        assert_eq!(options.scene.as_deref(), Some("scene.ron"));
+        assert!(options.headless);
+    }
+
+    #[test]
+    fn help_is_asked_for_either_way() {
+        assert!(matches!(parse(&["--help"]), Err(ArgsError::Help)));
+        assert!(matches!(parse(&["--width", "10", "-h"]), Err(ArgsError::Help)));
+    }
+
+    #[test]
+    fn unknown_flags_and_a_second_scene_are_refused() {
+        assert!(matches!(parse(&["--widht", "10"]), Err(ArgsError::UnknownArgument(arg)) if arg == "--widht"));
+        assert!(matches!(parse(&["a.ron", "b.ron"]), Err(ArgsError::UnknownArgument(arg)) if arg == "b.ron"));
+    }
+
+    #[test]
+    fn flags_at_the_end_without_their_values_are_refused() {
+        for flag in ["--width", "--fov", "--tonemap", "--channel", "--output", "--region"] {
+            assert!(matches!(parse(&[flag]), Err(ArgsError::MissingValue(missing)) if missing == flag), "{}", flag);
+        }
+        // Every one of the region's corners is needed
+        assert!(matches!(parse(&["--region", "0", "0", "10"]), Err(ArgsError::MissingValue(_))));
+    }
+
+    #[test]
+    fn values_that_would_break_the_renderer_are_refused() {
+        assert_eq!(rejected(&["--width", "0"]), "--width");
+        assert_eq!(rejected(&["--height", "0"]), "--height");
+        assert_eq!(rejected(&["--width", "-3"]), "--width");
+        assert_eq!(rejected(&["--fov", "-10"]), "--fov");
+        assert_eq!(rejected(&["--fov", "180"]), "--fov");
+        assert_eq!(rejected(&["--fov", "NaN"]), "--fov");
+        assert_eq!(rejected(&["--samples", "0"]), "--samples");
+        assert_eq!(rejected(&["--exposure", "inf"]), "--exposure");
+        assert_eq!(rejected(&["--shutter", "-1"]), "--shutter");
+        assert_eq!(rejected(&["--depth-range", "5", "2"]), "--depth-range");
+        assert_eq!(rejected(&["--width", "100", "--region", "0", "0", "101", "10"]), "--region");
+        assert_eq!(rejected(&["--vignette", "1.5"]), "--vignette");
+        assert_eq!(rejected(&["--tonemap", "filmic"]), "--tonemap");
+        assert_eq!(rejected(&["--cornell-box", "scene.ron"]), "--cornell-box");
+        assert_eq!(rejected(&["--samples", "many"]), "--samples");
+    }
+
+    #[test]
+    fn errors_name_the_flag_and_value() {
+        let message = parse(&["--fov", "-10"]).unwrap_err().to_string();
+        assert!(message.contains("--fov") && message.contains("-10"), "{}", message);
+        assert!(parse(&["--output"]).unwrap_err().to_string().contains("--output"));
+    }
+
+    #[test]
+    fn denoise_takes_an_optional_filter() {
+        let options = parse(&["--denoise"]).unwrap();
+        assert!(options.denoise);
+        assert_eq!(options.denoiser.filter, DenoiseFilter::ATrous);
+        assert_eq!(parse(&["--denoise", "bilateral"]).unwrap().denoiser.filter, DenoiseFilter::Bilateral);
+        // Anything else after it is read as usual
+        let options = parse(&["--denoise", "scene.ron"]).unwrap();
+        assert_eq!((options.denoiser.filter, options.scene.as_deref()), (DenoiseFilter::ATrous, Some("scene.ron")));
+        assert!(parse(&["--denoise", "--headless"]).unwrap().headless);
+    }
+}
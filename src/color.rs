@@ -1,29 +1,234 @@
+use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::sync::OnceLock;
 
-#[derive(Debug, Clone, Copy)]
+// Scene files write colors as an (r, g, b) tuple of bytes or as a hex string like "#8B4513".
+// The channels are kept as floats, with 0..1 being the displayable range, so arithmetic on
+// colors doesn't round to whole bytes at every step; only `to_hex` quantizes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ColorDef", into = "(u8, u8, u8)")]
 pub struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
+    r: f32,
+    g: f32,
+    b: f32,
 }
 
 impl Color {
-    // Constructor to initialize the color using r, g, b values
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
-        Color { r, g, b }
+    // Constructor to initialize the color using r, g, b byte values
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color {
+            r: r as f32 / 255.0,
+            g: g as f32 / 255.0,
+            b: b as f32 / 255.0,
+        }
+    }
+
+    pub fn black() -> Self {
+        Color::new(0, 0, 0)
+    }
+
+    pub fn white() -> Self {
+        Color::new(255, 255, 255)
+    }
+
+    // Function to blend between two colors, t = 0 gives a and t = 1 gives b
+    pub fn lerp(a: Color, b: Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        Color::from_vec3(a.to_vec3() + (b.to_vec3() - a.to_vec3()) * t)
+    }
+
+    // Function to clamp the channels to the displayable range
+    #[allow(dead_code)]
+    pub fn clamp(self) -> Self {
+        Color {
+            r: self.r.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
+        }
+    }
+
+    // Function to create a color from 0xRRGGBB, the layout `to_hex` and the framebuffer use.
+    // The top byte is ignored.
+    pub fn from_u32(rgb: u32) -> Self {
+        let r = ((rgb >> 16) & 0xFF) as u8;
+        let g = ((rgb >> 8) & 0xFF) as u8;
+        let b = (rgb & 0xFF) as u8;
+        Color::new(r, g, b)
+    }
+
+    // Function to parse "#RRGGBB" or the shorthand "#RGB", with or without the hash
+    pub fn from_hex_str(s: &str) -> Result<Self, ColorParseError> {
+        let digits = s.strip_prefix('#').unwrap_or(s);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ColorParseError::InvalidDigit(s.to_string()));
+        }
+
+        let value = u32::from_str_radix(digits, 16).unwrap_or(0);
+        match digits.len() {
+            6 => Ok(Color::from_u32(value)),
+            // Each shorthand digit is repeated, so "#F80" is "#FF8800"
+            3 => {
+                let expand = |d: u32| (d * 17) as u8;
+                Ok(Color::new(expand(value >> 8), expand((value >> 4) & 0xF), expand(value & 0xF)))
+            }
+            _ => Err(ColorParseError::InvalidLength(s.to_string())),
+        }
+    }
+
+    // Function to create a color from hue in degrees, saturation and value in 0..1
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+
+        let chroma = v * s;
+        let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = v - chroma;
+        Color::from_vec3(Vec3::new(r + m, g + m, b + m))
+    }
+
+    // Function to return the color as a hex value, clamping and rounding each channel to a byte
+    pub fn to_hex(self) -> u32 {
+        let (r, g, b): (u8, u8, u8) = self.into();
+        ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
+    }
+
+    // Function to return the raw channels, without any decoding
+    pub fn to_vec3(self) -> Vec3 {
+        Vec3::new(self.r, self.g, self.b)
+    }
+
+    // Function to create a color from raw channels
+    pub fn from_vec3(v: Vec3) -> Self {
+        Color { r: v.x, g: v.y, b: v.z }
     }
 
-    // Function to create a color from a hex value
-    pub fn from_hex(hex: u32) -> Self {
-        let r = ((hex >> 16) & 0xFF) as u8;
-        let g = ((hex >> 8) & 0xFF) as u8;
-        let b = (hex & 0xFF) as u8;
-        Color { r, g, b }
+    // Function to decode the sRGB channels into linear values
+    pub fn to_linear(self) -> Vec3 {
+        Vec3::new(decode_channel(self.r), decode_channel(self.g), decode_channel(self.b))
     }
 
-    // Function to return the color as a hex value
-    pub fn to_hex(&self) -> u32 {
-        ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
+    // Function to encode linear channel values as sRGB, clamping to 0..1 first
+    pub fn from_linear(linear: Vec3) -> Self {
+        let encode = |c: f32| linear_to_srgb(c.clamp(0.0, 1.0));
+        Color::from_vec3(Vec3::new(encode(linear.x), encode(linear.y), encode(linear.z)))
+    }
+}
+
+// Colors written in scene files are whole bytes, which are decoded through the table;
+// anything computed in between goes through the curve itself
+fn decode_channel(c: f32) -> f32 {
+    let scaled = c * 255.0;
+    let byte = scaled.round();
+    // Allow for the rounding error of dividing by 255 in `new`
+    if (0.0..=255.0).contains(&byte) && (scaled - byte).abs() < 1e-3 {
+        srgb_decode_table()[byte as usize]
+    } else {
+        srgb_to_linear(c.max(0.0))
+    }
+}
+
+// The exact piecewise sRGB transfer functions, on channel values in 0..1
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Byte channels only have 256 possible values, so material colors are decoded through a
+// table built on first use instead of evaluating the curve for every hit
+fn srgb_decode_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, value) in table.iter_mut().enumerate() {
+            *value = srgb_to_linear(i as f32 / 255.0);
+        }
+        table
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorParseError {
+    InvalidLength(String), // Not 3 or 6 digits
+    InvalidDigit(String),  // Something other than 0-9, a-f or A-F
+}
+
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorParseError::InvalidLength(input) => {
+                write!(f, "invalid color '{}': expected #RGB or #RRGGBB", input)
+            }
+            ColorParseError::InvalidDigit(input) => {
+                write!(f, "invalid color '{}': contains a non-hex digit", input)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ColorParseError {}
+
+// Either way of writing a color in a scene file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDef {
+    Bytes(u8, u8, u8),
+    Hex(String),
+}
+
+impl TryFrom<ColorDef> for Color {
+    type Error = ColorParseError;
+
+    fn try_from(def: ColorDef) -> Result<Self, Self::Error> {
+        match def {
+            ColorDef::Bytes(r, g, b) => Ok(Color::new(r, g, b)),
+            ColorDef::Hex(s) => Color::from_hex_str(&s),
+        }
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Color::new(r, g, b)
+    }
+}
+
+impl From<Color> for (u8, u8, u8) {
+    fn from(color: Color) -> Self {
+        let encode = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (encode(color.r), encode(color.g), encode(color.b))
+    }
+}
+
+impl From<Vec3> for Color {
+    fn from(v: Vec3) -> Self {
+        Color::from_vec3(v)
+    }
+}
+
+impl From<Color> for Vec3 {
+    fn from(color: Color) -> Self {
+        color.to_vec3()
     }
 }
 
@@ -34,11 +239,7 @@ impl Add for Color {
     type Output = Color;
 
     fn add(self, other: Color) -> Color {
-        Color {
-            r: self.r.saturating_add(other.r),
-            g: self.g.saturating_add(other.g),
-            b: self.b.saturating_add(other.b),
-        }
+        Color::from_vec3(self.to_vec3() + other.to_vec3())
     }
 }
 
@@ -49,17 +250,121 @@ impl Mul<f32> for Color {
     type Output = Color;
 
     fn mul(self, scalar: f32) -> Color {
-        Color {
-            r: (self.r as f32 * scalar).clamp(0.0, 255.0) as u8,
-            g: (self.g as f32 * scalar).clamp(0.0, 255.0) as u8,
-            b: (self.b as f32 * scalar).clamp(0.0, 255.0) as u8,
-        }
+        Color::from_vec3(self.to_vec3() * scalar)
+    }
+}
+
+// Implement component-wise multiplication for Color, e.g. light color times albedo
+impl Mul<Color> for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
+        Color::from_vec3(self.to_vec3().component_mul(&other.to_vec3()))
     }
 }
 
 // Implement display formatting for Color
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Color(r: {}, g: {}, b: {})", self.r, self.g, self.b)
+        write!(f, "Color(r: {:.3}, g: {:.3}, b: {:.3})", self.r, self.g, self.b)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adding_saturates_at_white() {
+        let sum = Color::new(200, 200, 200) + Color::new(200, 100, 0);
+        assert_eq!(sum.to_hex(), 0xffffc8);
+        assert_eq!(sum.clamp(), Color::new(255, 255, 200));
+        assert_eq!((Color::white() * 3.0).to_hex(), 0xffffff);
+        assert_eq!((Color::white() * -1.0).to_hex(), 0x000000);
+    }
+
+    #[test]
+    fn lerp_gives_its_endpoints() {
+        let a = Color::new(10, 20, 30);
+        let b = Color::new(250, 128, 0);
+        assert_eq!(Color::lerp(a, b, 0.0), a);
+        assert_eq!(Color::lerp(a, b, 1.0).to_hex(), b.to_hex());
+        // Outside 0..1 stays at the ends
+        assert_eq!(Color::lerp(a, b, -1.0), a);
+        assert_eq!(Color::lerp(a, b, 2.0).to_hex(), b.to_hex());
+        assert_eq!(Color::lerp(Color::black(), Color::white(), 0.5).to_hex(), 0x808080);
+    }
+
+    #[test]
+    fn multiplying_colors_goes_channel_by_channel() {
+        let light = Color::new(255, 128, 0);
+        let albedo = Color::new(128, 255, 255);
+        assert_eq!((light * albedo).to_hex(), 0x808000);
+    }
+
+    #[test]
+    fn hex_strings_round_trip_through_to_hex() {
+        for hex in [0x000000, 0xffffff, 0x8b4513, 0x0a0b0c, 0xff0080] {
+            assert_eq!(Color::from_hex_str(&format!("#{:06X}", hex)).unwrap().to_hex(), hex);
+            assert_eq!(Color::from_hex_str(&format!("{:06x}", hex)).unwrap().to_hex(), hex);
+        }
+        assert_eq!(Color::from_hex_str("#F80"), Ok(Color::new(0xff, 0x88, 0x00)));
+        assert_eq!(Color::from_hex_str("#abc"), Color::from_hex_str("#AABBCC"));
+    }
+
+    #[test]
+    fn from_u32_takes_the_channels_in_order() {
+        assert_eq!(Color::from_u32(0x8b4513), Color::new(0x8b, 0x45, 0x13));
+        assert_eq!(Color::from_u32(0xff8b4513), Color::new(0x8b, 0x45, 0x13));
+        for hex in [0x000000, 0xffffff, 0x8b4513, 0x0a0b0c] {
+            assert_eq!(Color::from_u32(hex).to_hex(), hex);
+        }
+    }
+
+    #[test]
+    fn scene_files_take_either_way_of_writing_a_color() {
+        let fur: Color = ron::from_str("\"#8B4513\"").unwrap();
+        assert_eq!(fur, Color::from_u32(0x8b4513));
+        assert_eq!(ron::from_str::<Color>("(139, 69, 19)").unwrap(), fur);
+        assert_eq!(ron::from_str::<Color>("\"#fff\"").unwrap(), Color::white());
+        // Saved colors are written as bytes, which read back the same
+        let saved = ron::to_string(&fur).unwrap();
+        assert_eq!(saved, "(139,69,19)");
+        assert_eq!(ron::from_str::<Color>(&saved).unwrap(), fur);
+        // A bad hex string fails the file naming it
+        let error = ron::from_str::<Color>("\"#8B45\"").unwrap_err().to_string();
+        assert!(error.contains("#8B45"), "{}", error);
+    }
+
+    #[test]
+    fn hex_errors_name_the_input() {
+        assert_eq!(Color::from_hex_str("#12345"), Err(ColorParseError::InvalidLength("#12345".to_string())));
+        assert_eq!(Color::from_hex_str("#12G456"), Err(ColorParseError::InvalidDigit("#12G456".to_string())));
+        assert_eq!(Color::from_hex_str("#F8"), Err(ColorParseError::InvalidLength("#F8".to_string())));
+        assert_eq!(Color::from_hex_str(""), Err(ColorParseError::InvalidLength("".to_string())));
+        assert_eq!(Color::from_hex_str("#-80"), Err(ColorParseError::InvalidDigit("#-80".to_string())));
+        assert!(Color::from_hex_str("#12G456").unwrap_err().to_string().contains("#12G456"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hsv_primaries_and_grays() {
+        assert_eq!(Color::from_hsv(0.0, 1.0, 1.0).to_hex(), 0xff0000);
+        assert_eq!(Color::from_hsv(120.0, 1.0, 1.0).to_hex(), 0x00ff00);
+        assert_eq!(Color::from_hsv(240.0, 1.0, 1.0).to_hex(), 0x0000ff);
+        assert_eq!(Color::from_hsv(60.0, 1.0, 1.0).to_hex(), 0xffff00);
+        // Hue wraps around, and without saturation every hue is the same gray
+        assert_eq!(Color::from_hsv(360.0 + 240.0, 1.0, 1.0).to_hex(), 0x0000ff);
+        assert_eq!(Color::from_hsv(77.0, 0.0, 0.5).to_hex(), 0x808080);
+    }
+
+    #[test]
+    fn repeated_halving_keeps_its_precision() {
+        let mut color = Color::white();
+        for _ in 0..8 {
+            color = color * 0.5;
+        }
+        // Bytes would have rounded down to 127, 63, ..., 1, 0 on the way
+        assert_eq!(color.to_vec3(), Vec3::repeat(1.0 / 256.0));
+        assert_eq!((color * 256.0).to_hex(), 0xffffff);
+        assert_eq!((Color::new(3, 3, 3) * 0.5 * 2.0).to_hex(), 0x030303);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::ray_intersect::{Intersect, Ray, RayIntersect, Span};
+use crate::scene::Object;
+
+// How a CSG node combines the insides of its two children
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    Union,        // Inside either
+    Intersection, // Inside both
+    Difference,   // Inside `left` but not `right`, which carves `right` out of `left`
+}
+
+impl Operation {
+    fn inside(self, left: bool, right: bool) -> bool {
+        match self {
+            Operation::Union => left || right,
+            Operation::Intersection => left && right,
+            Operation::Difference => left && !right,
+        }
+    }
+}
+
+// Two solids combined into one by where the ray is inside each of them, e.g. a sphere
+// with a flat bottom is `Csg(operation: Difference, left: Sphere(...), right: Cube(...))`.
+// Both children need an inside: spheres, cubes, other CSG nodes, or those transformed.
+// Surfaces keep the material of the child they come from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Csg {
+    pub operation: Operation,
+    pub left: Box<Object>,
+    pub right: Box<Object>,
+}
+
+impl Csg {
+    pub fn bounding_box(&self) -> Aabb {
+        let (left, right) = (self.left.bounding_box(), self.right.bounding_box());
+        match self.operation {
+            Operation::Union => left.union(&right),
+            Operation::Intersection => left.intersection(&right),
+            Operation::Difference => left,
+        }
+    }
+}
+
+impl RayIntersect for Csg {
+    // The first boundary of the combined spans in range, entering or leaving
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let Some(spans) = self.ray_spans(ray) else {
+            return Intersect::empty();
+        };
+        spans
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|hit| hit.distance > t_min && hit.distance < t_max)
+            .unwrap_or_else(Intersect::empty)
+    }
+
+    // Walks the boundaries of both children's spans in order along the ray, keeping track
+    // of which child the ray is inside, and starts or ends a span wherever that changes
+    // whether it's inside the combination
+    fn ray_spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        let left = self.left.ray_spans(ray)?;
+        let right = self.right.ray_spans(ray)?;
+
+        // (hit, whether it's on the left child, whether the ray enters that child there)
+        let mut boundaries: Vec<(Intersect, bool, bool)> = Vec::new();
+        for (spans, is_left) in [(left, true), (right, false)] {
+            for span in spans {
+                boundaries.push((span.enter, is_left, true));
+                boundaries.push((span.exit, is_left, false));
+            }
+        }
+        boundaries.sort_by(|a, b| a.0.distance.total_cmp(&b.0.distance));
+
+        let mut spans = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<Intersect> = None;
+        for (mut hit, is_left, entering) in boundaries {
+            let was_inside = self.operation.inside(in_left, in_right);
+            if is_left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+            let inside = self.operation.inside(in_left, in_right);
+            if inside == was_inside {
+                continue;
+            }
+
+            // Leaving a child's inside to enter the combination, like the carved face of a
+            // difference, turns its normal around to point out of the combination
+            if entering != inside {
+                hit.normal = -hit.normal;
+            }
+            match enter.take() {
+                None => enter = Some(hit),
+                Some(enter) => spans.push(Span { enter, exit: hit }),
+            }
+        }
+        Some(spans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_intersect::Material;
+    use crate::sphere::Sphere;
+    use nalgebra_glm::Vec3;
+
+    fn sphere(center: Vec3) -> Box<Object> {
+        Box::new(Object::Sphere(Sphere { center, radius: 1.0, material: Material::default(), velocity: Vec3::zeros() }))
+    }
+
+    // A unit ball with the unit ball one higher up taken out of it, leaving a bowl whose
+    // hollow goes down to the origin
+    fn bowl() -> Csg {
+        Csg { operation: Operation::Difference, left: sphere(Vec3::zeros()), right: sphere(Vec3::y()) }
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn bowl_is_entered_through_its_carved_face_from_above() {
+        // Straight down the carved part, to the bottom of the hollow and through the floor
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), -Vec3::y());
+        let spans = bowl().ray_spans(&ray).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].enter.distance - 5.0).abs() < 1e-5 && (spans[0].exit.distance - 6.0).abs() < 1e-5);
+
+        let hit = bowl().ray_intersect(&ray, 0.0, f32::INFINITY);
+        assert!(hit.is_intersecting);
+        assert_near(hit.point, Vec3::zeros());
+        // The carved face's normal points up out of the bowl, not down into the carved ball
+        assert_near(hit.normal, Vec3::y());
+    }
+
+    #[test]
+    fn ray_across_the_bowl_crosses_both_walls() {
+        // Level with the hollow, which takes up |x| < √(1 - 0.75²) of the ball's √(1 - 0.25²)
+        let ray = Ray::new(Vec3::new(-5.0, 0.25, 0.0), Vec3::x());
+        let (outer, inner) = ((1.0f32 - 0.25 * 0.25).sqrt(), (1.0f32 - 0.75 * 0.75).sqrt());
+        let spans = bowl().ray_spans(&ray).unwrap();
+        assert_eq!(spans.len(), 2);
+
+        // In through the outside of the near wall, out into the hollow through the carved
+        // face, then in again through the carved face of the far wall
+        let walls = [(&spans[0].enter, -outer), (&spans[0].exit, -inner), (&spans[1].enter, inner), (&spans[1].exit, outer)];
+        for (hit, x) in walls {
+            assert_near(hit.point, Vec3::new(x, 0.25, 0.0));
+        }
+        assert_near(spans[0].enter.normal, Vec3::new(-outer, 0.25, 0.0));
+        assert_near(spans[0].exit.normal, Vec3::new(inner, 0.75, 0.0));
+        assert_near(spans[1].enter.normal, Vec3::new(-inner, 0.75, 0.0));
+        assert_near(spans[1].exit.normal, Vec3::new(outer, 0.25, 0.0));
+
+        // Starting in the hollow, the first surface is the carved face, facing the ray
+        let hit = bowl().ray_intersect(&ray, 5.0, f32::INFINITY);
+        assert_near(hit.point, Vec3::new(inner, 0.25, 0.0));
+        assert!(hit.normal.dot(&ray.direction) < 0.0);
+    }
+
+    #[test]
+    fn rays_through_only_the_carved_part_miss() {
+        let ray = Ray::new(Vec3::new(-5.0, 0.9, 0.0), Vec3::x());
+        assert!(bowl().ray_spans(&ray).unwrap().is_empty());
+        assert!(!bowl().ray_intersect(&ray, 0.0, f32::INFINITY).is_intersecting);
+    }
+}
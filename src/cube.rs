@@ -0,0 +1,95 @@
+use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
+use crate::ray_intersect::{RayIntersect, Material, Intersect, Ray, Span};
+use crate::aabb::Aabb;
+
+// An axis-aligned box; wrap it in a transform to rotate it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cube {
+    pub center: Vec3,
+    pub size: Vec3, // Edge length along each axis
+    pub material: Material,
+}
+
+impl Cube {
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::around(&self.center, &(self.size * 0.5))
+    }
+}
+
+impl Cube {
+    // Where the line through the ray enters and leaves the box, with the axis of the face
+    // at each, or None if it misses. Either may be behind the ray's origin.
+    fn slabs(&self, ray: &Ray) -> Option<((f32, usize), (f32, usize))> {
+        let min = self.center - self.size * 0.5;
+        let max = self.center + self.size * 0.5;
+
+        // Slab method: intersect the ray with the three pairs of parallel planes and keep
+        // the overlap of the three [near, far] intervals
+        let mut t_near = f32::NEG_INFINITY;
+        let mut t_far = f32::INFINITY;
+        let mut near_axis = 0;
+        let mut far_axis = 0;
+
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let mut t0 = (min[axis] - ray.origin[axis]) * inverse;
+            let mut t1 = (max[axis] - ray.origin[axis]) * inverse;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            if t0 > t_near {
+                t_near = t0;
+                near_axis = axis;
+            }
+            if t1 < t_far {
+                t_far = t1;
+                far_axis = axis;
+            }
+        }
+
+        // A ray parallel to a pair of faces and outside them enters at infinity
+        if t_near > t_far || t_near.is_infinite() {
+            None
+        } else {
+            Some(((t_near, near_axis), (t_far, far_axis)))
+        }
+    }
+
+    // The hit `t` along the ray on a face perpendicular to `axis`
+    fn hit_at(&self, ray: &Ray, t: f32, axis: usize) -> Intersect {
+        let point = ray.at(t);
+
+        let mut normal = Vec3::zeros();
+        normal[axis] = if point[axis] > self.center[axis] { 1.0 } else { -1.0 };
+
+        Intersect::new(point, normal, t, self.material.clone())
+    }
+}
+
+impl RayIntersect for Cube {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let Some(((t_near, near_axis), (t_far, far_axis))) = self.slabs(ray) else {
+            return Intersect::empty();
+        };
+        if t_far <= t_min {
+            return Intersect::empty();
+        }
+
+        // From inside the box the exit face is the visible one
+        let (t, axis) = if t_near > t_min { (t_near, near_axis) } else { (t_far, far_axis) };
+        if t >= t_max {
+            return Intersect::empty();
+        }
+        self.hit_at(ray, t, axis)
+    }
+
+    fn ray_spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        let spans = self.slabs(ray).map(|((t_near, near_axis), (t_far, far_axis))| Span {
+            enter: self.hit_at(ray, t_near, near_axis),
+            exit: self.hit_at(ray, t_far, far_axis),
+        });
+        Some(spans.into_iter().collect())
+    }
+}
@@ -0,0 +1,310 @@
+use nalgebra_glm::{dot, Vec3};
+
+use crate::framebuffer::Framebuffer;
+
+// How quickly the weight falls off across a change of normal and of relative depth. Edges
+// between objects show up in both, so either is enough to stop the blur there.
+const NORMAL_POWER: i32 = 64;
+const DEPTH_SIGMA: f32 = 0.02;
+
+// B3 spline taps of the à-trous kernel, applied along x and y
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// An edge-aware filter for noisy path traced images
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    pub filter: DenoiseFilter,
+    pub radius: u32, // Reach of the filter in pixels, rounded up to the next pass
+    pub sigma: f32,  // Scale of the color differences, after compressing to 0..1, that get smoothed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DenoiseFilter {
+    // An à-trous wavelet filter. Each pass blurs with the same 5 x 5 kernel with its taps
+    // spread twice as far apart as the last, and weights every tap by how closely its
+    // color, normal and depth match the center pixel's.
+    ATrous,
+    // `Framebuffer::denoise_bilateral` over the whole radius in one pass, guided by depth
+    // only
+    Bilateral,
+}
+
+impl DenoiseFilter {
+    pub fn from_name(name: &str) -> Option<DenoiseFilter> {
+        match name.to_ascii_lowercase().as_str() {
+            "atrous" => Some(DenoiseFilter::ATrous),
+            "bilateral" => Some(DenoiseFilter::Bilateral),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser { filter: DenoiseFilter::ATrous, radius: 8, sigma: 0.2 }
+    }
+}
+
+// The first hit of the ray through each pixel's center. `depth` is infinite and `normal`
+// zero where the ray misses everything.
+pub struct GuideBuffers {
+    pub normals: Vec<Vec3>,
+    pub depths: Vec<f32>,
+}
+
+impl Denoiser {
+    // A filtered copy of a `width` x `height` radiance buffer; the input is left alone so
+    // passes can keep accumulating into it
+    pub fn apply(&self, radiance: &[Vec3], width: usize, height: usize, guides: &GuideBuffers) -> Vec<Vec3> {
+        if self.filter == DenoiseFilter::Bilateral {
+            // Reaching twice its spatial sigma
+            return bilateral(radiance, width, height, Some(&guides.depths), self.radius as f32 / 2.0, self.sigma);
+        }
+        let mut current = radiance.to_vec();
+        let mut next = vec![Vec3::zeros(); current.len()];
+
+        let mut step = 1;
+        let mut sigma = self.sigma.max(1e-4);
+        for _ in 0..self.passes() {
+            for y in 0..height {
+                for x in 0..width {
+                    next[y * width + x] = filter_pixel(&current, width, height, x, y, step, sigma, guides);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            step *= 2;
+            // Later passes see an image with less noise left, so they tolerate less
+            sigma *= 0.5;
+        }
+        current
+    }
+
+    // Passes with steps 1, 2, 4, ... reach 2 * (2^n - 1) pixels
+    fn passes(&self) -> u32 {
+        let mut passes = 1;
+        while 2 * ((1 << passes) - 1) < self.radius && passes < 10 {
+            passes += 1;
+        }
+        passes
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn filter_pixel(
+    radiance: &[Vec3],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    step: usize,
+    sigma: f32,
+    guides: &GuideBuffers,
+) -> Vec3 {
+    let center = y * width + x;
+    let color = compress(&radiance[center]);
+    let normal = guides.normals[center];
+    let depth = guides.depths[center];
+
+    let mut sum = Vec3::zeros();
+    let mut total = 0.0;
+    for (j, ky) in KERNEL.iter().enumerate() {
+        let sy = y as isize + (j as isize - 2) * step as isize;
+        if sy < 0 || sy >= height as isize {
+            continue;
+        }
+        for (i, kx) in KERNEL.iter().enumerate() {
+            let sx = x as isize + (i as isize - 2) * step as isize;
+            if sx < 0 || sx >= width as isize {
+                continue;
+            }
+            let tap = sy as usize * width + sx as usize;
+
+            let difference = (compress(&radiance[tap]) - color).magnitude_squared();
+            let color_weight = (-difference / (sigma * sigma)).exp();
+            let guide_weight = guide_weight(&normal, depth, &guides.normals[tap], guides.depths[tap]);
+
+            let weight = kx * ky * color_weight * guide_weight;
+            sum += radiance[tap] * weight;
+            total += weight;
+        }
+    }
+    // The center tap always has a positive weight
+    sum / total
+}
+
+// How likely two pixels are to show the same surface, from 0 to 1
+fn guide_weight(normal: &Vec3, depth: f32, other_normal: &Vec3, other_depth: f32) -> f32 {
+    let normal_weight = if depth.is_finite() && other_depth.is_finite() {
+        dot(normal, other_normal).max(0.0).powi(NORMAL_POWER)
+    } else {
+        1.0
+    };
+    normal_weight * depth_weight(depth, other_depth)
+}
+
+// The part of `guide_weight` that depth alone gives
+fn depth_weight(depth: f32, other_depth: f32) -> f32 {
+    match (depth.is_finite(), other_depth.is_finite()) {
+        (false, false) => 1.0,
+        (true, true) => {
+            let relative = (depth - other_depth).abs() / depth.max(1e-3);
+            (-relative / DEPTH_SIGMA).exp()
+        }
+        // Never mix an object with the background behind it
+        _ => 0.0,
+    }
+}
+
+impl Framebuffer {
+    // A single pass bilateral filter of the radiance, a simpler and slower alternative to
+    // the à-trous filter: each pixel becomes the average of the pixels within
+    // 2 * `spatial_sigma` of it, weighted by a Gaussian of their distance in pixels and of
+    // how far their color is from its own, after compressing to 0..1 like `Denoiser` does.
+    // Colors further apart than `color_sigma` barely mix, which keeps edges sharp. With a
+    // depth buffer, pixels on surfaces at other distances don't mix either, whatever their
+    // colors. Only the radiance changes, retone mapping it shows the result.
+    pub fn denoise_bilateral(&mut self, spatial_sigma: f32, color_sigma: f32) {
+        let depths = (self.depth.len() == self.width * self.height).then_some(&self.depth[..]);
+        self.radiance = bilateral(&self.radiance, self.width, self.height, depths, spatial_sigma, color_sigma);
+    }
+}
+
+fn bilateral(radiance: &[Vec3], width: usize, height: usize, depths: Option<&[f32]>, spatial_sigma: f32, color_sigma: f32) -> Vec<Vec3> {
+    let spatial_sigma = spatial_sigma.max(1e-4);
+    let color_sigma = color_sigma.max(1e-4);
+    let reach = (2.0 * spatial_sigma).ceil() as isize;
+
+    let mut filtered = Vec::with_capacity(radiance.len());
+    for y in 0..height {
+        for x in 0..width {
+            let center = y * width + x;
+            let color = compress(&radiance[center]);
+            let mut sum = Vec3::zeros();
+            let mut total = 0.0;
+            for dy in -reach..=reach {
+                let sy = y as isize + dy;
+                if sy < 0 || sy >= height as isize {
+                    continue;
+                }
+                for dx in -reach..=reach {
+                    let sx = x as isize + dx;
+                    if sx < 0 || sx >= width as isize {
+                        continue;
+                    }
+                    let tap = sy as usize * width + sx as usize;
+
+                    let distance = (dx * dx + dy * dy) as f32;
+                    let difference = (compress(&radiance[tap]) - color).magnitude_squared();
+                    let mut weight = (-distance / (2.0 * spatial_sigma * spatial_sigma)).exp()
+                        * (-difference / (2.0 * color_sigma * color_sigma)).exp();
+                    if let Some(depths) = depths {
+                        weight *= depth_weight(depths[center], depths[tap]);
+                    }
+                    sum += radiance[tap] * weight;
+                    total += weight;
+                }
+            }
+            // The center tap always has a weight of 1
+            filtered.push(sum / total);
+        }
+    }
+    filtered
+}
+
+// Maps radiance into 0..1 so the color weight treats bright and dim areas alike
+fn compress(radiance: &Vec3) -> Vec3 {
+    radiance.map(|c| c / (1.0 + c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    // A 16 x 16 image of `value` with noise of up to `noise` either way in each channel
+    fn noisy(value: f32, noise: f32) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(16, 16);
+        let mut rng = Rng::new(3);
+        for radiance in &mut framebuffer.radiance {
+            *radiance = Vec3::repeat(value) + Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()).map(|r| (2.0 * r - 1.0) * noise);
+        }
+        framebuffer
+    }
+
+    fn mean(radiance: &[Vec3]) -> Vec3 {
+        radiance.iter().sum::<Vec3>() / radiance.len() as f32
+    }
+
+    fn spread(radiance: &[Vec3]) -> f32 {
+        let mean = mean(radiance);
+        radiance.iter().map(|c| (c - mean).magnitude_squared()).sum::<f32>() / radiance.len() as f32
+    }
+
+    #[test]
+    fn uniform_image_stays_as_it_is() {
+        let mut framebuffer = noisy(0.5, 0.0);
+        framebuffer.denoise_bilateral(2.0, 0.1);
+        for radiance in &framebuffer.radiance {
+            assert!((radiance - Vec3::repeat(0.5)).magnitude() < 1e-6, "{:?}", radiance);
+        }
+    }
+
+    #[test]
+    fn noise_on_a_flat_region_is_smoothed_towards_its_mean() {
+        let mut framebuffer = noisy(0.5, 0.1);
+        let (before, noise) = (mean(&framebuffer.radiance), spread(&framebuffer.radiance));
+        framebuffer.denoise_bilateral(2.0, 0.2);
+        assert!(spread(&framebuffer.radiance) < noise * 0.2, "{} from {}", spread(&framebuffer.radiance), noise);
+        assert!((mean(&framebuffer.radiance) - before).magnitude() < 0.01);
+    }
+
+    // Left half dark at a distance of 1, right half bright at 10, both noisy
+    fn step() -> Framebuffer {
+        let mut framebuffer = noisy(0.0, 0.05);
+        for (i, radiance) in framebuffer.radiance.iter_mut().enumerate() {
+            *radiance += Vec3::repeat(if i % 16 < 8 { 0.2 } else { 0.8 });
+        }
+        framebuffer.depth = (0..256).map(|i| if i % 16 < 8 { 1.0 } else { 10.0 }).collect();
+        framebuffer
+    }
+
+    fn assert_sides_apart(framebuffer: &Framebuffer) {
+        for y in 0..16 {
+            let (left, right) = (framebuffer.radiance[y * 16 + 7], framebuffer.radiance[y * 16 + 8]);
+            assert!(left.max() < 0.3 && right.min() > 0.7, "row {}: {:?} and {:?}", y, left, right);
+        }
+    }
+
+    #[test]
+    fn depth_edges_stay_sharp_whatever_the_colors() {
+        // Colors this far apart would blend without the depths
+        let mut guided = step();
+        guided.denoise_bilateral(2.0, 10.0);
+        assert_sides_apart(&guided);
+        let mut unguided = step();
+        unguided.depth.clear();
+        unguided.denoise_bilateral(2.0, 10.0);
+        assert!(unguided.radiance[7].x > 0.3);
+    }
+
+    #[test]
+    fn color_edges_stay_sharp_without_depths() {
+        let mut framebuffer = step();
+        framebuffer.depth.clear();
+        framebuffer.denoise_bilateral(2.0, 0.05);
+        assert_sides_apart(&framebuffer);
+    }
+
+    #[test]
+    fn bilateral_denoiser_applies_the_framebuffer_filter() {
+        let mut framebuffer = step();
+        let guides = GuideBuffers { normals: vec![Vec3::y(); 256], depths: framebuffer.depth.clone() };
+        let denoiser = Denoiser { filter: DenoiseFilter::Bilateral, radius: 4, sigma: 0.2 };
+        let denoised = denoiser.apply(&framebuffer.radiance, 16, 16, &guides);
+        framebuffer.denoise_bilateral(2.0, 0.2);
+        assert_eq!(denoised, framebuffer.radiance);
+        assert_eq!(DenoiseFilter::from_name("Bilateral"), Some(DenoiseFilter::Bilateral));
+        assert_eq!(DenoiseFilter::from_name("atrous"), Some(DenoiseFilter::ATrous));
+        assert_eq!(DenoiseFilter::from_name("median"), None);
+    }
+}
@@ -0,0 +1,117 @@
+use nalgebra_glm::{Vec3, dot};
+use serde::{Deserialize, Serialize};
+use crate::ray_intersect::{RayIntersect, Material, Intersect, Ray};
+use crate::aabb::Aabb;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Disk {
+    // A circle tilted away from an axis reaches less far along it
+    pub fn bounding_box(&self) -> Aabb {
+        let normal = self.normal.normalize();
+        let half_size = normal.map(|n| self.radius * (1.0 - n * n).max(0.0).sqrt());
+        Aabb::around(&self.center, &half_size)
+    }
+}
+
+impl RayIntersect for Disk {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let normal = self.normal.normalize();
+
+        // A ray parallel to the disk's plane never crosses it
+        let denom = dot(&normal, &ray.direction);
+        if denom.abs() < 1e-6 {
+            return Intersect::empty();
+        }
+
+        // Rays going the way the normal points come from behind
+        if denom > 0.0 && !self.material.double_sided {
+            return Intersect::empty();
+        }
+
+        // Distance along the ray to the plane that contains the disk
+        let t = dot(&(self.center - ray.origin), &normal) / denom;
+        if t <= t_min || t >= t_max {
+            return Intersect::empty();
+        }
+
+        // Only hits within the radius around the center belong to the disk
+        let point = ray.at(t);
+        let offset = point - self.center;
+        if dot(&offset, &offset) > self.radius * self.radius {
+            return Intersect::empty();
+        }
+
+        // The disk has no inside, so the normal faces the incoming ray
+        let facing_normal = if denom > 0.0 { -normal } else { normal };
+
+        Intersect::new(point, facing_normal, t, self.material.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor_disk() -> Disk {
+        Disk {
+            center: Vec3::zeros(),
+            normal: Vec3::y(),
+            radius: 1.0,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn ray_inside_the_radius_hits() {
+        let ray = Ray::new(Vec3::new(0.5, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = floor_disk().ray_intersect(&ray, 0.0, f32::INFINITY);
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - 2.0).abs() < 1e-6);
+        assert!((hit.point - Vec3::new(0.5, 0.0, 0.0)).magnitude() < 1e-6);
+        assert_eq!(hit.normal, Vec3::y());
+    }
+
+    #[test]
+    fn ray_just_outside_the_radius_misses() {
+        let ray = Ray::new(Vec3::new(1.001, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(!floor_disk().ray_intersect(&ray, 0.0, f32::INFINITY).is_intersecting);
+    }
+
+    #[test]
+    fn ray_parallel_to_the_disk_misses() {
+        let ray = Ray::new(Vec3::new(-2.0, 0.0, 0.0), Vec3::x());
+        assert!(!floor_disk().ray_intersect(&ray, 0.0, f32::INFINITY).is_intersecting);
+    }
+
+    #[test]
+    fn one_sided_disk_is_invisible_from_behind() {
+        let disk = Disk { material: Material { double_sided: false, ..Material::default() }, ..floor_disk() };
+        let from_below = Ray::new(Vec3::new(0.5, -2.0, 0.0), Vec3::y());
+        assert!(!disk.ray_intersect(&from_below, 0.0, f32::INFINITY).is_intersecting);
+        let from_above = Ray::new(Vec3::new(0.5, 2.0, 0.0), -Vec3::y());
+        assert_eq!(disk.ray_intersect(&from_above, 0.0, f32::INFINITY).normal, Vec3::y());
+    }
+
+    #[test]
+    fn double_sided_disk_faces_the_ray_from_either_side() {
+        // Surfaces are double sided unless their material says otherwise
+        let disk = floor_disk();
+        for direction in [-Vec3::y(), Vec3::y(), Vec3::new(0.3, 1.0, 0.0).normalize(), Vec3::new(0.0, -1.0, 0.4).normalize()] {
+            let ray = Ray::new(-direction * 2.0, direction);
+            let hit = disk.ray_intersect(&ray, 0.0, f32::INFINITY);
+            assert!(hit.is_intersecting, "{:?}", direction);
+            assert!((hit.distance - 2.0).abs() < 1e-6);
+            assert!(hit.point.magnitude() < 1e-6);
+            assert_eq!(hit.normal, if direction.y < 0.0 { Vec3::y() } else { -Vec3::y() });
+            assert!(hit.normal.dot(&ray.direction) < 0.0);
+        }
+    }
+}
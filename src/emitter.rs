@@ -0,0 +1,96 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+
+use crate::ray_intersect::Material;
+use crate::render::perpendicular_basis;
+use crate::rng::Rng;
+use crate::scene::{Object, Scene};
+
+// An emissive object whose surface the path tracer can pick points on, to aim shadow
+// rays straight at it instead of waiting for random bounces to find it
+#[derive(Debug, Clone)]
+pub struct Emitter {
+    pub object: usize, // Index into the scene's objects
+    pub area: f32,
+    shape: Shape,
+}
+
+#[derive(Debug, Clone)]
+enum Shape {
+    Sphere { center: Vec3, radius: f32 },
+    Disk { center: Vec3, normal: Vec3, radius: f32 },
+    Cube { center: Vec3, size: Vec3 },
+}
+
+impl Emitter {
+    // Every emissive sphere, disk and box in the scene with a plain emission color. Other
+    // emissive objects, including textured ones whose color depends on where they are hit,
+    // still light the scene, but only through the rays that happen to hit them.
+    pub fn collect(scene: &Scene) -> Vec<Emitter> {
+        scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter_map(|(object, shape)| {
+                let (shape, area) = match shape {
+                    Object::Sphere(sphere) if emits_uniformly(&sphere.material) => (
+                        Shape::Sphere { center: sphere.center, radius: sphere.radius },
+                        4.0 * PI * sphere.radius * sphere.radius,
+                    ),
+                    Object::Disk(disk) if emits_uniformly(&disk.material) => (
+                        Shape::Disk { center: disk.center, normal: disk.normal.normalize(), radius: disk.radius },
+                        PI * disk.radius * disk.radius,
+                    ),
+                    Object::Cube(cube) if emits_uniformly(&cube.material) => (
+                        Shape::Cube { center: cube.center, size: cube.size },
+                        2.0 * (cube.size.x * cube.size.y + cube.size.y * cube.size.z + cube.size.z * cube.size.x),
+                    ),
+                    _ => return None,
+                };
+                Some(Emitter { object, area, shape })
+            })
+            .collect()
+    }
+
+    // A uniformly distributed point on the surface and the surface normal there
+    pub fn sample(&self, rng: &mut Rng) -> (Vec3, Vec3) {
+        match &self.shape {
+            Shape::Sphere { center, radius } => {
+                let z = 1.0 - 2.0 * rng.next_f32();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.next_f32();
+                let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                (center + normal * *radius, normal)
+            }
+            Shape::Disk { center, normal, radius } => {
+                let (tangent, bitangent) = perpendicular_basis(normal);
+                let (x, y) = rng.in_unit_disk();
+                (center + (tangent * x + bitangent * y) * *radius, *normal)
+            }
+            Shape::Cube { center, size } => {
+                // Pick a face with probability proportional to its area, then a point on it
+                let face_areas = [size.y * size.z, size.z * size.x, size.x * size.y];
+                let mut pick = rng.next_f32() * (face_areas[0] + face_areas[1] + face_areas[2]);
+                let mut axis = 2;
+                for (i, area) in face_areas.iter().enumerate() {
+                    if pick < *area {
+                        axis = i;
+                        break;
+                    }
+                    pick -= area;
+                }
+                let side = if rng.next_f32() < 0.5 { -1.0 } else { 1.0 };
+
+                let mut offset = Vec3::new(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5);
+                offset[axis] = 0.5 * side;
+                let mut normal = Vec3::zeros();
+                normal[axis] = side;
+                (center + offset.component_mul(size), normal)
+            }
+        }
+    }
+}
+
+fn emits_uniformly(material: &Material) -> bool {
+    material.emission_strength > 0.0 && material.emission_texture.is_none()
+}
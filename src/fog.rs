@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+
+// Exponential distance fog: light coming from `t` away is mixed with the fog color by
+// 1 - e^(-density * t), so distant parts of the scene fade into it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fog {
+    pub color: Color,
+    pub density: f32, // Per unit of distance; 0 turns the fog off
+    // Whether rays that escape the scene end in fog instead of showing the background
+    #[serde(default)]
+    pub background: bool,
+}
+
+impl Fog {
+    // Fraction of the light from a hit at `distance` that is replaced by fog color. Infinite
+    // distances are rays that missed everything.
+    pub fn amount(&self, distance: f32) -> f32 {
+        if self.density <= 0.0 {
+            0.0
+        } else if distance.is_infinite() {
+            if self.background { 1.0 } else { 0.0 }
+        } else {
+            1.0 - (-self.density * distance).exp()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fog(density: f32, background: bool) -> Fog {
+        Fog { color: Color::white(), density, background }
+    }
+
+    #[test]
+    fn no_density_is_no_fog() {
+        for background in [false, true] {
+            for distance in [0.0, 1.0, 1e6, f32::INFINITY] {
+                assert_eq!(fog(0.0, background).amount(distance), 0.0);
+                assert_eq!(fog(-1.0, background).amount(distance), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn fog_thickens_with_distance() {
+        for background in [false, true] {
+            let fog = fog(0.5, background);
+            assert_eq!(fog.amount(0.0), 0.0);
+            assert!((fog.amount(2.0) - (1.0 - (-1.0f32).exp())).abs() < 1e-6);
+            assert!(fog.amount(1.0) < fog.amount(2.0) && fog.amount(2.0) < fog.amount(4.0));
+            assert!(fog.amount(100.0) > 0.999 && fog.amount(100.0) <= 1.0);
+        }
+    }
+
+    #[test]
+    fn escaping_rays_are_fogged_only_with_the_background() {
+        assert_eq!(fog(0.5, false).amount(f32::INFINITY), 0.0);
+        assert_eq!(fog(0.5, true).amount(f32::INFINITY), 1.0);
+    }
+}
@@ -0,0 +1,58 @@
+// Smooths per-frame timings with an exponential moving average so the displayed
+// frame rate doesn't jitter from one frame to the next
+pub struct FpsCounter {
+    smoothing: f32,          // Weight given to the newest frame, in (0, 1]
+    average_frame_time: f32, // Seconds, zero until the first tick
+}
+
+impl FpsCounter {
+    pub fn new(smoothing: f32) -> Self {
+        FpsCounter {
+            smoothing: smoothing.clamp(f32::EPSILON, 1.0),
+            average_frame_time: 0.0,
+        }
+    }
+
+    // Feed the duration of the last frame in seconds and get the smoothed frame rate back
+    pub fn tick(&mut self, dt: f32) -> f32 {
+        if self.average_frame_time == 0.0 {
+            // The first frame seeds the average instead of being blended with zero
+            self.average_frame_time = dt;
+        } else {
+            self.average_frame_time += self.smoothing * (dt - self.average_frame_time);
+        }
+        self.fps()
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.average_frame_time > 0.0 {
+            1.0 / self.average_frame_time
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_frame_times_converge_to_their_rate() {
+        let mut counter = FpsCounter::new(0.1);
+        // Start far off so the average has to move
+        counter.tick(1.0);
+        let mut fps = 0.0;
+        for _ in 0..200 {
+            fps = counter.tick(1.0 / 50.0);
+        }
+        assert!((fps - 50.0).abs() < 0.01, "{}", fps);
+    }
+
+    #[test]
+    fn first_tick_gives_its_own_rate() {
+        let mut counter = FpsCounter::new(0.1);
+        assert_eq!(counter.fps(), 0.0);
+        assert!((counter.tick(0.25) - 4.0).abs() < 1e-6);
+    }
+}
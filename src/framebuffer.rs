@@ -1,7 +1,20 @@
+use nalgebra_glm::Vec3;
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use crate::font::{self, GLYPH_SIZE};
+
 pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
     pub buffer: Vec<u32>,
+    // Linear HDR value behind each displayed pixel, kept so exposure and tone mapping
+    // changes can be re-applied without tracing the image again
+    pub radiance: Vec<Vec3>,
+    // Distance to the first surface seen through each pixel's center, infinite for the
+    // background. Only filled in by passes that need it, until then it's empty.
+    pub depth: Vec<f32>,
+    #[allow(dead_code)]
     background_color: u32,
     current_color: u32,
 }
@@ -12,23 +25,234 @@ impl Framebuffer {
             width,
             height,
             buffer: vec![0; width * height],
+            radiance: vec![Vec3::zeros(); width * height],
+            depth: Vec::new(),
             background_color: 0x000000,
             current_color: 0xFFFFFF,
         }
     }
 
-    pub fn clear(&mut self) {
-        for pixel in self.buffer.iter_mut() {
-            *pixel = self.background_color;
+    // Reallocates the buffers for a new size. The old contents don't carry over, the next
+    // render fills them in again.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.buffer = vec![0; width * height];
+        self.radiance = vec![Vec3::zeros(); width * height];
+        self.depth.clear();
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        self.buffer.fill(color);
+    }
+
+    // Fills a w x h rectangle whose top-left corner is (x, y), clipped to the buffer
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: u32) {
+        let x1 = x.saturating_add(w).min(self.width);
+        let y1 = y.saturating_add(h).min(self.height);
+        if x >= x1 {
+            return;
+        }
+        for row in y.min(y1)..y1 {
+            self.buffer[row * self.width + x..row * self.width + x1].fill(color);
+        }
+    }
+
+    // Returns None outside the buffer
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<u32> {
+        if x < self.width && y < self.height {
+            Some(self.buffer[y * self.width + x])
+        } else {
+            None
         }
     }
 
+    // Rows of pixels from top to bottom, each `width` long
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u32]> {
+        self.buffer.chunks_exact_mut(self.width.max(1))
+    }
+
     pub fn point(&mut self, x: usize, y: usize) {
         if x < self.width && y < self.height {
             self.buffer[y * self.width + x] = self.current_color;
         }
     }
 
+    // Bresenham line between two pixels, both ends included. Either end may lie outside the
+    // buffer, only the pixels inside it are set.
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: u32) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (step_x, step_y) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            if (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y) {
+                self.buffer[y as usize * self.width + x as usize] = color;
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    // Draws text in the built-in 8x8 font with its top-left corner at (x, y). A newline
+    // starts a new line back at x, and pixels past the right or bottom edge are dropped.
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: u32) {
+        let (mut glyph_x, mut glyph_y) = (x, y);
+        for c in text.chars() {
+            if c == '\n' {
+                glyph_x = x;
+                glyph_y += GLYPH_SIZE;
+                continue;
+            }
+
+            for (row, bits) in font::glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_SIZE {
+                    let (px, py) = (glyph_x + column, glyph_y + row);
+                    if (bits >> column) & 1 == 1 && px < self.width && py < self.height {
+                        self.buffer[py * self.width + px] = color;
+                    }
+                }
+            }
+            glyph_x += GLYPH_SIZE;
+        }
+    }
+
+    // A copy shrunk by `factor` in both directions, each pixel the average of a factor x
+    // factor block. Blocks cut off by the right or bottom edge average the pixels they have.
+    pub fn downsample(&self, factor: u32) -> Framebuffer {
+        let factor = factor.max(1) as usize;
+        let mut small = Framebuffer::new(self.width.div_ceil(factor), self.height.div_ceil(factor));
+
+        for y in 0..small.height {
+            for x in 0..small.width {
+                let (x0, y0) = (x * factor, y * factor);
+                let (x1, y1) = ((x0 + factor).min(self.width), (y0 + factor).min(self.height));
+
+                let mut channels = [0u32; 3];
+                let mut radiance = Vec3::zeros();
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let pixel = self.buffer[sy * self.width + sx];
+                        channels[0] += (pixel >> 16) & 0xFF;
+                        channels[1] += (pixel >> 8) & 0xFF;
+                        channels[2] += pixel & 0xFF;
+                        radiance += self.radiance[sy * self.width + sx];
+                    }
+                }
+
+                let count = ((x1 - x0) * (y1 - y0)) as u32;
+                let [r, g, b] = channels.map(|sum| (sum + count / 2) / count);
+                small.buffer[y * small.width + x] = (r << 16) | (g << 8) | b;
+                small.radiance[y * small.width + x] = radiance / count as f32;
+            }
+        }
+        small
+    }
+
+    // Fills `target` with this image stretched to its size, each pixel copying the one it
+    // falls on here. Its depth buffer is cleared, as this has no more detail to give it.
+    pub fn upscale_into(&self, target: &mut Framebuffer) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        for y in 0..target.height {
+            let sy = y * self.height / target.height;
+            for x in 0..target.width {
+                let sx = x * self.width / target.width;
+                target.buffer[y * target.width + x] = self.buffer[sy * self.width + sx];
+                target.radiance[y * target.width + x] = self.radiance[sy * self.width + sx];
+            }
+        }
+        target.depth.clear();
+    }
+
+    // Blackens the pixels on the outlines of objects for a toon look: wherever a Sobel
+    // filter finds the first surface jumping in distance by more than `threshold`, relative
+    // to the nearer side. Without a depth buffer it looks for jumps in brightness instead.
+    // Only the displayed pixels change, so retone mapping the radiance removes the lines.
+    pub fn edge_detect(&mut self, threshold: f32) {
+        let values: Vec<f32> = if self.depth.len() == self.width * self.height {
+            // Inverse depth changes evenly across a flat surface and is 0 for the background
+            self.depth.iter().map(|d| if d.is_finite() && *d > 0.0 { 1.0 / d } else { 0.0 }).collect()
+        } else {
+            self.radiance.iter().map(|c| {
+                let luminance = c.x * 0.2126 + c.y * 0.7152 + c.z * 0.0722;
+                luminance / (1.0 + luminance)
+            }).collect()
+        };
+
+        let at = |x: usize, y: usize, dx: isize, dy: isize| {
+            let sx = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+            let sy = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+            values[sy * self.width + sx]
+        };
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let v = |dx, dy| at(x, y, dx, dy);
+                let gx = v(1, -1) + 2.0 * v(1, 0) + v(1, 1) - v(-1, -1) - 2.0 * v(-1, 0) - v(-1, 1);
+                let gy = v(-1, 1) + 2.0 * v(0, 1) + v(1, 1) - v(-1, -1) - 2.0 * v(0, -1) - v(1, -1);
+
+                // The nearest of the neighborhood, so a step is measured against the object
+                // in front rather than the background
+                let mut nearest = 0.0f32;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        nearest = nearest.max(v(dx, dy));
+                    }
+                }
+                // The Sobel taps add up to 4 on each side of a step
+                let gradient = (gx * gx + gy * gy).sqrt() / 4.0;
+                if nearest > 0.0 && gradient / nearest > threshold {
+                    self.buffer[y * self.width + x] = 0x000000;
+                }
+            }
+        }
+    }
+
+    // Darkens the image towards its edges like a camera lens does: each pixel is scaled by
+    // 1 - strength * d², where d is its distance from the center as a fraction of the
+    // distance to a corner. The center stays as it is and the corners lose `strength` of
+    // their brightness. Like the outlines, only the displayed pixels change.
+    pub fn vignette(&mut self, strength: f32) {
+        let center_x = self.width as f32 / 2.0;
+        let center_y = self.height as f32 / 2.0;
+        let corner_squared = center_x * center_x + center_y * center_y;
+        if corner_squared == 0.0 {
+            return;
+        }
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let dx = x as f32 + 0.5 - center_x;
+                let dy = y as f32 + 0.5 - center_y;
+                let factor = (1.0 - strength * (dx * dx + dy * dy) / corner_squared).clamp(0.0, 1.0);
+
+                let pixel = &mut self.buffer[y * self.width + x];
+                let [r, g, b] = [16, 8, 0].map(|shift| {
+                    let channel = ((*pixel >> shift) & 0xFF) as f32;
+                    ((channel * factor).round() as u32) << shift
+                });
+                *pixel = r | g | b;
+            }
+        }
+    }
+
+    pub fn set_radiance(&mut self, x: usize, y: usize, radiance: Vec3) {
+        if x < self.width && y < self.height {
+            self.radiance[y * self.width + x] = radiance;
+        }
+    }
+
     pub fn set_background_color(&mut self, color: u32) {
         self.background_color = color;
     }
@@ -36,4 +260,182 @@ impl Framebuffer {
     pub fn set_current_color(&mut self, color: u32) {
         self.current_color = color;
     }
-}
\ No newline at end of file
+
+    // Write the buffer as an 8-bit RGB PNG, dropping the unused high byte of each pixel
+    pub fn save_png(&self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut data = Vec::with_capacity(self.width * self.height * 3);
+        for pixel in &self.buffer {
+            data.push(((pixel >> 16) & 0xFF) as u8);
+            data.push(((pixel >> 8) & 0xFF) as u8);
+            data.push((pixel & 0xFF) as u8);
+        }
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_pixel_outside_the_buffer_is_none() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        framebuffer.clear(0x123456);
+        assert_eq!(framebuffer.get_pixel(3, 2), Some(0x123456));
+        assert_eq!(framebuffer.get_pixel(4, 0), None);
+        assert_eq!(framebuffer.get_pixel(0, 3), None);
+        assert_eq!(framebuffer.get_pixel(usize::MAX, usize::MAX), None);
+    }
+
+    #[test]
+    fn fill_rect_is_clipped_to_the_edges() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        framebuffer.fill_rect(2, 1, 10, 10, 0xffffff);
+        let filled: Vec<u32> = framebuffer.buffer.iter().map(|&pixel| (pixel != 0) as u32).collect();
+        assert_eq!(filled, [0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1]);
+
+        // Rectangles entirely outside, or reaching past the end of usize, are fine too
+        framebuffer.fill_rect(4, 0, 2, 2, 0xff0000);
+        framebuffer.fill_rect(0, 3, 2, 2, 0xff0000);
+        framebuffer.fill_rect(1, 0, usize::MAX, 1, 0x00ff00);
+        assert_eq!(&framebuffer.buffer[..4], [0, 0x00ff00, 0x00ff00, 0x00ff00]);
+        assert!(!framebuffer.buffer.contains(&0xff0000));
+    }
+
+    #[test]
+    fn rows_are_a_width_long() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        for (y, row) in framebuffer.rows_mut().enumerate() {
+            assert_eq!(row.len(), 4);
+            row.fill(y as u32);
+        }
+        assert_eq!(framebuffer.get_pixel(3, 2), Some(2));
+        assert_eq!(framebuffer.get_pixel(0, 1), Some(1));
+    }
+
+    #[test]
+    fn resize_reallocates_everything() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        framebuffer.clear(0xffffff);
+        framebuffer.set_radiance(1, 1, Vec3::repeat(1.0));
+        framebuffer.depth = vec![1.0; 12];
+
+        framebuffer.resize(6, 5);
+        assert_eq!((framebuffer.width, framebuffer.height), (6, 5));
+        assert_eq!(framebuffer.buffer, vec![0; 30]);
+        assert_eq!(framebuffer.radiance, vec![Vec3::zeros(); 30]);
+        assert!(framebuffer.depth.is_empty());
+        assert_eq!(framebuffer.rows_mut().count(), 5);
+    }
+
+    #[test]
+    fn downsampling_averages_each_block() {
+        // A checkerboard of red and blue pixels, with matching radiance
+        let mut framebuffer = Framebuffer::new(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                let red = (x + y) % 2 == 0;
+                framebuffer.buffer[y * 4 + x] = if red { 0xff0000 } else { 0x0000ff };
+                framebuffer.radiance[y * 4 + x] = if red { Vec3::x() } else { Vec3::z() };
+            }
+        }
+
+        let small = framebuffer.downsample(2);
+        assert_eq!((small.width, small.height), (2, 2));
+        assert_eq!(small.buffer, vec![0x800080; 4]);
+        assert_eq!(small.radiance, vec![Vec3::new(0.5, 0.0, 0.5); 4]);
+    }
+
+    #[test]
+    fn partial_blocks_average_the_pixels_they_have() {
+        let mut framebuffer = Framebuffer::new(3, 3);
+        framebuffer.clear(0x404040);
+        framebuffer.buffer[2] = 0xffffff;
+        framebuffer.buffer[5] = 0x000000;
+
+        let small = framebuffer.downsample(2);
+        assert_eq!((small.width, small.height), (2, 2));
+        assert_eq!(small.buffer, vec![0x404040, 0x808080, 0x404040, 0x404040]);
+    }
+
+    #[test]
+    fn depth_steps_are_outlined_and_flat_regions_are_not() {
+        // A near surface on the left half against the background, and a wall across the
+        // right half that slopes away evenly
+        let mut framebuffer = Framebuffer::new(8, 4);
+        framebuffer.clear(0xffffff);
+        framebuffer.depth = (0..32)
+            .map(|i| match i % 8 {
+                0..=1 => 2.0,
+                2..=3 => f32::INFINITY,
+                x => 5.0 + x as f32 * 0.1,
+            })
+            .collect();
+
+        framebuffer.edge_detect(0.2);
+        for y in 0..4 {
+            let row = &framebuffer.buffer[y * 8..y * 8 + 8];
+            // Both sides of the near surface's edge are outlined
+            assert_eq!(row[1], 0x000000);
+            assert_eq!(row[2], 0x000000);
+            // The edge of the far wall against the background is too, but not its middle
+            assert_eq!(row[4], 0x000000);
+            assert_eq!(&row[5..], [0xffffff; 3]);
+        }
+    }
+
+    // The pixels of a line drawn on an empty buffer
+    fn line_pixels(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<(usize, usize)> {
+        let mut framebuffer = Framebuffer::new(8, 6);
+        framebuffer.draw_line(x0, y0, x1, y1, 0xffffff);
+        (0..48).filter(|i| framebuffer.buffer[*i] != 0).map(|i| (i % 8, i / 8)).collect()
+    }
+
+    #[test]
+    fn horizontal_line_sets_exactly_its_pixels() {
+        assert_eq!(line_pixels(1, 2, 5, 2), [(1, 2), (2, 2), (3, 2), (4, 2), (5, 2)]);
+        // Either way round
+        assert_eq!(line_pixels(5, 2, 1, 2), line_pixels(1, 2, 5, 2));
+    }
+
+    #[test]
+    fn lines_are_clipped_and_connected() {
+        assert_eq!(line_pixels(0, 0, 3, 3), [(0, 0), (1, 1), (2, 2), (3, 3)]);
+        // A steep line has one pixel per row
+        let steep = line_pixels(2, 0, 3, 5);
+        assert_eq!(steep.len(), 6);
+        assert!(steep.iter().enumerate().all(|(row, &(_, y))| y == row));
+        // Only the part inside the buffer is drawn
+        assert_eq!(line_pixels(-3, 1, 2, 1), [(0, 1), (1, 1), (2, 1)]);
+        assert!(line_pixels(-5, -5, -1, -1).is_empty());
+    }
+
+    #[test]
+    fn vignette_keeps_the_center_and_darkens_the_corners_by_its_strength() {
+        let vignetted = |strength| {
+            let mut framebuffer = Framebuffer::new(9, 9);
+            framebuffer.clear(0xc8c8c8);
+            framebuffer.vignette(strength);
+            framebuffer
+        };
+        // The corner pixel's middle is 4 pixels across and down from the image's, which is
+        // 4.5 from its corners, so it is at d² = 32 / 40.5
+        let d_squared = 32.0 / 40.5;
+        for strength in [0.0, 0.25, 0.5, 1.0] {
+            let framebuffer = vignetted(strength);
+            assert_eq!(framebuffer.get_pixel(4, 4), Some(0xc8c8c8), "strength {}", strength);
+            let corner = (200.0 * (1.0 - strength * d_squared)).round() as u32;
+            for (x, y) in [(0, 0), (8, 0), (0, 8), (8, 8)] {
+                assert_eq!(framebuffer.get_pixel(x, y), Some(corner * 0x010101), "strength {} at {}, {}", strength, x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,605 @@
+use nalgebra_glm::{self as glm, Mat4, Vec3, Vec4};
+use std::fs;
+use std::path::Path;
+
+use crate::color::Color;
+use crate::mesh::{Face, Model};
+use crate::ray_intersect::Material;
+
+// Reads a glTF 2.0 file into one model: every triangle of every mesh in its scene, moved
+// into world space by the transforms of the nodes above it. Only the JSON kind (.gltf) is
+// read, with its buffers in files next to it or embedded as base64 data URIs. Materials
+// keep their base color and whether they're double-sided; textures, animations, skins,
+// cameras and lights are skipped.
+pub fn load(path: &str) -> Result<Model, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("could not open model '{}': {}", path, err))?;
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+    parse(&source, path, directory)
+}
+
+// `load` for a file already read. Buffers given by a relative path are looked for in
+// `directory`, and `path` is only used to label errors.
+pub fn parse(source: &str, path: &str, directory: &Path) -> Result<Model, String> {
+    let invalid = |reason: String| format!("invalid glTF '{}': {}", path, reason);
+    let root = Json::parse(source).map_err(invalid)?;
+    let buffers = root
+        .get("buffers")
+        .map_or(&[][..], Json::items)
+        .iter()
+        .map(|buffer| load_buffer(buffer, directory))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let document = Document { root: &root, buffers };
+
+    let materials = document.array("materials").iter().map(material).collect::<Vec<_>>();
+    let mut geometry = Geometry::default();
+    for node in document.scene_nodes().map_err(invalid)? {
+        document.add_node(node, &Mat4::identity(), 0, materials.len(), &mut geometry).map_err(invalid)?;
+    }
+    Ok(Model::from_faces(path, geometry.positions, geometry.normals, geometry.faces, materials))
+}
+
+// The triangles gathered from the nodes so far, in world space
+#[derive(Default)]
+struct Geometry {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    faces: Vec<Face>,
+}
+
+struct Document<'a> {
+    root: &'a Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Document<'_> {
+    // The array at the top level under `key`, empty when the file has none
+    fn array(&self, key: &str) -> &[Json] {
+        self.root.get(key).map_or(&[], Json::items)
+    }
+
+    // The root nodes of the scene the file picks, or of the first one. Files without any
+    // scene get every node that isn't another one's child.
+    fn scene_nodes(&self) -> Result<Vec<usize>, String> {
+        let scenes = self.array("scenes");
+        if scenes.is_empty() {
+            let children: Vec<usize> = self
+                .array("nodes")
+                .iter()
+                .flat_map(|node| node.get("children").map_or(&[][..], Json::items))
+                .filter_map(Json::as_usize)
+                .collect();
+            return Ok((0..self.array("nodes").len()).filter(|node| !children.contains(node)).collect());
+        }
+        let scene = self.root.get("scene").and_then(Json::as_usize).unwrap_or(0);
+        let scene = scenes.get(scene).ok_or_else(|| format!("scene {} doesn't exist", scene))?;
+        indices(scene.get("nodes"), "scene nodes")
+    }
+
+    // Adds the meshes of a node and of everything below it, with `parent` the transform
+    // from the node's parent to world space
+    fn add_node(&self, index: usize, parent: &Mat4, depth: usize, materials: usize, geometry: &mut Geometry) -> Result<(), String> {
+        let nodes = self.array("nodes");
+        // A tree can't be deeper than it has nodes, only a cycle can
+        if depth > nodes.len() {
+            return Err("the node hierarchy has a cycle".to_string());
+        }
+        let node = nodes.get(index).ok_or_else(|| format!("node {} doesn't exist", index))?;
+        let world = parent * local_transform(node)?;
+
+        if let Some(mesh) = node.get("mesh") {
+            let mesh = mesh.as_usize().ok_or("a node's mesh isn't an index")?;
+            let mesh = self.array("meshes").get(mesh).ok_or_else(|| format!("mesh {} doesn't exist", mesh))?;
+            for primitive in mesh.get("primitives").map_or(&[][..], Json::items) {
+                self.add_primitive(primitive, &world, materials, geometry)?;
+            }
+        }
+        for child in indices(node.get("children"), "node children")? {
+            self.add_node(child, &world, depth + 1, materials, geometry)?;
+        }
+        Ok(())
+    }
+
+    // Adds the triangles of one primitive moved by `world`. Points, lines and triangle
+    // strips or fans are skipped.
+    fn add_primitive(&self, primitive: &Json, world: &Mat4, materials: usize, geometry: &mut Geometry) -> Result<(), String> {
+        const TRIANGLES: usize = 4;
+        if primitive.get("mode").and_then(Json::as_usize).unwrap_or(TRIANGLES) != TRIANGLES {
+            return Ok(());
+        }
+        let attributes = primitive.get("attributes").ok_or("a primitive has no attributes")?;
+        let position = attributes.get("POSITION").and_then(Json::as_usize).ok_or("a primitive has no positions")?;
+        let positions = self.vectors(position)?;
+        let normals = match attributes.get("NORMAL").and_then(Json::as_usize) {
+            Some(normal) => Some(self.vectors(normal)?).filter(|normals| normals.len() == positions.len()),
+            None => None,
+        };
+        let corners: Vec<usize> = match primitive.get("indices").and_then(Json::as_usize) {
+            Some(indices) => self.accessor(indices)?.0.into_iter().map(|index| index as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        if corners.iter().any(|&corner| corner >= positions.len()) {
+            return Err("a primitive refers to a vertex it doesn't have".to_string());
+        }
+        let material = match primitive.get("material") {
+            Some(material) => Some(material.as_usize().filter(|&material| material < materials).ok_or("a primitive refers to a material that doesn't exist")?),
+            None => None,
+        };
+
+        // Normals move by the inverse transpose, which keeps them perpendicular under
+        // non-uniform scaling. A mirroring transform turns the corners around, so they're
+        // put back in the order that makes the front face the same way.
+        let linear = glm::mat4_to_mat3(world);
+        let normal_matrix = glm::transpose(&glm::inverse(&linear));
+        let mirrored = linear.determinant() < 0.0;
+
+        let (position_offset, normal_offset) = (geometry.positions.len(), geometry.normals.len());
+        geometry.positions.extend(positions.iter().map(|p| (world * Vec4::new(p.x, p.y, p.z, 1.0)).xyz()));
+        if let Some(normals) = &normals {
+            geometry.normals.extend(normals.iter().map(|n| (normal_matrix * n).try_normalize(1e-12).unwrap_or_else(Vec3::zeros)));
+        }
+        for triangle in corners.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+            let corners = if mirrored { [a, c, b] } else { [a, b, c] };
+            let normal_indices = corners.map(|corner| normals.as_ref().map(|_| normal_offset + corner));
+            geometry.faces.push((corners.map(|corner| position_offset + corner), normal_indices, material));
+        }
+        Ok(())
+    }
+
+    // The elements of an accessor of three numbers each, like positions and normals
+    fn vectors(&self, index: usize) -> Result<Vec<Vec3>, String> {
+        let (values, components) = self.accessor(index)?;
+        if components != 3 {
+            return Err(format!("accessor {} doesn't hold 3D vectors", index));
+        }
+        Ok(values.chunks_exact(3).map(|v| Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32)).collect())
+    }
+
+    // The numbers of an accessor, one element after the other, and how many each element
+    // has. Integers are read as they are, without normalizing them to 0..1.
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), String> {
+        let accessor = self.array("accessors").get(index).ok_or_else(|| format!("accessor {} doesn't exist", index))?;
+        if accessor.get("sparse").is_some() {
+            return Err(format!("accessor {} is sparse, which isn't supported", index));
+        }
+        let count = accessor.get("count").and_then(Json::as_usize).ok_or_else(|| format!("accessor {} has no count", index))?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(format!("accessor {} has no valid type", index)),
+        };
+        let component_type = accessor.get("componentType").and_then(Json::as_usize);
+        let size = match component_type {
+            Some(5120 | 5121) => 1,
+            Some(5122 | 5123) => 2,
+            Some(5125 | 5126) => 4,
+            _ => return Err(format!("accessor {} has no valid component type", index)),
+        };
+
+        // Without a buffer view every number is zero
+        let Some(view) = accessor.get("bufferView") else {
+            return Ok((vec![0.0; count * components], components));
+        };
+        let view = view.as_usize().and_then(|view| self.array("bufferViews").get(view));
+        let view = view.ok_or_else(|| format!("accessor {} refers to a buffer view that doesn't exist", index))?;
+        let buffer = view.get("buffer").and_then(Json::as_usize).and_then(|buffer| self.buffers.get(buffer));
+        let buffer = buffer.ok_or_else(|| format!("accessor {} refers to a buffer that doesn't exist", index))?;
+        let view_offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let view_length = view.get("byteLength").and_then(Json::as_usize).unwrap_or(0);
+        let stride = view.get("byteStride").and_then(Json::as_usize).filter(|&stride| stride > 0).unwrap_or(size * components);
+        let view = buffer.get(view_offset..view_offset + view_length);
+        let view = view.ok_or_else(|| format!("accessor {} has a buffer view past the end of its buffer", index))?;
+        let offset = accessor.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let start = offset + element * stride + component * size;
+                let bytes = view
+                    .get(start..start + size)
+                    .ok_or_else(|| format!("accessor {} reaches past the end of its buffer view", index))?;
+                values.push(match component_type {
+                    Some(5120) => bytes[0] as i8 as f64,
+                    Some(5121) => bytes[0] as f64,
+                    Some(5122) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    Some(5123) => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    Some(5125) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                });
+            }
+        }
+        Ok((values, components))
+    }
+}
+
+// A list of indices like a node's children, empty when it's left out
+fn indices(list: Option<&Json>, what: &str) -> Result<Vec<usize>, String> {
+    list.map_or(&[][..], Json::items)
+        .iter()
+        .map(|index| index.as_usize().ok_or_else(|| format!("{} aren't all indices", what)))
+        .collect()
+}
+
+// From a node's space to its parent's: its matrix, or its translation, rotation and scale
+// applied scale first
+fn local_transform(node: &Json) -> Result<Mat4, String> {
+    let numbers = |key: &str, count: usize| -> Result<Option<Vec<f32>>, String> {
+        let Some(value) = node.get(key) else {
+            return Ok(None);
+        };
+        let numbers: Option<Vec<f32>> = value.items().iter().map(|n| n.as_f64().map(|n| n as f32)).collect();
+        match numbers {
+            Some(numbers) if numbers.len() == count => Ok(Some(numbers)),
+            _ => Err(format!("a node's {} isn't {} numbers", key, count)),
+        }
+    };
+    if let Some(matrix) = numbers("matrix", 16)? {
+        // Column by column, as glTF writes it
+        return Ok(Mat4::from_column_slice(&matrix));
+    }
+    let translation = numbers("translation", 3)?.map_or(Vec3::zeros(), |t| Vec3::new(t[0], t[1], t[2]));
+    let rotation = numbers("rotation", 4)?.map_or(glm::quat_identity(), |r| glm::quat(r[0], r[1], r[2], r[3]));
+    let scale = numbers("scale", 3)?.map_or(Vec3::repeat(1.0), |s| Vec3::new(s[0], s[1], s[2]));
+    Ok(glm::translation(&translation) * glm::quat_to_mat4(&glm::quat_normalize(&rotation)) * glm::scaling(&scale))
+}
+
+// The base color factor, which is linear and white when left out, and whether the back of
+// the surface is seen too, which glTF leaves off unless it's set
+fn material(json: &Json) -> Material {
+    let factor = json.get("pbrMetallicRoughness").and_then(|pbr| pbr.get("baseColorFactor"));
+    let factor: Option<Vec<f32>> = factor.map(|factor| factor.items().iter().filter_map(|c| c.as_f64().map(|c| c as f32)).collect());
+    let color = match factor.as_deref() {
+        Some([r, g, b, ..]) => Vec3::new(*r, *g, *b),
+        _ => Vec3::repeat(1.0),
+    };
+    Material {
+        diffuse: Color::from_linear(color),
+        double_sided: json.get("doubleSided").and_then(Json::as_bool).unwrap_or(false),
+        ..Material::default()
+    }
+}
+
+// A buffer's bytes, from a base64 data URI or a file. Buffers without a URI only come with
+// the binary .glb kind of file.
+fn load_buffer(buffer: &Json, directory: &Path) -> Result<Vec<u8>, String> {
+    let uri = buffer.get("uri").and_then(Json::as_str).ok_or("a buffer has no URI, only .gltf files with their buffers apart are supported")?;
+    let bytes = match uri.strip_prefix("data:") {
+        Some(data) => {
+            let (_, encoded) = data.split_once(";base64,").ok_or("a buffer's data URI isn't base64")?;
+            decode_base64(encoded).ok_or("a buffer's data URI isn't valid base64")?
+        }
+        None => {
+            let file = directory.join(uri);
+            fs::read(&file).map_err(|err| format!("could not open buffer '{}': {}", file.display(), err))?
+        }
+    };
+    let length = buffer.get("byteLength").and_then(Json::as_usize).unwrap_or(0);
+    if bytes.len() < length {
+        return Err(format!("buffer '{}' is shorter than its byteLength", if uri.starts_with("data:") { "data URI" } else { uri }));
+    }
+    Ok(bytes)
+}
+
+// Standard base64, also taking the URL-safe alphabet, with or without padding
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// A JSON value, as much of JSON as glTF files need. Objects keep their members in order.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(source: &str) -> Result<Json, String> {
+        let mut parser = Parser { bytes: source.as_bytes(), position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position < parser.bytes.len() {
+            return Err(parser.error("text after the end of the JSON"));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // An array's items, none for anything else
+    fn items(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    // A whole number that's not negative, like the indices glTF refers to things by
+    fn as_usize(&self) -> Option<usize> {
+        self.as_f64().filter(|n| *n >= 0.0 && n.fract() == 0.0).map(|n| n as usize)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position) {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of the JSON")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.position) != Some(&b'"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat(b']') {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(b']') {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.position) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.bytes.get(self.position).copied();
+                    self.position += 1;
+                    let escaped = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // A character outside the basic plane is written as two halves
+                            if (0xD800..0xDC00).contains(&code) && self.bytes[self.position..].starts_with(b"\\u") {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(self.error("invalid escape in a string")),
+                    };
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        // The source was a string, and escapes add whole characters, so this is still UTF-8
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in a string"))
+    }
+
+    // Four hex digits after `\u`
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.position..self.position + 4).ok_or_else(|| self.error("unterminated escape"))?;
+        let digits = std::str::from_utf8(digits).ok().and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        let code = digits.ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.position += 4;
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while matches!(self.bytes.get(self.position), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or("");
+        text.parse().map(Json::Number).map_err(|_| self.error("invalid number"))
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.bytes[self.position..].starts_with(word.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.bytes.get(self.position) == Some(&byte);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.bytes.get(self.position), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn error(&self, reason: &str) -> String {
+        format!("{} at byte {}", reason, self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{Mesh, ShadingMode};
+    use crate::ray_intersect::{Ray, RayIntersect};
+
+    // One red triangle with corners (0, 0, 0), (1, 0, 0) and (0, 1, 0), doubled in size and
+    // moved 1 along x by its node, whose parent moves it 5 away from the camera
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "translation": [0, 0, -5], "children": [1] },
+            { "translation": [1, 0, 0], "scale": [2, 2, 2], "mesh": 0 }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0, 0, 1] } }],
+        "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+        }]
+    }"#;
+
+    fn triangle() -> Mesh {
+        Mesh {
+            model: parse(TRIANGLE, "triangle.gltf", Path::new("")).unwrap(),
+            shading: ShadingMode::Flat,
+            material: Material::default(),
+        }
+    }
+
+    fn down_z(x: f32, y: f32) -> Ray {
+        Ray::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn node_transforms_place_the_triangle_in_the_world() {
+        let mesh = triangle();
+        let bounds = mesh.bounding_box();
+        assert!((bounds.min - Vec3::new(1.0, 0.0, -5.0)).magnitude() < 1e-3, "{:?}", bounds);
+        assert!((bounds.max - Vec3::new(3.0, 2.0, -5.0)).magnitude() < 1e-3, "{:?}", bounds);
+
+        let hit = mesh.ray_intersect(&down_z(2.5, 0.5), 0.0, f32::INFINITY);
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert!((hit.point - Vec3::new(2.5, 0.5, -5.0)).magnitude() < 1e-5);
+        assert!((hit.normal - Vec3::z()).magnitude() < 1e-5);
+        assert_eq!(hit.material.diffuse, Color::from_linear(Vec3::new(1.0, 0.0, 0.0)));
+        assert!(!hit.material.double_sided);
+
+        // Where the triangle was before its nodes moved it, and past its long edge, there's nothing
+        assert!(!mesh.ray_intersect(&down_z(0.5, 0.5), 0.0, f32::INFINITY).is_intersecting);
+        assert!(!mesh.ray_intersect(&down_z(2.5, 1.6), 0.0, f32::INFINITY).is_intersecting);
+        // Its back isn't seen, since the material isn't double sided
+        let from_behind = Ray::new(Vec3::new(2.5, 0.5, -10.0), Vec3::z());
+        assert!(!mesh.ray_intersect(&from_behind, 0.0, f32::INFINITY).is_intersecting);
+    }
+
+    #[test]
+    fn broken_files_say_what_is_wrong() {
+        let missing_mesh = TRIANGLE.replace(r#""mesh": 0"#, r#""mesh": 3"#);
+        let error = parse(&missing_mesh, "triangle.gltf", Path::new("")).unwrap_err();
+        assert!(error.contains("triangle.gltf") && error.contains("mesh 3 doesn't exist"), "{}", error);
+
+        let cycle = TRIANGLE.replace(r#""mesh": 0 }"#, r#""mesh": 0, "children": [0] }"#);
+        let error = parse(&cycle, "triangle.gltf", Path::new("")).unwrap_err();
+        assert!(error.contains("cycle"), "{}", error);
+    }
+}
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::ray_intersect::{Intersect, Material, Ray, RayIntersect};
+use crate::scene::{bounds_of, nearest_intersect, Object};
+use crate::transform::Transform;
+
+// A named group of objects that scenes set down any number of times with `Instance`. The
+// objects are stored once and shared by every instance.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prototype {
+    pub name: String,
+    pub objects: Arc<Vec<Object>>,
+}
+
+// A copy of a prototype placed with a transform, optionally with every object of it
+// painted in one material. Scene files name the prototype, which is looked up once the
+// whole scene has been read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Instance {
+    pub prototype: String,
+    #[serde(default)]
+    pub transform: Transform,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<Material>,
+    // The prototype's objects, empty until resolved
+    #[serde(skip)]
+    pub objects: Arc<Vec<Object>>,
+}
+
+impl Instance {
+    pub fn new(prototype: &Prototype, transform: Transform, material: Option<Material>) -> Self {
+        Instance {
+            prototype: prototype.name.clone(),
+            transform,
+            material,
+            objects: Arc::clone(&prototype.objects),
+        }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.transform.bounds_to_world(&bounds_of(&self.objects))
+    }
+}
+
+impl RayIntersect for Instance {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let mut intersect = self
+            .transform
+            .intersect(ray, |local_ray| nearest_intersect(&self.objects, local_ray, t_min, t_max));
+        if let (true, Some(material)) = (intersect.is_intersecting, &self.material) {
+            intersect.material = material.clone();
+        }
+        intersect
+    }
+}
+
+// Points every instance among `objects`, including ones inside transforms and groups, at
+// the prototype it names. Returns the first name without a prototype.
+pub fn resolve_instances(objects: &mut [Object], prototypes: &[Prototype]) -> Result<(), String> {
+    for object in objects {
+        match object {
+            Object::Instance(instance) => {
+                let prototype = prototypes
+                    .iter()
+                    .find(|prototype| prototype.name == instance.prototype)
+                    .ok_or_else(|| instance.prototype.clone())?;
+                instance.objects = Arc::clone(&prototype.objects);
+            }
+            Object::Transformed { object, .. } => resolve_instances(std::slice::from_mut(object.as_mut()), prototypes)?,
+            Object::Group { objects, .. } => resolve_instances(objects, prototypes)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::sphere::Sphere;
+    use nalgebra_glm::Vec3;
+
+    // A unit sphere at the origin, set down at x = -3 as it is and at x = 3 painted red
+    fn two_instances() -> (Prototype, Instance, Instance) {
+        let sphere = Sphere { center: Vec3::zeros(), radius: 1.0, material: Material::default(), velocity: Vec3::zeros() };
+        let prototype = Prototype { name: "ball".to_string(), objects: Arc::new(vec![Object::Sphere(sphere)]) };
+        let at = |x| Transform::from_parts(Vec3::new(x, 0.0, 0.0), Vec3::zeros(), Vec3::repeat(1.0)).unwrap();
+        let red = Material { diffuse: Color::new(255, 0, 0), ..Material::default() };
+        let left = Instance::new(&prototype, at(-3.0), None);
+        let right = Instance::new(&prototype, at(3.0), Some(red));
+        (prototype, left, right)
+    }
+
+    fn down_z(x: f32) -> Ray {
+        Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn two_instances_are_hit_where_they_were_placed() {
+        let (prototype, left, right) = two_instances();
+        assert!(Arc::ptr_eq(&left.objects, &prototype.objects) && Arc::ptr_eq(&right.objects, &prototype.objects));
+
+        for (instance, x) in [(&left, -3.0), (&right, 3.0)] {
+            let hit = instance.ray_intersect(&down_z(x), 0.0, f32::INFINITY);
+            assert!(hit.is_intersecting, "instance at x = {}", x);
+            assert!((hit.distance - 4.0).abs() < 1e-5, "{}", hit.distance);
+            assert!((hit.point - Vec3::new(x, 0.0, 1.0)).magnitude() < 1e-5);
+            assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+            // Each misses where the other one is, and neither is where the prototype was made
+            assert!(!instance.ray_intersect(&down_z(-x), 0.0, f32::INFINITY).is_intersecting);
+            assert!(!instance.ray_intersect(&down_z(0.0), 0.0, f32::INFINITY).is_intersecting);
+        }
+    }
+
+    #[test]
+    fn material_override_applies_to_its_instance_only() {
+        let (_, left, right) = two_instances();
+        let left_hit = left.ray_intersect(&down_z(-3.0), 0.0, f32::INFINITY);
+        let right_hit = right.ray_intersect(&down_z(3.0), 0.0, f32::INFINITY);
+        assert_eq!(left_hit.material, Material::default());
+        assert_eq!(right_hit.material.diffuse, Color::new(255, 0, 0));
+    }
+}
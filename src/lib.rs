@@ -0,0 +1,39 @@
+// The renderer without a window: scenes, the camera, tracing and the framebuffer it all
+// ends up in. The native binary shows it in a minifb window and `web` in a browser canvas.
+// Nothing in here opens a window, starts a thread or sleeps, so it builds for wasm32 too.
+pub mod framebuffer;
+pub mod ray_intersect;
+pub mod sphere;
+pub mod color;
+pub mod camera;
+pub mod disk;
+pub mod torus;
+pub mod csg;
+pub mod cube;
+pub mod transform;
+pub mod render;
+pub mod scene;
+pub mod light;
+pub mod tonemap;
+pub mod channel;
+pub mod turntable;
+pub mod texture;
+pub mod background;
+pub mod font;
+pub mod rng;
+pub mod noise;
+pub mod occlusion;
+pub mod emitter;
+pub mod denoise;
+pub mod fog;
+pub mod aabb;
+pub mod mesh;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+pub mod instance;
+pub mod animation;
+pub mod shadow;
+pub mod blob;
+pub mod sdf;
+pub mod progressive;
+pub mod web;
@@ -0,0 +1,355 @@
+use nalgebra_glm::Vec3;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+use crate::color::Color;
+
+// Scene files tell the kinds apart by their fields: a light with a `position` is a point
+// light, one with a `direction` shines from infinitely far away like the sun, and one with
+// both is a spot light
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "LightDef")]
+pub enum Light {
+    Point(PointLight),
+    Directional(DirectionalLight),
+    Spot(SpotLight),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PointLight {
+    pub position: Vec3,
+    // Size of a spherical light, for soft shadows. Zero is a point light with hard shadows.
+    pub radius: f32,
+    pub color: Color,
+    pub intensity: f32,
+    pub falloff: Falloff,
+    // Distances below this count as this, so surfaces right next to the light don't blow out
+    pub min_distance: f32,
+}
+
+// Light arriving along the same direction everywhere, which casts parallel shadows and
+// doesn't dim with distance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DirectionalLight {
+    pub direction: Vec3, // The way the light travels, e.g. (0, -1, 0) shines straight down
+    pub color: Color,
+    pub intensity: f32,
+}
+
+// A point light that only shines into a cone around `direction`: fully within
+// `inner_angle` of it, fading out smoothly to nothing at `outer_angle`. Angles are in
+// degrees from the axis, so the cone is twice as wide as them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub radius: f32,
+    pub color: Color,
+    pub intensity: f32,
+    pub falloff: Falloff,
+    pub min_distance: f32,
+}
+
+// How a light dims with distance. Physically it's the inverse square, which needs a high
+// intensity to light anything a few units away; the other two are easier to set up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Falloff {
+    #[default]
+    None,
+    Linear,
+    InverseSquare,
+}
+
+impl Light {
+    // A white point light of intensity 1 that doesn't dim with distance
+    pub fn point(position: Vec3) -> Self {
+        Light::Point(PointLight {
+            position,
+            radius: 0.0,
+            color: Color::white(),
+            intensity: default_intensity(),
+            falloff: Falloff::None,
+            min_distance: default_min_distance(),
+        })
+    }
+
+    // The unit vector from a point towards the light
+    pub fn direction_from(&self, point: &Vec3) -> Vec3 {
+        match self {
+            Light::Point(light) => (light.position - point).normalize(),
+            Light::Directional(light) => -light.direction.normalize(),
+            Light::Spot(light) => (light.position - point).normalize(),
+        }
+    }
+
+    // Where the light is, `None` for a directional light, which has no position
+    pub fn position(&self) -> Option<Vec3> {
+        match self {
+            Light::Point(light) => Some(light.position),
+            Light::Directional(_) => None,
+            Light::Spot(light) => Some(light.position),
+        }
+    }
+
+    // The position to move the light to, `None` for a directional light
+    pub fn position_mut(&mut self) -> Option<&mut Vec3> {
+        match self {
+            Light::Point(light) => Some(&mut light.position),
+            Light::Directional(_) => None,
+            Light::Spot(light) => Some(&mut light.position),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Light::Point(light) => light.color,
+            Light::Directional(light) => light.color,
+            Light::Spot(light) => light.color,
+        }
+    }
+
+    // Factor the light's color is scaled by when it reaches a point, ignoring shadows
+    pub fn attenuation(&self, point: &Vec3) -> f32 {
+        match self {
+            Light::Point(light) => {
+                let distance = (light.position - point).magnitude();
+                light.intensity * light.falloff.factor(distance, light.min_distance)
+            }
+            Light::Directional(light) => light.intensity,
+            Light::Spot(light) => {
+                let offset = point - light.position;
+                let distance = offset.magnitude();
+                let cone = light.cone_factor(&(offset / distance));
+                light.intensity * light.falloff.factor(distance, light.min_distance) * cone
+            }
+        }
+    }
+}
+
+impl Falloff {
+    // Distances below `min_distance` count as `min_distance`
+    fn factor(self, distance: f32, min_distance: f32) -> f32 {
+        let distance = distance.max(min_distance).max(1e-6);
+        match self {
+            Falloff::None => 1.0,
+            Falloff::Linear => 1.0 / distance,
+            Falloff::InverseSquare => 1.0 / (distance * distance),
+        }
+    }
+}
+
+impl SpotLight {
+    // 1 inside the inner cone, 0 outside the outer one and a smoothstep in between, for a
+    // unit vector pointing away from the light
+    fn cone_factor(&self, direction: &Vec3) -> f32 {
+        let outer = self.outer_angle.clamp(0.0, 180.0);
+        let inner = self.inner_angle.clamp(0.0, outer);
+        let cos_outer = outer.to_radians().cos();
+        let cos_inner = inner.to_radians().cos();
+
+        let cos_angle = direction.dot(&self.direction.normalize());
+        // A point right at the light, or a zero axis, has no angle at all
+        if cos_angle.is_nan() || cos_angle <= cos_outer {
+            return 0.0;
+        }
+        if cos_angle >= cos_inner {
+            return 1.0;
+        }
+        // Only reached when cos_inner > cos_angle > cos_outer, so this can't divide by zero
+        let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_min_distance() -> f32 {
+    0.1
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightError {
+    NoPlacement, // Neither a position nor a direction
+    // A setting the kind of light doesn't have, e.g. a radius on a directional light
+    NotApplicable { field: &'static str, kind: &'static str },
+}
+
+impl fmt::Display for LightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LightError::NoPlacement => write!(f, "a light needs a `position`, a `direction` or both"),
+            LightError::NotApplicable { field, kind } => write!(f, "`{}` doesn't apply to {} lights", field, kind),
+        }
+    }
+}
+
+impl std::error::Error for LightError {}
+
+// Every field either kind of light may be written with, so unknown fields are still
+// reported by name
+#[derive(Deserialize)]
+#[serde(rename = "Light", deny_unknown_fields)]
+struct LightDef {
+    #[serde(default, deserialize_with = "present")]
+    position: Option<Vec3>,
+    #[serde(default, deserialize_with = "present")]
+    direction: Option<Vec3>,
+    #[serde(default, deserialize_with = "present")]
+    radius: Option<f32>,
+    #[serde(default = "Color::white")]
+    color: Color,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    #[serde(default, deserialize_with = "present")]
+    falloff: Option<Falloff>,
+    #[serde(default, deserialize_with = "present")]
+    min_distance: Option<f32>,
+    #[serde(default, deserialize_with = "present")]
+    inner_angle: Option<f32>,
+    #[serde(default, deserialize_with = "present")]
+    outer_angle: Option<f32>,
+}
+
+// An error when a field was written that `kind` lights don't have
+fn reject<T>(field: &'static str, value: &Option<T>, kind: &'static str) -> Result<(), LightError> {
+    match value {
+        Some(_) => Err(LightError::NotApplicable { field, kind }),
+        None => Ok(()),
+    }
+}
+
+// Lets optional fields be written without `Some(...)`
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+impl TryFrom<LightDef> for Light {
+    type Error = LightError;
+
+    fn try_from(def: LightDef) -> Result<Self, Self::Error> {
+        match (def.position, def.direction) {
+            (Some(position), None) => {
+                reject("inner_angle", &def.inner_angle, "point")?;
+                reject("outer_angle", &def.outer_angle, "point")?;
+                Ok(Light::Point(PointLight {
+                    position,
+                    radius: def.radius.unwrap_or(0.0),
+                    color: def.color,
+                    intensity: def.intensity,
+                    falloff: def.falloff.unwrap_or_default(),
+                    min_distance: def.min_distance.unwrap_or_else(default_min_distance),
+                }))
+            }
+            (None, Some(direction)) => {
+                reject("radius", &def.radius, "directional")?;
+                reject("min_distance", &def.min_distance, "directional")?;
+                reject("inner_angle", &def.inner_angle, "directional")?;
+                reject("outer_angle", &def.outer_angle, "directional")?;
+                reject("falloff", &def.falloff, "directional")?;
+                Ok(Light::Directional(DirectionalLight {
+                    direction,
+                    color: def.color,
+                    intensity: def.intensity,
+                }))
+            }
+            (Some(position), Some(direction)) => {
+                // A cone 60° across with the outer fifth fading out, unless set
+                let outer_angle = def.outer_angle.unwrap_or(30.0);
+                Ok(Light::Spot(SpotLight {
+                    position,
+                    direction,
+                    inner_angle: def.inner_angle.unwrap_or(outer_angle * 0.8),
+                    outer_angle,
+                    radius: def.radius.unwrap_or(0.0),
+                    color: def.color,
+                    intensity: def.intensity,
+                    falloff: def.falloff.unwrap_or_default(),
+                    min_distance: def.min_distance.unwrap_or_else(default_min_distance),
+                }))
+            }
+            (None, None) => Err(LightError::NoPlacement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A spot light at the origin shining down -z
+    fn spot(inner_angle: f32, outer_angle: f32) -> SpotLight {
+        SpotLight {
+            position: Vec3::zeros(),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+            inner_angle,
+            outer_angle,
+            radius: 0.0,
+            color: Color::white(),
+            intensity: 1.0,
+            falloff: Falloff::None,
+            min_distance: default_min_distance(),
+        }
+    }
+
+    // The unit vector `degrees` off the spot light's axis
+    fn off_axis(degrees: f32) -> Vec3 {
+        let angle = degrees.to_radians();
+        Vec3::new(angle.sin(), 0.0, -angle.cos())
+    }
+
+    #[test]
+    fn cone_is_full_on_its_axis_and_dark_from_its_outer_angle() {
+        let light = spot(20.0, 30.0);
+        assert_eq!(light.cone_factor(&Vec3::new(0.0, 0.0, -1.0)), 1.0);
+        assert_eq!(light.cone_factor(&off_axis(19.0)), 1.0);
+        let edge = light.cone_factor(&off_axis(25.0));
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+        assert_eq!(light.cone_factor(&off_axis(30.0)), 0.0);
+        assert_eq!(light.cone_factor(&off_axis(45.0)), 0.0);
+        assert_eq!(light.cone_factor(&Vec3::z()), 0.0);
+    }
+
+    #[test]
+    fn wide_cones_stay_between_zero_and_one() {
+        for outer_angle in [90.0, 135.0, 180.0] {
+            let light = spot(outer_angle * 0.5, outer_angle);
+            assert_eq!(light.cone_factor(&Vec3::new(0.0, 0.0, -1.0)), 1.0, "{}°", outer_angle);
+            for degrees in (0..=180).step_by(5) {
+                let factor = light.cone_factor(&off_axis(degrees as f32));
+                assert!(factor.is_finite() && (0.0..=1.0).contains(&factor), "{}° at {}°: {}", outer_angle, degrees, factor);
+            }
+            // Even a cone as wide as can be is dark straight behind the light
+            assert_eq!(light.cone_factor(&Vec3::z()), 0.0, "{}°", outer_angle);
+        }
+        // Inner and outer angles the same are a hard edge
+        let hard = spot(90.0, 90.0);
+        assert_eq!(hard.cone_factor(&off_axis(89.0)), 1.0);
+        assert_eq!(hard.cone_factor(&off_axis(91.0)), 0.0);
+    }
+
+    #[test]
+    fn spot_light_gives_nothing_at_its_own_position() {
+        let light = Light::Spot(spot(20.0, 30.0));
+        assert_eq!(light.attenuation(&Vec3::zeros()), 0.0);
+        assert_eq!(light.attenuation(&Vec3::new(0.0, 0.0, -2.0)), 1.0);
+    }
+
+    #[test]
+    fn fields_of_other_kinds_of_light_are_rejected() {
+        let error = |text: &str| ron::from_str::<Light>(text).unwrap_err().to_string();
+        let directional = error("(direction: (0.0, -1.0, 0.0), radius: 0.5)");
+        assert!(directional.contains(&LightError::NotApplicable { field: "radius", kind: "directional" }.to_string()), "{}", directional);
+        let point = error("(position: (0.0, 4.0, 0.0), outer_angle: 30.0)");
+        assert!(point.contains(&LightError::NotApplicable { field: "outer_angle", kind: "point" }.to_string()), "{}", point);
+        assert!(error("(intensity: 2.0)").contains(&LightError::NoPlacement.to_string()));
+
+        let Light::Spot(light) = ron::from_str("(position: (0.0, 4.0, 0.0), direction: (0.0, -1.0, 0.0), outer_angle: 40.0)").unwrap() else {
+            panic!("not a spot light");
+        };
+        assert_eq!((light.inner_angle, light.outer_angle), (32.0, 40.0));
+    }
+}
@@ -1,194 +1,871 @@
-use nalgebra_glm::{Vec3, normalize};
-use minifb::{Key, Window, WindowOptions};
-use std::time::Duration;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::f32::consts::PI;
 
-mod framebuffer;
-mod ray_intersect;
-mod sphere;
-mod color;
-mod camera;
+mod cli;
+mod fps;
+mod worker;
+mod terminal;
 
-use framebuffer::Framebuffer;
-use sphere::Sphere;
-use color::Color;
-use ray_intersect::{Intersect, RayIntersect, Material};
-use camera::Camera;
+use raytracing_animal::framebuffer::Framebuffer;
+use cli::ArgsError;
+use raytracing_animal::render::{draw_bounding_boxes, draw_box, draw_lights, focus_distance_at, pick_at, render_guides, render_with_progress, retonemap, Integrator, RenderMode, RenderSettings, RenderStats};
+use raytracing_animal::channel::Channel;
+use raytracing_animal::progressive::{preview_level, PreviewLevel};
+use raytracing_animal::scene::{Object, Pick, Scene, SceneWatcher};
+use raytracing_animal::animation::Animation;
+use raytracing_animal::camera::Camera;
+use fps::FpsCounter;
+use worker::{RenderJob, RenderWorker};
+use raytracing_animal::fog::Fog;
+use raytracing_animal::color::Color;
+use raytracing_animal::{scene, turntable};
+use nalgebra_glm as glm;
 
-pub fn cast_ray(ray_origin: &Vec3, ray_direction: &Vec3, objects: &[Sphere]) -> Color {
-    let mut intersect = Intersect::empty();
-    let mut zbuffer = f32::INFINITY;
+fn main() {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(ArgsError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let window_width = options.width;
+    let window_height = options.height;
+    let framebuffer_width = options.width;
+    let framebuffer_height = options.height;
+    let frame_delay = Duration::from_millis(16);
+
+    let mut settings = RenderSettings {
+        integrator: match options.path_samples {
+            Some(samples) => Integrator::PathTraced { samples },
+            None => Integrator::Whitted,
+        },
+        mode: if options.clay { RenderMode::Clay } else { RenderMode::Materials },
+        channel: options.channel,
+        depth_range: options.depth_range,
+        fov: options.fov.to_radians(),
+        samples: options.samples,
+        adaptive_threshold: options.adaptive_threshold,
+        sample_clamp: options.sample_clamp,
+        shadow_samples: options.shadow_samples,
+        max_reflection_depth: options.reflection_depth,
+        light_samples: options.light_samples,
+        exposure: options.exposure,
+        tone_map: options.tone_map,
+        seed: options.seed,
+        shutter: options.shutter,
+        region: options.region,
+        ..RenderSettings::default()
+    };
 
-    for object in objects {
-        let tmp = object.ray_intersect(ray_origin, ray_direction);
-        if tmp.is_intersecting && tmp.distance < zbuffer {
-            zbuffer = tmp.distance;
-            intersect = tmp;
+    let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
+
+    let scene = match &options.scene {
+        Some(path) => Scene::load(path),
+        None if options.cornell_box => Ok(Scene::cornell_box()),
+        None => Scene::parse(scene::DEFAULT_SCENE, "scenes/teddy.ron"),
+    };
+    let mut scene = match scene {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
         }
-    }
+    };
 
-    if !intersect.is_intersecting {
-        return Color::new(120, 180, 130); // Green background
+    // Renders to files see the animation at a given moment, which it can jump straight to
+    // since every frame places things anew from where the scene file has them
+    let mut animation = Animation::new(&mut scene, options.seed);
+    if let (true, Some(time)) = (options.headless || options.turntable.is_some(), options.time) {
+        animation.update(&mut scene, time, settings.shutter);
     }
-    
-    let diffuse = intersect.material.diffuse;
 
-    diffuse
-}
-
-pub fn render(framebuffer: &mut Framebuffer, objects: &[Sphere], camera: &Camera) {
-    let width = framebuffer.width as f32;
-    let height = framebuffer.height as f32;
-    let aspect_ratio = width / height;
-    let fov = PI/3.0;
-    let perspective_scale = (fov * 0.5).tan();
+    if options.frame {
+        let aspect_ratio = framebuffer_width as f32 / framebuffer_height as f32;
+        let mut camera = scene.camera;
+        camera.frame_scene(&scene, settings.fov, aspect_ratio);
+        scene.camera = camera;
+    }
 
-    for y in 0..framebuffer.height {
-        for x in 0..framebuffer.width {
-            let screen_x = (2.0 * x as f32) / width - 1.0;
-            let screen_y = -(2.0 * y as f32) / height + 1.0;
+    // The scene only provides the starting viewpoint, the window orbits its own copy
+    let mut camera = scene.camera;
 
-            let screen_x = screen_x * aspect_ratio * perspective_scale;
-            let screen_y = screen_y * perspective_scale;
+    // Saving captures the interactive camera as the scene's new default viewpoint. What the
+    // animation moves is saved where the scene file had it, not where it was at the time.
+    let save_path = options.save_scene.clone()
+        .or_else(|| options.scene.clone())
+        .unwrap_or_else(|| "scene.ron".to_string());
+    let save_scene = |scene: &Scene, animation: &Animation, camera: &Camera| {
+        let mut saved = animation.at_rest(scene);
+        saved.camera = *camera;
+        match saved.save(&save_path) {
+            Ok(()) => println!("Saved scene to {}", save_path),
+            Err(err) => eprintln!("error: {}", err),
+        }
+    };
 
-            let ray_direction = normalize(&Vec3::new(screen_x, screen_y, -1.0));
-            let rotated_direction = camera.basis_change(&ray_direction);
+    if let Some(frames) = options.turntable {
+        let out_dir = options.output.as_deref().unwrap_or("turntable");
+        if let Err(err) = turntable::render_turntable(&mut framebuffer, &scene, &settings, frames, out_dir) {
+            eprintln!("error: could not write turntable to '{}': {}", out_dir, err);
+            std::process::exit(1);
+        }
+        println!("Saved {} frames to {}", frames, out_dir);
+        return;
+    }
 
-            let pixel_color = cast_ray(&camera.eye, &rotated_direction, objects);
+    if options.terminal {
+        let (width, height) = terminal::terminal_pixels();
+        framebuffer.resize(width, height);
+        if let Err(err) = terminal::run(&mut framebuffer, &scene, &camera, &settings) {
+            eprintln!("error: could not draw to the terminal: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-            framebuffer.set_current_color(pixel_color.to_hex());
-            framebuffer.point(x, y);
+    if options.headless {
+        // Render a single frame straight to disk, never touching the windowing system
+        let output = options.output.as_deref().unwrap_or("render.png");
+        match render_to_file(&mut framebuffer, &scene, &camera, &settings, &options, output) {
+            Ok(stats) => {
+                eprintln!("Rendered in {}", stats);
+                println!("Saved frame to {}", output);
+            }
+            Err(err) => {
+                eprintln!("error: could not write '{}': {}", output, err);
+                std::process::exit(1);
+            }
         }
+        if let Some(factor) = options.thumbnail {
+            save_frame(&framebuffer.downsample(factor), &thumbnail_path(output));
+        }
+        if options.save_scene.is_some() {
+            save_scene(&scene, &animation, &camera);
+        }
+        return;
     }
-}
 
-fn main() {
-    let window_width = 800;
-    let window_height = 600;
-    let framebuffer_width = 800;
-    let framebuffer_height = 600;
-    let frame_delay = Duration::from_millis(16);
+    // Shared with the render worker, which keeps its own reference for the frame in flight
+    let mut scene = Arc::new(scene);
 
-    let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
+    let title = "Rust Graphics - Osito Teddy";
     let mut window = Window::new(
-        "Rust Graphics - Osito Teddy",
+        title,
         window_width,
         window_height,
-        WindowOptions::default(),
-    ).unwrap();
+        WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        },
+    ).unwrap_or_else(|err| {
+        // Typically there's no display to open it on
+        eprintln!("error: could not open a window: {}\nUse --headless to render to a file instead", err);
+        std::process::exit(1);
+    });
 
     window.set_position(500, 500);
     window.update();
 
-    // Define materials
-    let fur = Material {
-        diffuse: Color::new(139, 69, 19), // Brown color for fur
-    };
-    let eye = Material {
-        diffuse: Color::new(0, 0, 0), // Black color for eyes
-    };
-    let nose = Material {
-        diffuse: Color::new(0, 0, 0), // Black color for nose
-    };
-    let inner_ear = Material {
-        diffuse: Color::new(255, 255, 255), // White color for inner ear
-    };
-    let mouth = Material {
-        diffuse: Color::new(255, 255, 255), // White color for inner ear
-    };
-
-    // Define spheres for the bear face
-    let objects = [
-        // Head
-        Sphere {
-            center: Vec3::new(0.0, 0.0, -5.0),
-            radius: 1.0,
-            material: fur,
-        },
-        // Left Ear
-        Sphere {
-            center: Vec3::new(-0.75, 0.75, -5.0),
-            radius: 0.5,
-            material: fur,
-        },
-        Sphere {
-            center: Vec3::new(-0.75, 0.75, -4.75),
-            radius: 0.3,
-            material: inner_ear,
-        },
-        // Right Ear
-        Sphere {
-            center: Vec3::new(0.75, 0.75, -5.0),
-            radius: 0.5,
-            material: fur,
-        },
-        Sphere {
-            center: Vec3::new(0.75, 0.75, -4.75),
-            radius: 0.3,
-            material: inner_ear,
-        },
-        // Left Eye
-        Sphere {
-            center: Vec3::new(-0.45, 0.1, -4.2), 
-            radius: 0.15, 
-            material: eye,
-        },
-        // Right Eye
-        Sphere {
-            center: Vec3::new(0.45, 0.1, -4.2), 
-            radius: 0.15, 
-            material: eye,
-        },
-        // Nose
-        Sphere {
-            center: Vec3::new(0.0, -0.3, -4.2), 
-            radius: 0.25, 
-            material: nose,
-        },
-        // Mouth
-        Sphere {
-            center: Vec3::new(0.0, -0.4, -4.5), 
-            radius: 0.5, 
-            material: mouth,
-        },
+    // In radians per second, scaled by each frame's duration so the camera turns just as
+    // fast whatever the frame rate. These match the fixed steps per frame it used to take at
+    // around 60 frames per second.
+    let rotation_speed = PI * 6.0;
+    let roll_speed = PI * 1.5;
+    let viewpoint_keys = [
+        Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5,
+        Key::Key6, Key::Key7, Key::Key8, Key::Key9,
     ];
+    let transition_duration = Duration::from_millis(500);
+    // Start camera, target camera and start time of an ongoing viewpoint switch
+    let mut transition: Option<(Camera, Camera, Instant)> = None;
+    // Tab turns the camera to the scene's next point of interest, moving where it looks at
+    // from the old point to the new one over this long
+    let retarget_duration = Duration::from_millis(300);
+    let mut point_of_interest = 0;
+    // Start and target points and start time of an ongoing turn
+    let mut retarget: Option<(glm::Vec3, glm::Vec3, Instant)> = None;
+    // Counts rendered frames, timed between one finishing and the next, rather than
+    // iterations of the event loop, which go on at the same pace whatever is rendering
+    let mut fps_counter = FpsCounter::new(0.1);
+    let mut last_finished = Instant::now();
+    let mut last_frame = Instant::now();
+    let mut stats = RenderStats::default();
+    // Stats are printed at most once a second, and only when frames finished in between
+    let mut last_report = Instant::now();
+    let mut frames_since_report = 0;
+    // Path traced frames of an unchanged view are refined by averaging in new passes, up to
+    // a limit past which the remaining noise isn't worth the CPU time
+    let max_passes = 256;
+    // Frames rendered of the current view, and what the one in progress renders. With the
+    // progressive preview the first few are coarse ones traced into `preview`.
+    let mut frame = 0;
+    let mut level = PreviewLevel::full(0);
+    let mut preview = Framebuffer::new(0, 0);
+    let mut completed_passes = 0;
+    let mut refine = false;
+    // Whether a full resolution frame of the current view has come in. Until one has, the
+    // view isn't replaced by a newer one and the animation waits.
+    let mut view_shown = false;
+    let mut worker = RenderWorker::spawn();
+    // The stats, the vignette and the boxes are drawn onto a copy so they never end up in the
+    // rendered image
+    let mut show_stats = true;
+    let mut show_bounds = options.bounds;
+    // Clicks act once when the button goes down, not for as long as it's held
+    let mut mouse_was_down = false;
+    // The object last clicked on, shown with the stats
+    let mut selection: Option<Pick> = None;
+    let mut overlay = Framebuffer::new(framebuffer.width, framebuffer.height);
+    // The denoised image is kept apart from the framebuffer, whose radiance later passes
+    // keep averaging into. It's only shown once it has caught up with the latest pass.
+    let mut denoise = options.denoise;
+    let mut denoised = Framebuffer::new(framebuffer.width, framebuffer.height);
+    let mut denoised_current = false;
+    let mut guides = None;
+    let mut watcher = options.scene.as_deref().map(SceneWatcher::new);
 
-    // Initialize camera
-    let mut camera = Camera::new(
-        Vec3::new(0.0, 0.0, 0.0),  // Camera at origin
-        Vec3::new(0.0, 0.0, -5.0),  // Looking directly at the bear face
-        Vec3::new(0.0, 1.0, 0.0)   // up: World up vector
-    );
+    // Only re-render when something that affects the image changed
+    let mut dirty = true;
+    // Set when only exposure or tone mapping changed and the last frame's radiance can be reused
+    let mut display_dirty = false;
 
-    let rotation_speed = PI / 10.0;
+    // Exposure is adjusted in half stops (a factor of √2) around the command-line value,
+    // within a range that keeps the tone mapper's input finite
+    let base_exposure = settings.exposure;
+
+    // The F key cycles the fog through these: gray haze, warm dusk, blue mist and darkness
+    let fog_colors = [
+        Color::new(200, 205, 210),
+        Color::new(230, 190, 150),
+        Color::new(150, 175, 210),
+        Color::black(),
+    ];
+    let mut exposure_stops: f32 = 0.0;
+    // Seconds the animation has played for, which only advances while it plays
+    let mut animating = !options.no_animate;
+    let mut animation_time = 0.0;
 
     while window.is_open() {
         if window.is_key_down(Key::Escape) {
             break;
         }
 
-        // Camera controls
-        if window.is_key_down(Key::Left) {
-            camera.orbit(rotation_speed, 0.0);
+        // Time since the previous iteration, which the animation plays along with
+        let now = Instant::now();
+        let dt = now.duration_since(last_frame).as_secs_f32();
+        last_frame = now;
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            animating = !animating;
+            println!("Animation {}", if animating { "playing" } else { "paused" });
+        }
+        // The scene is only moved on once the frame showing it is done. Moving it every
+        // iteration would start a new frame over the unfinished one each time, which would
+        // never get past its first tiles or its coarse preview. The time keeps going meanwhile,
+        // so slow frames skip ahead rather than slow the animation down.
+        if animating {
+            animation_time += dt;
+            if view_shown {
+                // Moving objects restart the accumulation of path traced passes like any edit
+                dirty |= animation.update(Arc::make_mut(&mut scene), animation_time, settings.shutter);
+            }
+        }
+
+        // Follow the window size so the image is never stretched. A minimized window
+        // reports zero, in which case the previous frame is kept as is.
+        let (width, height) = window.get_size();
+        let mut resized = false;
+        if width > 0 && height > 0 && (width, height) != (framebuffer.width, framebuffer.height) {
+            framebuffer.resize(width, height);
+            dirty = true;
+            resized = true;
         }
-        if window.is_key_down(Key::Right) {
-            camera.orbit(-rotation_speed, 0.0);
+
+        // Clicking selects the object under the mouse, reports it and focuses the lens on
+        // it. Clicking the background clears the selection.
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+        if let (true, false, Some((x, y))) = (mouse_down, mouse_was_down, window.get_mouse_pos(MouseMode::Discard)) {
+            selection = pick_at(x, y, framebuffer.width, framebuffer.height, &scene, &camera, &settings);
+            match &selection {
+                Some(pick) => {
+                    report_pick(pick);
+                    camera.focus_distance = focus_distance_at(x, y, framebuffer.width, framebuffer.height, &scene, &camera, &settings);
+                    // A pinhole camera looks the same wherever it's focused
+                    if let (Some(distance), true) = (camera.focus_distance, camera.aperture > 0.0) {
+                        dirty = true;
+                        println!("Focus distance: {:.3}", distance);
+                    }
+                }
+                None => println!("Selection cleared"),
+            }
         }
-        if window.is_key_down(Key::Up) {
-            camera.orbit(0.0, -rotation_speed);
+        mouse_was_down = mouse_down;
+
+        // With Shift held, the arrow keys and Page Up/Down move the selected sphere along x, y
+        // and z of the space it's placed in, Page Up away from the default camera. Comma and
+        // period shrink and grow it.
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        if let Some(pick) = &selection {
+            let pressed = |key| window.is_key_pressed(key, KeyRepeat::Yes);
+            let step = |minus, plus| if pressed(plus) { 1.0 } else if pressed(minus) { -1.0 } else { 0.0 };
+            let offset = if shift {
+                glm::vec3(step(Key::Left, Key::Right), step(Key::Down, Key::Up), step(Key::PageUp, Key::PageDown)) * SELECTION_STEP
+            } else {
+                glm::Vec3::zeros()
+            };
+            let growth = SELECTION_GROWTH.powf(step(Key::Comma, Key::Period));
+            if offset != glm::Vec3::zeros() || growth != 1.0 {
+                match Arc::make_mut(&mut scene).picked_mut(pick) {
+                    Some(Object::Sphere(sphere)) => {
+                        sphere.center += offset;
+                        sphere.radius *= growth;
+                        let center = sphere.center;
+                        println!("Sphere at ({:.2}, {:.2}, {:.2}), radius {:.3}", center.x, center.y, center.z, sphere.radius);
+                        dirty = true;
+                    }
+                    _ => println!("Only spheres can be moved, this is a {}", pick.kind),
+                }
+            }
         }
-        if window.is_key_down(Key::Down) {
-            camera.orbit(0.0, rotation_speed);
+
+        // Snap to a saved viewpoint, animating from the current camera
+        for (i, key) in viewpoint_keys.iter().enumerate() {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                if let Some(viewpoint) = scene.viewpoints.get(i) {
+                    transition = Some((camera, *viewpoint, Instant::now()));
+                    retarget = None;
+                }
+            }
+        }
+        if let Some((from, to, start)) = transition {
+            let t = (start.elapsed().as_secs_f32() / transition_duration.as_secs_f32()).min(1.0);
+            // Smoothstep so the camera eases in and out of the move
+            camera = from.lerp(&to, t * t * (3.0 - 2.0 * t));
+            dirty = true;
+            if t >= 1.0 {
+                transition = None;
+            }
         }
 
-        // Render the bear face
-        render(&mut framebuffer, &objects, &camera);
+        // Keeps the eye where it is, so orbiting goes round the new point from where the
+        // camera already is
+        if window.is_key_pressed(Key::Tab, KeyRepeat::No) {
+            let points = scene.points_of_interest();
+            point_of_interest = (point_of_interest + 1) % points.len();
+            let (name, point) = &points[point_of_interest];
+            println!("Looking at {}", name);
+            retarget = Some((camera.center, *point, Instant::now()));
+            transition = None;
+        }
+        if let Some((from, to, start)) = retarget {
+            let t = (start.elapsed().as_secs_f32() / retarget_duration.as_secs_f32()).min(1.0);
+            camera.set_center(from + (to - from) * (t * t * (3.0 - 2.0 * t)));
+            dirty = true;
+            if t >= 1.0 {
+                retarget = None;
+            }
+        }
+
+        // Camera controls, the arrow keys only without Shift
+        if !shift && window.is_key_down(Key::Left) {
+            camera.orbit(rotation_speed * dt, 0.0);
+            dirty = true;
+        }
+        if !shift && window.is_key_down(Key::Right) {
+            camera.orbit(-rotation_speed * dt, 0.0);
+            dirty = true;
+        }
+        if !shift && window.is_key_down(Key::Up) {
+            camera.orbit(0.0, -rotation_speed * dt);
+            dirty = true;
+        }
+        if !shift && window.is_key_down(Key::Down) {
+            camera.orbit(0.0, rotation_speed * dt);
+            dirty = true;
+        }
+        if window.is_key_down(Key::Q) {
+            camera.roll(roll_speed * dt);
+            dirty = true;
+        }
+        if window.is_key_down(Key::E) {
+            camera.roll(-roll_speed * dt);
+            dirty = true;
+        }
+        // Back the camera off until the whole scene is in view
+        if window.is_key_pressed(Key::Z, KeyRepeat::No) {
+            let aspect_ratio = framebuffer.width as f32 / framebuffer.height as f32;
+            camera.frame_scene(&scene, settings.fov, aspect_ratio);
+            transition = None;
+            retarget = None;
+            dirty = true;
+        }
+
+        // Reload the scene file when it changes on disk or when R is pressed
+        let file_changed = watcher.as_mut().is_some_and(|watcher| watcher.poll());
+        if file_changed || window.is_key_pressed(Key::R, KeyRepeat::No) {
+            if let Some(path) = &options.scene {
+                match Scene::load(path) {
+                    Ok(new_scene) => {
+                        // Keep the interactive viewpoint unless the file moved the camera
+                        if new_scene.camera != scene.camera {
+                            camera = new_scene.camera;
+                        }
+                        scene = Arc::new(new_scene);
+                        // Start the animation over from where the file places everything
+                        animation = Animation::new(Arc::make_mut(&mut scene), options.seed);
+                        animation_time = 0.0;
+                        // The objects may have changed places
+                        selection = None;
+                        dirty = true;
+                        println!("Reloaded scene from {}", path);
+                    }
+                    // A broken edit keeps the previous scene on screen
+                    Err(err) => eprintln!("error: {}", err),
+                }
+            }
+        }
+
+        // Compare against the uncorrected output
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            settings.gamma_correct = !settings.gamma_correct;
+            println!("Gamma correction {}", if settings.gamma_correct { "on" } else { "off" });
+            dirty = true;
+        }
+
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            settings.tone_map = settings.tone_map.next();
+            println!("Tone mapping: {}", settings.tone_map.name());
+            display_dirty = true;
+        }
+
+        let exposure_step = if window.is_key_pressed(Key::RightBracket, KeyRepeat::Yes) {
+            0.5
+        } else if window.is_key_pressed(Key::LeftBracket, KeyRepeat::Yes) {
+            -0.5
+        } else {
+            0.0
+        };
+        if exposure_step != 0.0 {
+            exposure_stops = (exposure_stops + exposure_step).clamp(-10.0, 10.0);
+            settings.exposure = base_exposure * exposure_stops.exp2();
+            println!("Exposure: {:+.1} EV", exposure_stops);
+            display_dirty = true;
+        }
+
+        // Minus and Equal thin and thicken the fog by half a stop, down to none at all
+        let fog_step = if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+            2.0_f32.sqrt()
+        } else if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+            0.5_f32.sqrt()
+        } else {
+            1.0
+        };
+        if fog_step != 1.0 {
+            let fog = Arc::make_mut(&mut scene).fog.get_or_insert(Fog {
+                color: fog_colors[0],
+                density: 0.0,
+                background: false,
+            });
+            fog.density = match (fog.density * fog_step, fog_step > 1.0) {
+                (density, _) if density >= 0.002 => density.min(10.0),
+                // Thickening fog that's off starts from a light haze
+                (_, true) => 0.02,
+                (_, false) => 0.0,
+            };
+            println!("Fog density: {:.3}", fog.density);
+            dirty = true;
+        }
+        // J and L turn the scene's first group, like the bear, about its own vertical axis;
+        // with Shift they slide it sideways instead
+        let group_step = if window.is_key_pressed(Key::L, KeyRepeat::Yes) {
+            1.0
+        } else if window.is_key_pressed(Key::J, KeyRepeat::Yes) {
+            -1.0
+        } else {
+            0.0
+        };
+        if group_step != 0.0 {
+            let (offset, degrees) = if shift {
+                (glm::vec3(0.1 * group_step, 0.0, 0.0), 0.0)
+            } else {
+                (glm::Vec3::zeros(), 5.0 * group_step)
+            };
+            dirty |= animation.move_group(Arc::make_mut(&mut scene), offset, degrees);
+        }
+
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            if let Some(fog) = &mut Arc::make_mut(&mut scene).fog {
+                let current = fog_colors.iter().position(|color| *color == fog.color);
+                fog.color = fog_colors[current.map_or(0, |i| (i + 1) % fog_colors.len())];
+                println!("Fog color: #{:06X}", fog.color.to_hex());
+                dirty = true;
+            }
+        }
+
+        // Switch between direct lighting and path tracing
+        if window.is_key_pressed(Key::I, KeyRepeat::No) {
+            settings.integrator = match settings.integrator {
+                Integrator::Whitted => Integrator::PathTraced { samples: options.path_samples.unwrap_or(1) },
+                Integrator::PathTraced { .. } => Integrator::Whitted,
+            };
+            println!("Integrator: {:?}", settings.integrator);
+            dirty = true;
+        }
+
+        if window.is_key_pressed(Key::C, KeyRepeat::No) {
+            settings.mode = match settings.mode {
+                RenderMode::Materials => RenderMode::Clay,
+                RenderMode::Clay => RenderMode::Materials,
+            };
+            println!("Render mode: {:?}", settings.mode);
+            dirty = true;
+        }
+
+        // V cycles through the beauty render and the debug channels
+        if window.is_key_pressed(Key::V, KeyRepeat::No) {
+            settings.channel = settings.channel.next();
+            println!("Channel: {}", settings.channel.name());
+            dirty = true;
+        }
+
+        // O toggles ambient occlusion, Shift+O the grayscale occlusion view
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            if shift {
+                settings.occlusion_only = !settings.occlusion_only;
+                println!("Occlusion view {}", if settings.occlusion_only { "on" } else { "off" });
+            } else {
+                settings.ambient_occlusion = !settings.ambient_occlusion;
+                println!("Ambient occlusion {}", if settings.ambient_occlusion { "on" } else { "off" });
+            }
+            dirty = true;
+        }
+
+        let mut denoise_dirty = false;
+        if window.is_key_pressed(Key::D, KeyRepeat::No) {
+            denoise = !denoise;
+            println!("Denoiser {}", if denoise { "on" } else { "off" });
+            denoise_dirty = denoise;
+        }
+
+        if window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            show_stats = !show_stats;
+        }
+        if window.is_key_pressed(Key::B, KeyRepeat::No) {
+            show_bounds = !show_bounds;
+        }
+
+        let ctrl = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
+        if ctrl && window.is_key_pressed(Key::S, KeyRepeat::No) {
+            save_scene(&scene, &animation, &camera);
+        }
+
+        // Render the bear face in the background, showing tiles as they come in. A view's
+        // coarse previews and its first full resolution frame always get to finish before the
+        // next view replaces them, or held keys would keep restarting them and only the top
+        // tiles of the coarsest level would ever show; later frames only refine an image
+        // that's already complete and make way right away. Tiles of the old size would land
+        // in the wrong places, so resizing doesn't wait.
+        let restart = dirty && (view_shown || resized || !(worker.is_busy() || refine));
+        if restart {
+            frame = 0;
+            view_shown = false;
+            // The guides follow the camera and scene, so they are traced again for the new view
+            guides = None;
+            denoised_current = false;
+        }
+        if restart || refine {
+            // A coarse frame is enlarged over the whole window, which would paint over
+            // everything outside the region, so regions skip the preview
+            level = if options.progressive && settings.region.is_none() {
+                preview_level(frame)
+            } else {
+                PreviewLevel::full(frame)
+            };
+            let (mut width, mut height) = (framebuffer.width, framebuffer.height);
+            if level.is_coarse() {
+                (width, height) = (width.div_ceil(level.scale), height.div_ceil(level.scale));
+                if (preview.width, preview.height) != (width, height) {
+                    preview.resize(width, height);
+                }
+            }
+            worker.submit(RenderJob {
+                scene: Arc::clone(&scene),
+                camera,
+                settings: settings.clone(),
+                width,
+                height,
+                pass: level.pass,
+            });
+            dirty = false;
+            refine = false;
+        }
+        // Coarse frames are only shown once they're done, enlarged over the whole window.
+        // The full resolution frame after them then comes in tile by tile on top.
+        let target = if level.is_coarse() { &mut preview } else { &mut framebuffer };
+        if let Some(frame_stats) = worker.poll(target, &settings) {
+            fps_counter.tick(last_finished.elapsed().as_secs_f32());
+            last_finished = Instant::now();
+            stats = frame_stats;
+            frames_since_report += 1;
+            if level.is_coarse() {
+                preview.upscale_into(&mut framebuffer);
+            } else {
+                completed_passes = level.pass + 1;
+                denoise_dirty = denoise;
+                view_shown = true;
+            }
+            let path_traced = matches!(settings.integrator, Integrator::PathTraced { .. });
+            if level.is_coarse() || (path_traced && level.pass + 1 < max_passes) {
+                frame += 1;
+                refine = true;
+            }
+        }
+        if frames_since_report > 0 && last_report.elapsed() >= Duration::from_secs(1) {
+            println!("{} frame(s), last: {}", frames_since_report, stats);
+            frames_since_report = 0;
+            last_report = Instant::now();
+        }
+        if display_dirty {
+            retonemap(&mut framebuffer, &settings);
+            retonemap(&mut denoised, &settings);
+            display_dirty = false;
+        }
+        if denoise_dirty && completed_passes > 0 {
+            let (width, height) = (framebuffer.width, framebuffer.height);
+            let guides = guides.get_or_insert_with(|| render_guides(width, height, &scene, &camera, &settings));
+            if (denoised.width, denoised.height) != (width, height) {
+                denoised.resize(width, height);
+            }
+            denoised.radiance = options.denoiser.apply(&framebuffer.radiance, width, height, guides);
+            retonemap(&mut denoised, &settings);
+            denoised_current = true;
+        }
 
         // Update the window with the framebuffer contents
+        let image = if denoise && denoised_current { &denoised } else { &framebuffer };
+        let displayed = if show_stats || show_bounds || options.vignette.is_some() || selection.is_some() {
+            if (overlay.width, overlay.height) != (image.width, image.height) {
+                overlay.resize(image.width, image.height);
+            }
+            overlay.buffer.copy_from_slice(&image.buffer);
+            if let Some(strength) = options.vignette {
+                overlay.vignette(strength);
+            }
+            if show_bounds {
+                draw_bounding_boxes(&mut overlay, &scene, &camera, &settings, BOUNDS_COLOR);
+                draw_lights(&mut overlay, &scene, &camera, &settings, LIGHT_COLOR);
+            }
+            if let Some(bounds) = selection.as_ref().and_then(|pick| scene.picked_bounds(pick)) {
+                draw_box(&mut overlay, &bounds, &camera, &settings, SELECTION_COLOR);
+            }
+            if show_stats {
+                let mut text = format_stats(fps_counter.fps(), &stats, &settings, completed_passes, &camera);
+                if let Some(pick) = &selection {
+                    text += &format!("\nselected {}", describe_pick(pick));
+                }
+                // A one pixel shadow keeps the text readable on light backgrounds
+                overlay.draw_text(5, 5, &text, 0x000000);
+                overlay.draw_text(4, 4, &text, 0xFFFFFF);
+            }
+            &overlay
+        } else {
+            image
+        };
         window
-            .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
+            .update_with_buffer(&displayed.buffer, displayed.width, displayed.height)
             .unwrap();
 
         std::thread::sleep(frame_delay);
+
+        window.set_title(&format!(
+            "{} — {:.1} FPS ({:.1} ms render)",
+            title,
+            fps_counter.fps(),
+            stats.millis
+        ));
+    }
+
+    // Stop the worker before exiting, it may be in the middle of a frame
+    worker.shutdown();
+
+    // Keep the last displayed frame when an output file was requested
+    if let Some(output) = &options.output {
+        save_frame(if denoise && denoised_current { &denoised } else { &framebuffer }, output);
+    }
+}
+
+// `passes` is how many passes the displayed image averages
+fn format_stats(fps: f32, stats: &RenderStats, settings: &RenderSettings, passes: u32, camera: &Camera) -> String {
+    let sampling = match (settings.integrator, settings.adaptive_threshold) {
+        (Integrator::PathTraced { samples }, _) => format!("path traced, {} spp", samples * passes),
+        (Integrator::Whitted, Some(threshold)) => format!("adaptive {}", threshold),
+        (Integrator::Whitted, None) => format!("{} spp", settings.samples),
+    };
+    let mut text = format!(
+        "{:.1} fps\n{:.1} ms render\n{}\n{} rays, {} tests\neye {:.2} {:.2} {:.2}",
+        fps,
+        stats.millis,
+        sampling,
+        stats.primary_rays + stats.shadow_rays + stats.bounce_rays,
+        stats.intersection_tests,
+        camera.eye.x,
+        camera.eye.y,
+        camera.eye.z
+    );
+    if settings.channel != Channel::Beauty {
+        text += &format!("\nchannel {}", settings.channel.name());
+    }
+    text
+}
+
+// Which object a pick is, e.g. "object 2, a sphere in 'bear' > 'nose'"
+fn describe_pick(pick: &Pick) -> String {
+    let mut description = format!("object {}, a {}", pick.index, pick.kind);
+    if !pick.groups.is_empty() {
+        let groups: Vec<String> = pick.groups.iter().map(|name| format!("'{}'", name)).collect();
+        description += &format!(" in {}", groups.join(" > "));
+    }
+    description
+}
+
+fn report_pick(pick: &Pick) {
+    let hit = &pick.intersect;
+    let material = &hit.material;
+    println!(
+        "Selected {}: {:.3} away at ({:.2}, {:.2}, {:.2})",
+        describe_pick(pick),
+        hit.distance,
+        hit.point.x,
+        hit.point.y,
+        hit.point.z
+    );
+    println!(
+        "  diffuse #{:06X}, specular {:.2}, shininess {:.0}, reflectivity {:.2}, roughness {:.2}, metallic {:.2}, emission {:.2}",
+        material.diffuse.to_hex(),
+        material.specular,
+        material.shininess,
+        material.reflectivity,
+        material.roughness,
+        material.metallic,
+        material.emission_strength
+    );
+}
+
+// Bright enough to stand out against most scenes
+const BOUNDS_COLOR: u32 = 0x00FF00;
+// The crosses where the lights are, apart from the boxes
+const LIGHT_COLOR: u32 = 0xFF00FF;
+// The box around the selected object, apart from the others
+const SELECTION_COLOR: u32 = 0xFFFF00;
+// How far a key press moves the selected sphere, and the factor its radius grows or
+// shrinks by
+const SELECTION_STEP: f32 = 0.05;
+const SELECTION_GROWTH: f32 = 1.1;
+
+// Renders one frame at the framebuffer's size and writes it to a PNG, without the window.
+// The options pick what's done to the image before saving: denoising, then outlines, then
+// the vignette, then bounding boxes and lights.
+fn render_to_file(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    options: &cli::Options,
+    path: &str,
+) -> std::io::Result<RenderStats> {
+    let denoiser = options.denoise.then_some(&options.denoiser);
+    let outline = options.outline;
+    // Rewritten in place, and only when the whole percentage goes up, not after every tile
+    let mut shown = None;
+    let stats = render_with_progress(framebuffer, scene, camera, settings, |done| {
+        let percent = (done * 100.0) as u32;
+        if shown != Some(percent) {
+            eprint!("\rRendering... {:3}%", percent);
+            shown = Some(percent);
+        }
+    });
+    eprintln!();
+    if denoiser.is_some() || outline.is_some() {
+        let guides = render_guides(framebuffer.width, framebuffer.height, scene, camera, settings);
+        if let Some(denoiser) = denoiser {
+            framebuffer.radiance = denoiser.apply(&framebuffer.radiance, framebuffer.width, framebuffer.height, &guides);
+            retonemap(framebuffer, settings);
+        }
+        framebuffer.depth = guides.depths;
+    }
+    if let Some(threshold) = outline {
+        framebuffer.edge_detect(threshold);
+    }
+    if let Some(strength) = options.vignette {
+        framebuffer.vignette(strength);
+    }
+    if options.bounds {
+        draw_bounding_boxes(framebuffer, scene, camera, settings, BOUNDS_COLOR);
+        draw_lights(framebuffer, scene, camera, settings, LIGHT_COLOR);
+    }
+    framebuffer.save_png(path)?;
+    Ok(stats)
+}
+
+// "renders/teddy.png" becomes "renders/teddy_thumb.png"
+fn thumbnail_path(path: &str) -> String {
+    let path = std::path::Path::new(path);
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("render");
+    path.with_file_name(format!("{}_thumb.png", stem)).to_string_lossy().into_owned()
+}
+
+fn save_frame(framebuffer: &Framebuffer, path: &str) {
+    match framebuffer.save_png(path) {
+        Ok(()) => println!("Saved frame to {}", path),
+        Err(err) => {
+            eprintln!("error: could not write '{}': {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headless_render_writes_a_png_of_the_frame() {
+        let scene = Scene::parse(
+            r#"Scene(
+                background: Solid((20, 30, 40)),
+                camera: (eye: (0.0, 0.0, 0.0), center: (0.0, 0.0, -5.0), up: (0.0, 1.0, 0.0)),
+                objects: [Sphere(center: (0.0, 0.0, -5.0), radius: 1.0, material: (diffuse: (200, 60, 20)))],
+                lights: [(position: (0.0, 5.0, 0.0), color: (255, 255, 255), intensity: 1.0)],
+            )"#,
+            "minimal.ron",
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join("raytracing_animal_headless.png").to_string_lossy().into_owned();
+        let mut framebuffer = Framebuffer::new(24, 16);
+        let stats = render_to_file(&mut framebuffer, &scene, &scene.camera, &RenderSettings::default(), &cli::Options::default(), &path).unwrap();
+        assert_eq!(stats.primary_rays, 24 * 16);
+
+        let decoder = png::Decoder::new(std::io::BufReader::new(std::fs::File::open(&path).unwrap()));
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (24, 16));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        let pixels: Vec<u32> = data[..info.buffer_size()]
+            .chunks(3)
+            .map(|rgb| ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32)
+            .collect();
+        assert_eq!(pixels, framebuffer.buffer);
+        // The ball in the middle and the background at the corner
+        assert_ne!(pixels[8 * 24 + 12], 0x141e28);
+        assert_eq!(pixels[0], 0x141e28);
     }
 }
@@ -0,0 +1,473 @@
+use nalgebra_glm::{Vec3, dot};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::ray_intersect::{RayIntersect, Material, Intersect, Ray};
+
+// Triangles loaded from a Wavefront OBJ file, or a glTF one with the `gltf` feature, wrap
+// it in a transform to place it. Faces the file gives a material of its own get that one,
+// the rest get `material`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mesh {
+    pub model: Model,
+    #[serde(default)]
+    pub shading: ShadingMode,
+    pub material: Material,
+}
+
+// Which normal a hit on a triangle gets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ShadingMode {
+    Flat, // The triangle's own, so every face shows up
+    // Blended from the normals at its corners, so a coarse mesh shades like the smooth
+    // surface it approximates; only the silhouette stays faceted
+    #[default]
+    Smooth,
+}
+
+// The geometry of a model file and the materials its faces use. Scene files refer to it by
+// its path, which is resolved relative to the working directory like texture paths are.
+// The data is shared between clones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Model {
+    pub path: String,
+    positions: Arc<[Vec3]>,
+    normals: Arc<[Vec3]>,
+    triangles: Arc<[Triangle]>,
+    materials: Arc<[Material]>,
+    bounds: Aabb,
+}
+
+// Indices into the model's positions and normals for each corner, and into its materials
+// unless the triangle uses the mesh's own
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Triangle {
+    positions: [usize; 3],
+    normals: [usize; 3],
+    material: Option<usize>,
+}
+
+// A triangle as read, with the normal index of each corner if it has one, and the index of
+// its material if it has its own
+pub type Face = ([usize; 3], [Option<usize>; 3], Option<usize>);
+
+impl Model {
+    // Also loads the material libraries the file names with `mtllib`, which are looked for
+    // next to the OBJ file. Paths ending in .gltf are read as glTF instead, see `gltf::load`.
+    pub fn load(path: &str) -> Result<Model, String> {
+        if path.to_ascii_lowercase().ends_with(".gltf") {
+            return load_gltf(path);
+        }
+        let source = fs::read_to_string(path).map_err(|err| format!("could not open model '{}': {}", path, err))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut materials = Vec::new();
+        for line in source.lines() {
+            let mut fields = line.split_whitespace();
+            if fields.next() != Some("mtllib") {
+                continue;
+            }
+            for library in fields {
+                let library_path = directory.join(library).to_string_lossy().into_owned();
+                let library_source = fs::read_to_string(&library_path)
+                    .map_err(|err| format!("could not open material library '{}': {}", library_path, err))?;
+                materials.extend(parse_materials(&library_source, &library_path)?);
+            }
+        }
+        Model::parse(&source, path, &materials)
+    }
+
+    // Reads vertices (`v`), vertex normals (`vn`) and faces (`f`), splitting faces with more
+    // than three corners into a fan of triangles. Corners written without a normal get the
+    // average of the normals of the faces around their vertex. Faces after a `usemtl` get
+    // the material of that name from `materials`; ones before any, or after a name that
+    // isn't there, keep the mesh's own. Everything else, like texture coordinates, is
+    // skipped. `path` is only used to label errors.
+    pub fn parse(source: &str, path: &str, materials: &[(String, Material)]) -> Result<Model, String> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut faces: Vec<Face> = Vec::new();
+        let mut material = None;
+
+        for (number, line) in source.lines().enumerate() {
+            let invalid = |reason: &str| format!("invalid model '{}' at line {}: {}", path, number + 1, reason);
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("v") => positions.push(parse_vector(fields).ok_or_else(|| invalid("expected x y z"))?),
+                Some("vn") => normals.push(parse_vector(fields).ok_or_else(|| invalid("expected x y z"))?.normalize()),
+                Some("f") => {
+                    let corners = fields
+                        .map(|corner| parse_corner(corner, positions.len(), normals.len()))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| invalid("face refers to a vertex or normal that doesn't exist"))?;
+                    if corners.len() < 3 {
+                        return Err(invalid("a face needs at least 3 corners"));
+                    }
+                    for i in 1..corners.len() - 1 {
+                        let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+                        faces.push(([a.0, b.0, c.0], [a.1, b.1, c.1], material));
+                    }
+                }
+                Some("usemtl") => {
+                    let name = fields.next().unwrap_or("");
+                    material = materials.iter().position(|(material_name, _)| material_name == name);
+                }
+                _ => {}
+            }
+        }
+
+        let materials = materials.iter().map(|(_, material)| material.clone()).collect();
+        Ok(Model::from_faces(path, positions, normals, faces, materials))
+    }
+
+    // The model of a list of triangles, whose material indices point into `materials`.
+    // Corners without a normal get the average of the normals of the faces around their
+    // vertex.
+    pub fn from_faces(path: &str, positions: Vec<Vec3>, mut normals: Vec<Vec3>, faces: Vec<Face>, materials: Vec<Material>) -> Model {
+        // Face normals weighted by area, since the cross product's length is twice the area
+        let mut averaged = vec![Vec3::zeros(); positions.len()];
+        for (corners, ..) in &faces {
+            let [a, b, c] = corners.map(|i| positions[i]);
+            let face_normal = (b - a).cross(&(c - a));
+            for &i in corners {
+                averaged[i] += face_normal;
+            }
+        }
+        let offset = normals.len();
+        normals.extend(averaged.iter().map(|n| n.try_normalize(1e-12).unwrap_or_else(Vec3::zeros)));
+
+        let triangles = faces
+            .into_iter()
+            .map(|(positions, corner_normals, material)| Triangle {
+                positions,
+                normals: [0, 1, 2].map(|i| corner_normals[i].unwrap_or(offset + positions[i])),
+                material,
+            })
+            .collect();
+
+        let bounds = positions
+            .iter()
+            .fold(Aabb::empty(), |bounds, p| bounds.union(&Aabb { min: *p, max: *p }));
+        Model {
+            path: path.to_string(),
+            positions: positions.into(),
+            normals: normals.into(),
+            triangles,
+            materials: materials.into(),
+            bounds,
+        }
+    }
+}
+
+#[cfg(feature = "gltf")]
+fn load_gltf(path: &str) -> Result<Model, String> {
+    crate::gltf::load(path)
+}
+
+#[cfg(not(feature = "gltf"))]
+fn load_gltf(path: &str) -> Result<Model, String> {
+    Err(format!("could not open model '{}': glTF models need the renderer built with the `gltf` feature", path))
+}
+
+// The materials of an MTL file by name: `Kd` sets the diffuse color, `Ks` the strength of
+// the highlight and `Ns` its exponent. The colors are linear like exporters write them, and
+// the highlight's color is averaged into a strength since highlights here are white. `d`,
+// the opacity, is checked but has nothing to apply to, every material is opaque. The rest,
+// like texture maps, is skipped. `path` is only used to label errors.
+pub fn parse_materials(source: &str, path: &str) -> Result<Vec<(String, Material)>, String> {
+    let mut materials: Vec<(String, Material)> = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let invalid = |reason: &str| format!("invalid material library '{}' at line {}: {}", path, number + 1, reason);
+        let mut fields = line.split_whitespace();
+        let keyword = fields.next();
+        if keyword == Some("newmtl") {
+            let name = fields.next().ok_or_else(|| invalid("expected a material name"))?;
+            materials.push((name.to_string(), Material::default()));
+            continue;
+        }
+
+        let Some((_, material)) = materials.last_mut() else {
+            if matches!(keyword, Some("Kd" | "Ks" | "Ns" | "d")) {
+                return Err(invalid("a material setting before any `newmtl`"));
+            }
+            continue;
+        };
+        match keyword {
+            Some("Kd") => {
+                let color = parse_vector(fields).ok_or_else(|| invalid("expected r g b"))?;
+                material.diffuse = Color::from_linear(color);
+            }
+            Some("Ks") => {
+                let color = parse_vector(fields).ok_or_else(|| invalid("expected r g b"))?;
+                material.specular = (color.x + color.y + color.z) / 3.0;
+            }
+            Some("Ns") => material.shininess = parse_number(fields).ok_or_else(|| invalid("expected a number"))?,
+            Some("d") => {
+                parse_number(fields).ok_or_else(|| invalid("expected a number"))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+fn parse_number<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<f32> {
+    fields.next()?.parse().ok()
+}
+
+// Three numbers, ignoring an optional fourth like the w of a vertex
+fn parse_vector<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<Vec3> {
+    let mut next = || fields.next()?.parse::<f32>().ok();
+    Some(Vec3::new(next()?, next()?, next()?))
+}
+
+// A face corner, `v`, `v/vt`, `v//vn` or `v/vt/vn`, as 0-based indices. OBJ indices start
+// at 1, and negative ones count back from the latest vertex.
+fn parse_corner(corner: &str, positions: usize, normals: usize) -> Option<(usize, Option<usize>)> {
+    let resolve = |field: &str, count: usize| -> Option<usize> {
+        let index: i64 = field.parse().ok()?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        (0..count as i64).contains(&resolved).then_some(resolved as usize)
+    };
+
+    let mut fields = corner.split('/');
+    let position = resolve(fields.next()?, positions)?;
+    let normal = match fields.nth(1) {
+        Some(field) if !field.is_empty() => Some(resolve(field, normals)?),
+        _ => None,
+    };
+    Some((position, normal))
+}
+
+impl TryFrom<String> for Model {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        Model::load(&path)
+    }
+}
+
+impl From<Model> for String {
+    fn from(model: Model) -> Self {
+        model.path
+    }
+}
+
+impl Mesh {
+    pub fn bounding_box(&self) -> Aabb {
+        self.model.bounds
+    }
+}
+
+impl RayIntersect for Mesh {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let model = &self.model;
+        if !hits_box(&model.bounds, ray, t_max) {
+            return Intersect::empty();
+        }
+
+        // Möller-Trumbore on every triangle, keeping the nearest hit, its barycentric
+        // coordinates and whether it's on the back
+        let mut nearest: Option<(f32, f32, f32, &Triangle, bool)> = None;
+        for triangle in model.triangles.iter() {
+            let [a, b, c] = triangle.positions.map(|i| model.positions[i]);
+            let edge1 = b - a;
+            let edge2 = c - a;
+            let p = ray.direction.cross(&edge2);
+            let determinant = dot(&edge1, &p);
+            // Parallel to the triangle's plane, or a degenerate triangle
+            if determinant.abs() < 1e-12 {
+                continue;
+            }
+
+            let inverse = 1.0 / determinant;
+            let to_origin = ray.origin - a;
+            let u = dot(&to_origin, &p) * inverse;
+            if !(0.0..=1.0).contains(&u) {
+                continue;
+            }
+            let q = to_origin.cross(&edge1);
+            let v = dot(&ray.direction, &q) * inverse;
+            if v < 0.0 || u + v > 1.0 {
+                continue;
+            }
+
+            // The determinant is negative for rays reaching the back of the triangle, where
+            // its corners go around clockwise
+            let material = triangle.material.map_or(&self.material, |i| &model.materials[i]);
+            if determinant < 0.0 && !material.double_sided {
+                continue;
+            }
+
+            let t = dot(&edge2, &q) * inverse;
+            if t > t_min && t < t_max && nearest.is_none_or(|(nearest_t, ..)| t < nearest_t) {
+                nearest = Some((t, u, v, triangle, determinant < 0.0));
+            }
+        }
+
+        let Some((t, u, v, triangle, back)) = nearest else {
+            return Intersect::empty();
+        };
+        let normal = match self.shading {
+            ShadingMode::Flat => {
+                let [a, b, c] = triangle.positions.map(|i| model.positions[i]);
+                (b - a).cross(&(c - a)).normalize()
+            }
+            ShadingMode::Smooth => {
+                let [na, nb, nc] = triangle.normals.map(|i| model.normals[i]);
+                let blended = na * (1.0 - u - v) + nb * u + nc * v;
+                // Opposite corner normals can cancel out, the face's own is next best
+                blended.try_normalize(1e-6).unwrap_or_else(|| {
+                    let [a, b, c] = triangle.positions.map(|i| model.positions[i]);
+                    (b - a).cross(&(c - a)).normalize()
+                })
+            }
+        };
+        // Only double-sided triangles are hit from behind, and seen from there they face the ray
+        let normal = if back { -normal } else { normal };
+        let material = triangle.material.map_or(&self.material, |i| &model.materials[i]);
+        let point = ray.at(t);
+        Intersect::new(point, normal, t, material.clone())
+    }
+}
+
+// Slab test against the model's box, so rays that miss it skip every triangle
+fn hits_box(bounds: &Aabb, ray: &Ray, t_max: f32) -> bool {
+    let mut t_near = f32::NEG_INFINITY;
+    let mut t_far = t_max;
+    for axis in 0..3 {
+        let inverse = 1.0 / ray.direction[axis];
+        let t0 = (bounds.min[axis] - ray.origin[axis]) * inverse;
+        let t1 = (bounds.max[axis] - ray.origin[axis]) * inverse;
+        t_near = t_near.max(t0.min(t1));
+        t_far = t_far.min(t0.max(t1));
+    }
+    t_near <= t_far && t_far > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The coarsest sphere there is, a unit octahedron
+    const OCTAHEDRON: &str = "\
+v 1 0 0
+v -1 0 0
+v 0 1 0
+v 0 -1 0
+v 0 0 1
+v 0 0 -1
+f 1 3 5
+f 3 2 5
+f 2 4 5
+f 4 1 5
+f 3 1 6
+f 2 3 6
+f 4 2 6
+f 1 4 6
+";
+
+    fn octahedron(shading: ShadingMode) -> Mesh {
+        Mesh {
+            model: Model::parse(OCTAHEDRON, "octahedron.obj", &[]).unwrap(),
+            shading,
+            material: Material::default(),
+        }
+    }
+
+    // The normal of the hit on the face between +x, +y and +z, aiming from outside at `point` on it
+    fn normal_at(mesh: &Mesh, point: Vec3) -> Vec3 {
+        let hit = mesh.ray_intersect(&Ray::new(point * 5.0, -point), 0.0, f32::INFINITY);
+        assert!(hit.is_intersecting);
+        assert!((hit.point - point).magnitude() < 1e-5);
+        hit.normal
+    }
+
+    #[test]
+    fn flat_shading_gives_the_face_normal_everywhere() {
+        let mesh = octahedron(ShadingMode::Flat);
+        let face_normal = Vec3::repeat(1.0).normalize();
+        for point in [Vec3::new(0.6, 0.2, 0.2), Vec3::new(0.2, 0.2, 0.6), Vec3::repeat(1.0 / 3.0)] {
+            assert!((normal_at(&mesh, point) - face_normal).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn smooth_shading_blends_towards_each_corner() {
+        // Every corner's normal is the average of the four faces round it, which points
+        // straight out from the center, so the blend points out through the hit like a sphere's
+        let mesh = octahedron(ShadingMode::Smooth);
+        let near_x = normal_at(&mesh, Vec3::new(0.6, 0.2, 0.2));
+        let near_z = normal_at(&mesh, Vec3::new(0.2, 0.2, 0.6));
+        assert!((near_x - Vec3::new(0.6, 0.2, 0.2).normalize()).magnitude() < 1e-5);
+        assert!((near_z - Vec3::new(0.2, 0.2, 0.6).normalize()).magnitude() < 1e-5);
+        assert!((normal_at(&mesh, Vec3::repeat(1.0 / 3.0)) - Vec3::repeat(1.0).normalize()).magnitude() < 1e-5);
+    }
+
+    const TWO_MATERIALS_MTL: &str = "\
+newmtl red
+Kd 1 0 0
+Ks 0.5 0.5 0.5
+Ns 32
+newmtl blue
+Kd 0 0 1
+d 1
+";
+
+    // Three triangles facing +z side by side along x: one before any `usemtl`, then one red
+    // and one blue, a name the library lacks resetting the last to the mesh's material
+    const TWO_MATERIALS_OBJ: &str = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 2 0 0
+v 3 0 0
+v 2 1 0
+v 4 0 0
+v 5 0 0
+v 4 1 0
+v 6 0 0
+v 7 0 0
+v 6 1 0
+f 1 2 3
+usemtl red
+f 4 5 6
+usemtl blue
+f 7 8 9
+usemtl missing
+f 10 11 12
+";
+
+    #[test]
+    fn faces_get_the_material_named_before_them() {
+        let materials = parse_materials(TWO_MATERIALS_MTL, "two.mtl").unwrap();
+        assert_eq!(materials.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["red", "blue"]);
+        assert_eq!(materials[0].1.specular, 0.5);
+        assert_eq!(materials[0].1.shininess, 32.0);
+
+        let own = Material { diffuse: Color::new(0, 255, 0), ..Material::default() };
+        let mesh = Mesh {
+            model: Model::parse(TWO_MATERIALS_OBJ, "two.obj", &materials).unwrap(),
+            shading: ShadingMode::Flat,
+            material: own.clone(),
+        };
+        let red = Color::from_linear(Vec3::new(1.0, 0.0, 0.0));
+        let blue = Color::from_linear(Vec3::new(0.0, 0.0, 1.0));
+        for (x, diffuse) in [(0.25, own.diffuse), (2.25, red), (4.25, blue), (6.25, own.diffuse)] {
+            let hit = mesh.ray_intersect(&Ray::new(Vec3::new(x, 0.25, 5.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f32::INFINITY);
+            assert!(hit.is_intersecting, "at x = {}", x);
+            assert_eq!(hit.material.diffuse, diffuse, "at x = {}", x);
+        }
+    }
+
+    #[test]
+    fn material_settings_need_a_newmtl_first() {
+        let error = parse_materials("Kd 1 0 0\nnewmtl red\n", "early.mtl").unwrap_err();
+        assert!(error.contains("early.mtl") && error.contains("line 1"), "{}", error);
+    }
+}
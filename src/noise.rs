@@ -0,0 +1,217 @@
+use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::rng::Rng;
+
+// Ken Perlin's improved gradient noise in 3D: a smooth random-looking value between about
+// -1 and 1 that changes over about a unit of distance and is 0 at every whole-numbered
+// point. The permutation table picks the gradients, so the same seed always gives the same
+// noise. It's kept twice over, so lookups of a lookup plus one never need wrapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Perlin {
+    permutation: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        // Fisher-Yates shuffle
+        let mut rng = Rng::new(seed);
+        for i in (1..table.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+        let permutation = table.iter().chain(table.iter()).copied().collect();
+        Perlin { permutation }
+    }
+
+    pub fn noise(&self, point: &Vec3) -> f32 {
+        let p = &self.permutation;
+        let cell = point.map(|c| c.floor());
+        let (x, y, z) = (cell.x as i32 as u8 as usize, cell.y as i32 as u8 as usize, cell.z as i32 as u8 as usize);
+        let local = point - cell;
+        let (u, v, w) = (fade(local.x), fade(local.y), fade(local.z));
+
+        // Hashes of the cell's eight corners
+        let (a, b) = (p[x] as usize + y, p[x + 1] as usize + y);
+        let (aa, ab, ba, bb) = (p[a] as usize + z, p[a + 1] as usize + z, p[b] as usize + z, p[b + 1] as usize + z);
+        let (lx, ly, lz) = (local.x, local.y, local.z);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, gradient(p[aa], lx, ly, lz), gradient(p[ba], lx - 1.0, ly, lz)),
+                lerp(u, gradient(p[ab], lx, ly - 1.0, lz), gradient(p[bb], lx - 1.0, ly - 1.0, lz)),
+            ),
+            lerp(
+                v,
+                lerp(u, gradient(p[aa + 1], lx, ly, lz - 1.0), gradient(p[ba + 1], lx - 1.0, ly, lz - 1.0)),
+                lerp(u, gradient(p[ab + 1], lx, ly - 1.0, lz - 1.0), gradient(p[bb + 1], lx - 1.0, ly - 1.0, lz - 1.0)),
+            ),
+        )
+    }
+
+    // Octaves of noise added up, each twice as fine and half as strong as the one before,
+    // scaled back to the range a single octave has. Turbulence adds up their sizes instead,
+    // which folds the noise over at every 0 into sharp creases: between 0 and about 1.
+    pub fn fractal(&self, point: &Vec3, octaves: u32, turbulence: bool) -> f32 {
+        let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+        for _ in 0..octaves.max(1) {
+            let value = self.noise(&(point * frequency));
+            sum += amplitude * if turbulence { value.abs() } else { value };
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum / total
+    }
+}
+
+// Eases in and out of each cell, smoothly enough that the noise has no creases at its edges
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// The dot product of the offset with one of 12 gradients pointing at the edges of a cube,
+// picked by the low four bits of the hash
+fn gradient(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// How thin marble veins are, higher gives thinner ones
+const VEIN_SHARPNESS: f32 = 12.0;
+
+// A material's procedural pattern, which blends its diffuse color towards `color` wherever
+// the noise is high, for mottled fur or clouds. Turbulence instead gives thin veins of
+// `color` where the noise crosses 0, like in marble. The noise is sampled at the world-space
+// hit point, `scale` times finer than a unit, with `octaves` layers of finer detail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "NoiseDef", into = "NoiseDef")]
+pub struct Noise {
+    pub color: Color,
+    pub scale: f32,
+    pub octaves: u32,
+    pub turbulence: bool,
+    pub seed: u64,
+    perlin: Perlin,
+}
+
+impl Noise {
+    // The diffuse color at a point, given the material's own
+    pub fn color_at(&self, point: &Vec3, diffuse: Color) -> Color {
+        let value = self.perlin.fractal(&(point * self.scale), self.octaves, self.turbulence);
+        // Smooth noise is centered on 0. Turbulence is only near 0 along its creases, which
+        // the power narrows down to veins.
+        let amount = if self.turbulence { (1.0 - value).max(0.0).powf(VEIN_SHARPNESS) } else { 0.5 + 0.5 * value };
+        Color::lerp(diffuse, self.color, amount)
+    }
+}
+
+// What scene files write for a noise pattern, everything but the color being optional
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Noise", deny_unknown_fields)]
+struct NoiseDef {
+    color: Color,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    #[serde(default)]
+    turbulence: bool,
+    #[serde(default)]
+    seed: u64,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+impl From<NoiseDef> for Noise {
+    fn from(def: NoiseDef) -> Self {
+        Noise {
+            color: def.color,
+            scale: def.scale,
+            octaves: def.octaves,
+            turbulence: def.turbulence,
+            seed: def.seed,
+            perlin: Perlin::new(def.seed),
+        }
+    }
+}
+
+impl From<Noise> for NoiseDef {
+    fn from(noise: Noise) -> Self {
+        NoiseDef {
+            color: noise.color,
+            scale: noise.scale,
+            octaves: noise.octaves,
+            turbulence: noise.turbulence,
+            seed: noise.seed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Points all over, on both sides of 0 and far out, for a given seed
+    fn points(seed: u64) -> Vec<Vec3> {
+        let mut rng = Rng::new(seed);
+        (0..2000).map(|_| Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()).map(|c| (c - 0.5) * 600.0)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_noise() {
+        let (a, b, other) = (Perlin::new(5), Perlin::new(5), Perlin::new(6));
+        let points = points(1);
+        assert!(points.iter().all(|p| a.noise(p) == b.noise(p) && a.fractal(p, 4, true) == b.fractal(p, 4, true)));
+        assert!(points.iter().any(|p| a.noise(p) != other.noise(p)));
+    }
+
+    #[test]
+    fn noise_is_zero_on_whole_numbers_and_stays_in_range() {
+        let perlin = Perlin::new(5);
+        for (x, y, z) in [(0.0, 0.0, 0.0), (3.0, -7.0, 12.0), (-300.0, 255.0, 256.0)] {
+            assert_eq!(perlin.noise(&Vec3::new(x, y, z)), 0.0);
+        }
+        for p in points(2) {
+            assert!(perlin.noise(&p).abs() <= 1.1, "{:?}", p);
+            let turbulence = perlin.fractal(&p, 4, true);
+            assert!((0.0..=1.1).contains(&turbulence), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn small_steps_change_the_noise_a_little() {
+        let perlin = Perlin::new(5);
+        let step = 1e-3;
+        let mut rng = Rng::new(3);
+        // Including across the edges of the cells, where the corner hashes change
+        let mut points = points(4);
+        points.extend((-20..20).map(|i| Vec3::new(i as f32 - step / 2.0, 0.5, 0.25)));
+        for p in points {
+            let direction = Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()).map(|c| c - 0.5).normalize();
+            for (a, b) in [(perlin.noise(&p), perlin.noise(&(p + direction * step))), (perlin.fractal(&p, 4, false), perlin.fractal(&(p + direction * step), 4, false))] {
+                assert!((a - b).abs() < 0.02, "{} and {} at {:?}", a, b, p);
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+// Darkens the ambient light in creases and corners by checking how much of the
+// hemisphere above each hit point is blocked by nearby geometry
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmbientOcclusion {
+    pub samples: u32,  // Rays per hit point
+    pub distance: f32, // Occluders further away than this don't count
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            samples: 16,
+            distance: 1.0,
+        }
+    }
+}
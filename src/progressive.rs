@@ -0,0 +1,56 @@
+// The progressive preview traces each new view coarsely first, so something shows up
+// right away, then finer frame by frame while the view stays still: at these fractions of
+// the window's resolution, then at full resolution
+const PREVIEW_SCALES: [usize; 2] = [4, 2];
+
+// What one frame of the preview renders: the image at 1 / `scale` of the window's
+// resolution, enlarged to fill it, and the pass of the path tracer it is. Only frames at
+// full resolution are passes averaged into the image, every coarser one is pass 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviewLevel {
+    pub scale: usize,
+    pub pass: u32,
+}
+
+impl PreviewLevel {
+    // Every frame at full resolution, as without the preview
+    pub fn full(pass: u32) -> Self {
+        PreviewLevel { scale: 1, pass }
+    }
+
+    pub fn is_coarse(&self) -> bool {
+        self.scale > 1
+    }
+}
+
+// The level of the `frame`th frame since the view last changed, counting from 0
+pub fn preview_level(frame: u32) -> PreviewLevel {
+    match PREVIEW_SCALES.get(frame as usize) {
+        Some(&scale) => PreviewLevel { scale, pass: 0 },
+        None => PreviewLevel::full(frame - PREVIEW_SCALES.len() as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_starts_coarse_and_sharpens() {
+        assert_eq!(preview_level(0), PreviewLevel { scale: 4, pass: 0 });
+        assert_eq!(preview_level(1), PreviewLevel { scale: 2, pass: 0 });
+        assert!(preview_level(0).is_coarse() && preview_level(1).is_coarse());
+        assert_eq!(preview_level(2), PreviewLevel::full(0));
+        assert!(!preview_level(2).is_coarse());
+    }
+
+    #[test]
+    fn full_resolution_frames_count_passes_from_0() {
+        for frame in 2..300 {
+            assert_eq!(preview_level(frame), PreviewLevel::full(frame - 2));
+        }
+        // The scale never goes back up once the preview reaches full resolution
+        let scales: Vec<usize> = (0..10).map(|frame| preview_level(frame).scale).collect();
+        assert!(scales.windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", scales);
+    }
+}
@@ -1,12 +1,147 @@
 use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use crate::color::Color;
+use crate::noise::Noise;
+use crate::texture::Texture;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Material {
     pub diffuse: Color,
+    #[serde(default)]
+    pub specular: f32, // Strength of the white Phong highlight, 0 for none
+    #[serde(default)]
+    pub shininess: f32, // Phong exponent, higher values give smaller highlights
+    // How strongly the surface mirrors its surroundings, 0 to 1, and how blurry the mirror
+    // image is, from 0 for a perfect mirror to 1 for barely a reflection at all. Like real
+    // varnish or plastic, the reflection is faint head-on and grows towards grazing angles.
+    #[serde(default)]
+    pub reflectivity: f32,
+    #[serde(default)]
+    pub roughness: f32,
+    // 1 for metals, which mirror everything tinted by their diffuse color and don't scatter
+    // any light diffusely, 0 for everything else; values in between mix the two
+    #[serde(default)]
+    pub metallic: f32,
+    // Light given off by the surface, as a color scaled by a strength that may exceed 1.
+    // These are the only light sources when path tracing.
+    #[serde(default)]
+    pub emission: Color,
+    #[serde(default)]
+    pub emission_strength: f32,
+    // Replaces the emission color on surfaces that provide texture coordinates, for
+    // glowing patterns. It is scaled by the same strength.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission_texture: Option<Texture>,
+    // Tangent-space normal map, only used on surfaces that provide texture coordinates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<Texture>,
+    // A procedural pattern painted over the diffuse color, e.g. for marble. It's shared
+    // between clones, since every hit gets a copy of the material.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<Arc<Noise>>,
+    // Whether surfaces without an inside, disks and mesh triangles, can be seen from behind,
+    // with the normal turned to face the ray. One-sided ones are only hit from the side
+    // their normal faces, rays reaching their back pass through.
+    #[serde(default = "double_sided", skip_serializing_if = "is_double_sided")]
+    pub double_sided: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            diffuse: Color::default(),
+            specular: 0.0,
+            shininess: 0.0,
+            reflectivity: 0.0,
+            roughness: 0.0,
+            metallic: 0.0,
+            emission: Color::default(),
+            emission_strength: 0.0,
+            emission_texture: None,
+            normal_map: None,
+            noise: None,
+            double_sided: true,
+        }
+    }
+}
+
+fn double_sided() -> bool {
+    true
+}
+
+fn is_double_sided(double_sided: &bool) -> bool {
+    *double_sided
+}
+
+// A half-line from `origin` along `direction`. The constructors make the direction a unit
+// vector, so t along the ray is a distance; only transforms build rays with other lengths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    // The moment the ray sees moving objects at, from 0 when the shutter opens to 1 when
+    // it closes. Rays traced on from a hit keep the time of the ray that made it.
+    pub time: f32,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        debug_assert!(direction.magnitude_squared() > 0.0, "ray direction has zero length");
+        Ray {
+            origin,
+            direction: direction.normalize(),
+            time: 0.0,
+        }
+    }
+
+    pub fn with_time(self, time: f32) -> Self {
+        Ray { time, ..self }
+    }
+
+    // The ray from `origin` through `target`, and the distance between them
+    pub fn between(origin: Vec3, target: Vec3) -> (Self, f32) {
+        let ray = Ray::new(origin, target - origin);
+        (ray, (target - origin).magnitude())
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+// A bundle of rays laid out as one contiguous array per coordinate, for intersection
+// routines that handle many rays at once and want loops the compiler can vectorize
+#[derive(Debug, Clone, Default)]
+pub struct RayPacket {
+    pub rays: Vec<Ray>,
+    pub origins: [Vec<f32>; 3],
+    pub directions: [Vec<f32>; 3],
+    pub times: Vec<f32>,
+}
+
+impl RayPacket {
+    pub fn new(rays: &[Ray]) -> Self {
+        let component = |axis: usize, of: fn(&Ray) -> Vec3| rays.iter().map(|ray| of(ray)[axis]).collect();
+        RayPacket {
+            rays: rays.to_vec(),
+            origins: [0, 1, 2].map(|axis| component(axis, |ray| ray.origin)),
+            directions: [0, 1, 2].map(|axis| component(axis, |ray| ray.direction)),
+            times: rays.iter().map(|ray| ray.time).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rays.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Intersect {
     pub point: Vec3,
@@ -14,6 +149,10 @@ pub struct Intersect {
     pub distance: f32,
     pub is_intersecting: bool,
     pub material: Material,
+    // Texture coordinates and the surface direction in which u grows, for surfaces
+    // that have them
+    pub uv: Option<(f32, f32)>,
+    pub tangent: Vec3,
 }
 
 impl Intersect {
@@ -24,22 +163,48 @@ impl Intersect {
             distance,
             is_intersecting: true,
             material,
+            uv: None,
+            tangent: Vec3::zeros(),
         }
     }
 
+    pub fn with_uv(mut self, u: f32, v: f32, tangent: Vec3) -> Self {
+        self.uv = Some((u, v));
+        self.tangent = tangent;
+        self
+    }
+
     pub fn empty() -> Self {
         Intersect {
             point: Vec3::zeros(),
             normal: Vec3::zeros(),
             distance: 0.0,
             is_intersecting: false,
-            material: Material{
-              diffuse: Color::new(0, 0, 0),
-            },
+            material: Material::default(),
+            uv: None,
+            tangent: Vec3::zeros(),
         }
     }
 }
 
+// A stretch of a ray inside a solid, from the hit where it enters to the one where it
+// leaves. Both normals point out of the solid.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub enter: Intersect,
+    pub exit: Intersect,
+}
+
 pub trait RayIntersect {
-  fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect;
+  // The nearest hit at a distance strictly between `t_min` and `t_max`, in units of the
+  // ray direction's length. A `t_min` above zero keeps rays leaving a surface from hitting it
+  // again, a finite `t_max` stops shadow rays at the light.
+  fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect;
+
+  // Every stretch of the whole line through the ray that is inside the object, in order
+  // along it, which is what CSG combines. Only closed surfaces have an inside, the rest
+  // return None.
+  fn ray_spans(&self, _ray: &Ray) -> Option<Vec<Span>> {
+      None
+  }
 }
\ No newline at end of file
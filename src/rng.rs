@@ -0,0 +1,138 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+
+use crate::render::perpendicular_basis;
+
+// A small deterministic random number generator (xorshift64*). Renders seed it from
+// pixel coordinates and the render's seed, so the same settings always produce the same
+// noise, whichever thread or tile a pixel is traced on.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed with a SplitMix64 step first: neighbouring pixels get
+        // neighbouring seeds, and xorshift needs a nonzero state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 1 } else { z } }
+    }
+
+    // A generator for one of many independent streams of a render, e.g. one per pixel.
+    // Seed 0 gives the streams renders had before they were seedable.
+    pub fn for_stream(seed: u64, stream: u64) -> Self {
+        Rng::new(stream ^ seed.wrapping_mul(0xD6E8_FEB8_6659_FD93))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Uniform in [0, 1), using the top 24 bits so every value is exactly representable
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // A point spread evenly over the disk of radius 1 around the origin, for lenses and
+    // round lights
+    pub fn in_unit_disk(&mut self) -> (f32, f32) {
+        let r = self.next_f32().sqrt();
+        let angle = 2.0 * PI * self.next_f32();
+        (r * angle.cos(), r * angle.sin())
+    }
+
+    // A direction above the surface with the given unit normal, more likely the closer it
+    // is to the normal, in proportion to the cosine of the angle between them like the light
+    // a diffuse surface scatters
+    pub fn cosine_hemisphere(&mut self, normal: &Vec3) -> Vec3 {
+        let (x, y) = self.in_unit_disk();
+        let (tangent, bitangent) = perpendicular_basis(normal);
+        (tangent * x + bitangent * y + normal * (1.0 - x * x - y * y).max(0.0).sqrt()).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::render::{render, render_tile, tiles, write_tile, Integrator, RenderSettings};
+    use crate::scene::{Scene, DEFAULT_SCENE};
+
+    // The bear path traced, where every sample takes random bounces
+    fn path_traced(width: usize, height: usize, seed: u64) -> Framebuffer {
+        let (scene, settings) = stochastic(seed);
+        let mut framebuffer = Framebuffer::new(width, height);
+        render(&mut framebuffer, &scene, &scene.camera, &settings);
+        framebuffer
+    }
+
+    fn stochastic(seed: u64) -> (Scene, RenderSettings) {
+        let mut scene = Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap();
+        // Through a wide lens too, so every sample also lands somewhere else on it
+        scene.camera.aperture = 0.1;
+        let settings = RenderSettings { integrator: Integrator::PathTraced { samples: 2 }, seed, ..RenderSettings::default() };
+        (scene, settings)
+    }
+
+    // `path_traced` with its tiles shared out between `threads` threads, written into the
+    // image in the order the threads finish them
+    fn path_traced_on_threads(width: usize, height: usize, seed: u64, threads: usize) -> Framebuffer {
+        let (scene, settings) = stochastic(seed);
+        let tiles = tiles(width, height, None);
+        let finished = std::sync::Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                let (tiles, finished, scene, settings) = (&tiles, &finished, &scene, &settings);
+                scope.spawn(move || {
+                    for tile in tiles.iter().skip(thread).step_by(threads) {
+                        let (radiance, _) = render_tile(width, height, tile, scene, &scene.camera, settings, 0);
+                        finished.lock().unwrap().push((*tile, radiance));
+                    }
+                });
+            }
+        });
+        let mut framebuffer = Framebuffer::new(width, height);
+        for (tile, radiance) in finished.into_inner().unwrap() {
+            write_tile(&mut framebuffer, &tile, &radiance, 0, &settings);
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn same_seed_renders_the_same_image() {
+        assert_eq!(path_traced(32, 24, 7).buffer, path_traced(32, 24, 7).buffer);
+        assert_ne!(path_traced(32, 24, 7).buffer, path_traced(32, 24, 8).buffer);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_numbers() {
+        let numbers = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..16).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(numbers(3), numbers(3));
+        assert_ne!(numbers(3), numbers(4));
+        // Streams of one seed differ from each other
+        assert_ne!(Rng::for_stream(3, 0).next_u64(), Rng::for_stream(3, 1).next_u64());
+    }
+
+    #[test]
+    fn threads_render_the_same_image_as_one() {
+        let once = path_traced(64, 64, 7);
+        // Stochastic frames twice over come out byte for byte the same
+        assert_eq!(path_traced(64, 64, 7).buffer, once.buffer);
+        // And with the tiles rendered in parallel, more threads than there are tiles included
+        for threads in [1, 3, 8] {
+            let parallel = path_traced_on_threads(64, 64, 7, threads);
+            assert_eq!(parallel.buffer, once.buffer, "{} threads", threads);
+            assert_eq!(parallel.radiance, once.radiance, "{} threads", threads);
+        }
+    }
+}
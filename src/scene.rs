@@ -0,0 +1,894 @@
+use nalgebra_glm::Vec3;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::aabb::Aabb;
+use crate::animation::{LightOrbit, Track};
+use crate::background::Background;
+use crate::blob::Blob;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::csg::Csg;
+use crate::cube::Cube;
+use crate::disk::Disk;
+use crate::fog::Fog;
+use crate::instance::{resolve_instances, Instance, Prototype};
+use crate::light::Light;
+use crate::mesh::Mesh;
+use crate::occlusion::AmbientOcclusion;
+use crate::ray_intersect::{Intersect, Material, Ray, RayIntersect, RayPacket, Span};
+use crate::sdf::{Sdf, SdfObject};
+use crate::shadow::ShadowFilter;
+use crate::sphere::{is_still, Sphere};
+use crate::torus::Torus;
+use crate::transform::Transform;
+
+// The bear face shown when the program is started without a scene file
+pub const DEFAULT_SCENE: &str = include_str!("../scenes/teddy.ron");
+
+// Distance secondary rays start off the surface when a scene doesn't set its own
+pub const DEFAULT_SHADOW_BIAS: f32 = 1e-4;
+
+// Every primitive that can appear in a scene file, written as e.g. `Sphere(center: ..., ...)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Object {
+    Sphere(Sphere),
+    Disk(Disk),
+    Torus(Torus),
+    Cube(Cube),
+    Mesh(Mesh),
+    Blob(Blob),
+    Sdf(SdfObject),
+    Csg(Csg),
+    // Any object placed with a matrix: the ray is moved into the object's own space,
+    // intersected there, and the hit is moved back out
+    Transformed { transform: Transform, object: Box<Object> },
+    // A placed copy of one of the scene's prototypes
+    Instance(Instance),
+    // Objects placed together, given relative to the group's own origin. Groups can hold
+    // groups, whose transforms then apply on top of their parent's. A name lets the
+    // animation find the group, like the bear's eyes. The velocity moves the whole group
+    // over the shutter interval like a sphere's does; the animation sets it to blur groups
+    // it moves.
+    Group {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default)]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "is_still")]
+        velocity: Vec3,
+        objects: Vec<Object>,
+    },
+}
+
+// An object found under the mouse: which of the scene's objects it's part of, where it is
+// inside that one as the index of each group's object leading down to it, what kind of
+// object it is, the named groups around it, outermost first, and the hit on it
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub index: usize,
+    pub path: Vec<usize>,
+    pub kind: &'static str,
+    pub groups: Vec<String>,
+    pub intersect: Intersect,
+}
+
+// The parts of a group that animating it changes
+pub struct GroupMut<'a> {
+    pub transform: &'a mut Transform,
+    pub velocity: &'a mut Vec3,
+    pub objects: &'a mut [Object],
+}
+
+impl RayIntersect for Object {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        match self {
+            Object::Sphere(sphere) => sphere.ray_intersect(ray, t_min, t_max),
+            Object::Disk(disk) => disk.ray_intersect(ray, t_min, t_max),
+            Object::Torus(torus) => torus.ray_intersect(ray, t_min, t_max),
+            Object::Cube(cube) => cube.ray_intersect(ray, t_min, t_max),
+            Object::Mesh(mesh) => mesh.ray_intersect(ray, t_min, t_max),
+            Object::Blob(blob) => blob.ray_intersect(ray, t_min, t_max),
+            Object::Sdf(sdf) => sdf.ray_intersect(ray, t_min, t_max),
+            Object::Csg(csg) => csg.ray_intersect(ray, t_min, t_max),
+            Object::Transformed { transform, object } => {
+                transform.intersect(ray, |local_ray| object.ray_intersect(local_ray, t_min, t_max))
+            }
+            Object::Instance(instance) => instance.ray_intersect(ray, t_min, t_max),
+            Object::Group { transform, velocity, objects, .. } => {
+                let shift = velocity * ray.time;
+                let still_ray = Ray { origin: ray.origin - shift, ..*ray };
+                let mut intersect =
+                    transform.intersect(&still_ray, |local_ray| nearest_intersect(objects, local_ray, t_min, t_max));
+                intersect.point += shift;
+                intersect
+            }
+        }
+    }
+
+    fn ray_spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        match self {
+            Object::Sphere(sphere) => sphere.ray_spans(ray),
+            Object::Cube(cube) => cube.ray_spans(ray),
+            Object::Csg(csg) => csg.ray_spans(ray),
+            Object::Transformed { transform, object } => transform.spans(ray, |local_ray| object.ray_spans(local_ray)),
+            _ => None,
+        }
+    }
+}
+
+// The closest hit on any of the objects between `t_min` and `t_max`
+pub fn nearest_intersect(objects: &[Object], ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+    let mut nearest = Intersect::empty();
+    for object in objects {
+        let t_max = if nearest.is_intersecting { nearest.distance } else { t_max };
+        let intersect = object.ray_intersect(ray, t_min, t_max);
+        if intersect.is_intersecting {
+            nearest = intersect;
+        }
+    }
+    nearest
+}
+
+// `nearest_intersect` for every ray of a packet at once, with the same hits bit for bit.
+// `t_max` starts out as each ray's nearest hit so far and is lowered along with `nearest`,
+// which also gets the index among `objects` of what was hit.
+fn nearest_intersect_batch(objects: &[Object], packet: &RayPacket, t_min: f32, nearest: &mut [(Intersect, Option<usize>)], t_max: &mut [f32]) {
+    let mut roots = vec![0.0; packet.len()];
+    for (i, object) in objects.iter().enumerate() {
+        match object {
+            Object::Sphere(sphere) => {
+                roots.copy_from_slice(t_max);
+                sphere.nearest_roots(packet, t_min, &mut roots);
+                for (ray, ((hit, index), (root, t_max))) in packet.rays.iter().zip(nearest.iter_mut().zip(roots.iter().zip(t_max.iter_mut()))) {
+                    if root < t_max {
+                        *hit = sphere.hit_at(ray, *root);
+                        *index = Some(i);
+                        *t_max = *root;
+                    }
+                }
+            }
+            // The packet moves into the group's space as each of its rays would on its own
+            Object::Group { transform, velocity, objects, .. } => {
+                let still: Vec<Ray> = packet
+                    .rays
+                    .iter()
+                    .map(|ray| Ray { origin: ray.origin - velocity * ray.time, ..*ray })
+                    .collect();
+                let local = if transform.is_identity {
+                    RayPacket::new(&still)
+                } else {
+                    RayPacket::new(&still.iter().map(|ray| transform.ray_to_local(ray)).collect::<Vec<_>>())
+                };
+                let mut inner: Vec<(Intersect, Option<usize>)> = packet.rays.iter().map(|_| (Intersect::empty(), None)).collect();
+                let mut inner_t_max = t_max.to_vec();
+                nearest_intersect_batch(objects, &local, t_min, &mut inner, &mut inner_t_max);
+                for (k, (intersect, inner_index)) in inner.into_iter().enumerate() {
+                    if inner_index.is_none() {
+                        continue;
+                    }
+                    let mut intersect = if transform.is_identity { intersect } else { transform.hit_to_world(&still[k], intersect) };
+                    intersect.point += velocity * packet.rays[k].time;
+                    t_max[k] = intersect.distance;
+                    nearest[k] = (intersect, Some(i));
+                }
+            }
+            _ => {
+                for (ray, ((hit, index), t_max)) in packet.rays.iter().zip(nearest.iter_mut().zip(t_max.iter_mut())) {
+                    let intersect = object.ray_intersect(ray, t_min, *t_max);
+                    if intersect.is_intersecting {
+                        *t_max = intersect.distance;
+                        *hit = intersect;
+                        *index = Some(i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The object inside `object` that `ray` hits first, going into transforms and groups the
+// way intersecting them does. Fills in the index within each group on the way down to it
+// and the names of the named ones, outermost first. Anything else counts as a single
+// object, like an instance or a CSG shape.
+fn hit_part<'a>(object: &'a Object, ray: &Ray, t_min: f32, path: &mut Vec<usize>, groups: &mut Vec<String>) -> &'a Object {
+    match object {
+        Object::Transformed { transform, object } => hit_part(object, &transform.ray_to_local(ray), t_min, path, groups),
+        Object::Group { name, transform, velocity, objects } => {
+            let still_ray = Ray { origin: ray.origin - velocity * ray.time, ..*ray };
+            let local_ray = transform.ray_to_local(&still_ray);
+            let mut nearest = (f32::INFINITY, None);
+            for (i, object) in objects.iter().enumerate() {
+                let intersect = object.ray_intersect(&local_ray, t_min, nearest.0);
+                if intersect.is_intersecting {
+                    nearest = (intersect.distance, Some(i));
+                }
+            }
+            let Some(i) = nearest.1 else {
+                return object;
+            };
+            path.push(i);
+            groups.extend(name.clone());
+            hit_part(&objects[i], &local_ray, t_min, path, groups)
+        }
+        _ => object,
+    }
+}
+
+// The object at the end of a pick's path inside `object`, see `hit_part`
+fn part_mut<'a>(object: &'a mut Object, path: &[usize]) -> Option<&'a mut Object> {
+    match (object, path.split_first()) {
+        (Object::Transformed { object, .. }, _) => part_mut(object, path),
+        (Object::Group { objects, .. }, Some((&i, rest))) => part_mut(objects.get_mut(i)?, rest),
+        (object, None) => Some(object),
+        _ => None,
+    }
+}
+
+// The world space box around that object, given the box around `object`'s own space. The
+// motion of groups isn't included.
+fn part_bounds(object: &Object, path: &[usize]) -> Option<Aabb> {
+    match (object, path.split_first()) {
+        (Object::Transformed { transform, object }, _) => Some(transform.bounds_to_world(&part_bounds(object, path)?)),
+        (Object::Group { transform, objects, .. }, Some((&i, rest))) => {
+            Some(transform.bounds_to_world(&part_bounds(objects.get(i)?, rest)?))
+        }
+        (object, None) => Some(object.bounding_box()),
+        _ => None,
+    }
+}
+
+// The first group called `name` among the objects, depth first
+fn find_group<'a>(objects: &'a mut [Object], name: &str) -> Option<GroupMut<'a>> {
+    objects.iter_mut().find_map(|object| match object {
+        Object::Group { name: group_name, transform, velocity, objects } => {
+            if group_name.as_deref() == Some(name) {
+                Some(GroupMut { transform, velocity, objects })
+            } else {
+                find_group(objects, name)
+            }
+        }
+        _ => None,
+    })
+}
+
+// The box around each named group among the objects, in the objects' own space, outermost
+// first. Groups placed by an instance are its prototype's and aren't included.
+fn named_bounds(objects: &[Object]) -> Vec<(String, Aabb)> {
+    objects
+        .iter()
+        .flat_map(|object| match object {
+            Object::Transformed { transform, object } => named_bounds(std::slice::from_ref(&**object))
+                .into_iter()
+                .map(|(name, bounds)| (name, transform.bounds_to_world(&bounds)))
+                .collect(),
+            Object::Group { name, transform, objects, .. } => {
+                let own = name.clone().map(|name| (name, bounds_of(objects)));
+                own.into_iter()
+                    .chain(named_bounds(objects))
+                    .map(|(name, bounds)| (name, transform.bounds_to_world(&bounds)))
+                    .collect()
+            }
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+// The box around all of the objects, empty when there are none
+pub fn bounds_of(objects: &[Object]) -> Aabb {
+    objects.iter().fold(Aabb::empty(), |bounds, object| bounds.union(&object.bounding_box()))
+}
+
+impl Object {
+    // A box around all of the object in world space. Transformed objects get the box around
+    // their transformed local box, which may be looser than needed.
+    pub fn bounding_box(&self) -> Aabb {
+        match self {
+            Object::Sphere(sphere) => sphere.bounding_box(),
+            Object::Disk(disk) => disk.bounding_box(),
+            Object::Torus(torus) => torus.bounding_box(),
+            Object::Cube(cube) => cube.bounding_box(),
+            Object::Mesh(mesh) => mesh.bounding_box(),
+            Object::Blob(blob) => blob.bounding_box(),
+            Object::Sdf(sdf) => sdf.bounding_box(),
+            Object::Csg(csg) => csg.bounding_box(),
+            Object::Transformed { transform, object } => transform.bounds_to_world(&object.bounding_box()),
+            Object::Instance(instance) => instance.bounding_box(),
+            Object::Group { transform, velocity, objects, .. } => {
+                let bounds = transform.bounds_to_world(&bounds_of(objects));
+                bounds.union(&Aabb { min: bounds.min + velocity, max: bounds.max + velocity })
+            }
+        }
+    }
+
+    // Paints the object in `color`, or every object inside it. Instances share their
+    // prototype's objects, so only ones with a material of their own change, and so do
+    // only the faces of a mesh that use its material rather than one from the OBJ file.
+    pub fn set_diffuse(&mut self, color: Color) {
+        self.for_each_material(&mut |material| material.diffuse = color);
+    }
+
+    // Sets how brightly the object glows, or every object inside it, with the same
+    // exceptions as `set_diffuse`
+    pub fn set_emission_strength(&mut self, strength: f32) {
+        self.for_each_material(&mut |material| material.emission_strength = strength);
+    }
+
+    fn for_each_material(&mut self, change: &mut impl FnMut(&mut Material)) {
+        match self {
+            Object::Sphere(sphere) => change(&mut sphere.material),
+            Object::Disk(disk) => change(&mut disk.material),
+            Object::Torus(torus) => change(&mut torus.material),
+            Object::Cube(cube) => change(&mut cube.material),
+            Object::Mesh(mesh) => change(&mut mesh.material),
+            Object::Blob(blob) => change(&mut blob.material),
+            Object::Sdf(sdf) => change(&mut sdf.material),
+            Object::Csg(csg) => {
+                csg.left.for_each_material(change);
+                csg.right.for_each_material(change);
+            }
+            Object::Transformed { object, .. } => object.for_each_material(change),
+            Object::Instance(instance) => {
+                if let Some(material) = &mut instance.material {
+                    change(material);
+                }
+            }
+            Object::Group { objects, .. } => objects.iter_mut().for_each(|object| object.for_each_material(change)),
+        }
+    }
+
+    // What kind of object it is, as scene files name it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::Sphere(_) => "sphere",
+            Object::Disk(_) => "disk",
+            Object::Torus(_) => "torus",
+            Object::Cube(_) => "cube",
+            Object::Mesh(_) => "mesh",
+            Object::Blob(_) => "blob",
+            Object::Sdf(_) => "sdf",
+            Object::Csg(_) => "csg",
+            Object::Transformed { .. } => "transformed",
+            Object::Instance(_) => "instance",
+            Object::Group { .. } => "group",
+        }
+    }
+
+    // Whether the object has an inside that CSG can combine, which `ray_spans` reports
+    pub fn is_solid(&self) -> bool {
+        match self {
+            Object::Sphere(_) | Object::Cube(_) => true,
+            Object::Csg(csg) => csg.left.is_solid() && csg.right.is_solid(),
+            Object::Transformed { object, .. } => object.is_solid(),
+            _ => false,
+        }
+    }
+}
+
+// Whether every CSG node among the objects, including ones inside transforms and groups,
+// combines solids
+fn csg_is_solid(objects: &[Object]) -> bool {
+    objects.iter().all(|object| match object {
+        Object::Csg(_) => object.is_solid(),
+        Object::Transformed { object, .. } => csg_is_solid(std::slice::from_ref(object.as_ref())),
+        Object::Group { objects, .. } => csg_is_solid(objects),
+        _ => true,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    pub background: Background,
+    pub camera: Camera,
+    // Preset viewpoints the number keys switch between
+    #[serde(default)]
+    pub viewpoints: Vec<Camera>,
+    // Groups of objects defined once for `Instance` objects to place copies of
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prototypes: Vec<Prototype>,
+    pub objects: Vec<Object>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow_filter: Option<ShadowFilter>,
+    // Hits closer than this to the start of a ray are ignored, so shadow and bounce rays
+    // don't hit the surface they leave straight away. Too small gives speckled
+    // self-shadowing ("acne") on curved surfaces, too large lets shadows and reflections
+    // skip thin gaps; it scales with the scene.
+    #[serde(default = "default_shadow_bias")]
+    pub shadow_bias: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fog: Option<Fog>,
+    // Keyframes moving named groups while the animation plays
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tracks: Vec<Track>,
+    // Lights going round in circles while the animation plays
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub light_orbits: Vec<LightOrbit>,
+}
+
+fn default_shadow_bias() -> f32 {
+    DEFAULT_SHADOW_BIAS
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: String, source: io::Error },
+    Parse { path: String, source: Box<ron::error::SpannedError> },
+    Serialize { path: String, source: ron::Error },
+    UnknownPrototype { path: String, name: String }, // An instance of a prototype that isn't defined
+    InvalidTrack { path: String, group: String, reason: String },
+    InvalidLightOrbit { path: String, light: usize, reason: String },
+    OpenCsg { path: String }, // A CSG node with a child that has no inside, like a disk
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "could not access scene file '{}': {}", path, source),
+            // The ron error carries the line:column of the offending entry and names the
+            // struct and field that were unknown or missing
+            SceneError::Parse { path, source } => write!(f, "invalid scene '{}' at {}", path, source),
+            SceneError::Serialize { path, source } => write!(f, "could not serialize scene '{}': {}", path, source),
+            SceneError::UnknownPrototype { path, name } => {
+                write!(f, "invalid scene '{}': no prototype named '{}' is defined before it's used", path, name)
+            }
+            SceneError::InvalidTrack { path, group, reason } => {
+                write!(f, "invalid scene '{}': the track for '{}' {}", path, group, reason)
+            }
+            SceneError::InvalidLightOrbit { path, light, reason } => {
+                write!(f, "invalid scene '{}': the orbit of light {} {}", path, light, reason)
+            }
+            SceneError::OpenCsg { path } => write!(
+                f,
+                "invalid scene '{}': CSG can only combine spheres, cubes and other CSG shapes, or those transformed",
+                path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Scene {
+    // An empty scene seen from `camera`, with the defaults a scene file gets for everything
+    // it leaves out
+    pub fn new(background: Background, camera: Camera) -> Scene {
+        Scene {
+            background,
+            camera,
+            viewpoints: Vec::new(),
+            prototypes: Vec::new(),
+            objects: Vec::new(),
+            lights: Vec::new(),
+            ambient_occlusion: None,
+            shadow_filter: None,
+            shadow_bias: DEFAULT_SHADOW_BIAS,
+            fog: None,
+            tracks: Vec::new(),
+            light_orbits: Vec::new(),
+        }
+    }
+
+    pub fn add_object(&mut self, object: Object) {
+        self.objects.push(object);
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    // The closest hit along a ray, ignoring hits within the shadow bias of its origin
+    pub fn intersect(&self, ray: &Ray) -> Intersect {
+        self.intersect_object(ray).0
+    }
+
+    // The closest hit along with the index of the object that was hit
+    pub fn intersect_object(&self, ray: &Ray) -> (Intersect, Option<usize>) {
+        let mut nearest = Intersect::empty();
+        let mut index = None;
+        for (i, object) in self.objects.iter().enumerate() {
+            // Only hits nearer than the nearest so far are reported
+            let t_max = if nearest.is_intersecting { nearest.distance } else { f32::INFINITY };
+            let intersect = object.ray_intersect(ray, self.shadow_bias, t_max);
+            if intersect.is_intersecting {
+                nearest = intersect;
+                index = Some(i);
+            }
+        }
+        (nearest, index)
+    }
+
+    // What `ray` hits first, for telling which object the mouse points at. `None` over the
+    // background.
+    pub fn pick(&self, ray: &Ray) -> Option<Pick> {
+        let (intersect, index) = self.intersect_object(ray);
+        let index = index?;
+        let (mut path, mut groups) = (Vec::new(), Vec::new());
+        let kind = hit_part(&self.objects[index], ray, self.shadow_bias, &mut path, &mut groups).kind();
+        Some(Pick { index, path, kind, groups, intersect })
+    }
+
+    // The object a pick found, or `None` once the scene no longer has it
+    pub fn picked_mut(&mut self, pick: &Pick) -> Option<&mut Object> {
+        part_mut(self.objects.get_mut(pick.index)?, &pick.path)
+    }
+
+    pub fn picked_bounds(&self, pick: &Pick) -> Option<Aabb> {
+        part_bounds(self.objects.get(pick.index)?, &pick.path)
+    }
+
+    // `intersect_object` for a packet of rays, e.g. a row of primary rays. Spheres test the
+    // whole packet at once and only build a hit for the rays they are nearest along, also
+    // inside groups; every other object still goes ray by ray.
+    pub fn intersect_object_batch(&self, packet: &RayPacket) -> Vec<(Intersect, Option<usize>)> {
+        let mut nearest: Vec<(Intersect, Option<usize>)> = packet.rays.iter().map(|_| (Intersect::empty(), None)).collect();
+        let mut t_max = vec![f32::INFINITY; packet.len()];
+        nearest_intersect_batch(&self.objects, packet, self.shadow_bias, &mut nearest, &mut t_max);
+        nearest
+    }
+
+    // Whether anything lies along a ray leaving a surface before `distance`
+    pub fn is_blocked(&self, ray: &Ray, distance: f32) -> bool {
+        self.objects
+            .iter()
+            .any(|object| object.ray_intersect(ray, self.shadow_bias, distance).is_intersecting)
+    }
+
+    pub fn load(path: &str) -> Result<Scene, SceneError> {
+        let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        Scene::parse(&source, path)
+    }
+
+    // Moves the scene's first group by `offset` and turns it by `degrees` about the vertical
+    // axis through its own origin. Returns false when the scene has no group to move.
+    pub fn move_group(&mut self, offset: Vec3, degrees: f32) -> bool {
+        let Some(GroupMut { transform, .. }) = self.first_group() else {
+            return false;
+        };
+        *transform = transform.moved(offset, degrees);
+        true
+    }
+
+    // The first group among the scene's objects, not counting ones inside others
+    pub fn first_group(&mut self) -> Option<GroupMut<'_>> {
+        self.objects.iter_mut().find_map(|object| match object {
+            Object::Group { transform, velocity, objects, .. } => Some(GroupMut { transform, velocity, objects }),
+            _ => None,
+        })
+    }
+
+    // The group called `name`, looking inside groups too
+    pub fn named_group(&mut self, name: &str) -> Option<GroupMut<'_>> {
+        find_group(&mut self.objects, name)
+    }
+
+    // The box around every object, empty for a scene without any
+    pub fn bounding_box(&self) -> Aabb {
+        bounds_of(&self.objects)
+    }
+
+    // The points Tab turns the camera to in turn: the one the scene's camera looks at, then
+    // the middle of each named group, like the bear's nose and ears. Scenes without named
+    // groups get the middle of each of their objects instead.
+    pub fn points_of_interest(&self) -> Vec<(String, Vec3)> {
+        let mut points = vec![("the start".to_string(), self.camera.center)];
+        let named = named_bounds(&self.objects);
+        if named.is_empty() {
+            points.extend(self.objects.iter().enumerate().map(|(i, object)| {
+                (format!("{} {}", object.kind(), i + 1), object.bounding_box().center())
+            }));
+        } else {
+            points.extend(named.into_iter().map(|(name, bounds)| (name, bounds.center())));
+        }
+        // Empty and endless objects have no middle to look at
+        points.retain(|(_, point)| point.iter().all(|c| c.is_finite()));
+        points
+    }
+
+    // `name` is only used to label errors
+    pub fn parse(source: &str, name: &str) -> Result<Scene, SceneError> {
+        let mut scene: Scene = ron_options().from_str(source).map_err(|source| SceneError::Parse {
+            path: name.to_string(),
+            source: Box::new(source),
+        })?;
+        scene.resolve_instances().map_err(|prototype| SceneError::UnknownPrototype {
+            path: name.to_string(),
+            name: prototype,
+        })?;
+        let prototypes = scene.prototypes.iter().map(|prototype| prototype.objects.as_slice());
+        if !std::iter::once(scene.objects.as_slice()).chain(prototypes).all(csg_is_solid) {
+            return Err(SceneError::OpenCsg { path: name.to_string() });
+        }
+        for track in &scene.tracks {
+            let invalid = |reason: &str| SceneError::InvalidTrack {
+                path: name.to_string(),
+                group: track.group.clone(),
+                reason: reason.to_string(),
+            };
+            track.check().map_err(invalid)?;
+            if find_group(&mut scene.objects, &track.group).is_none() {
+                return Err(invalid("has no group of that name to move"));
+            }
+        }
+        for orbit in &scene.light_orbits {
+            let invalid = |reason: &str| SceneError::InvalidLightOrbit {
+                path: name.to_string(),
+                light: orbit.light,
+                reason: reason.to_string(),
+            };
+            orbit.check().map_err(invalid)?;
+            match scene.lights.get(orbit.light) {
+                None => return Err(invalid("is for a light the scene doesn't have")),
+                Some(light) if light.position().is_none() => {
+                    return Err(invalid("is for a directional light, which has no position to move"))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(scene)
+    }
+
+    // Points every instance at its prototype's objects. Prototypes may place instances of
+    // the ones defined before them, which also rules out cycles.
+    fn resolve_instances(&mut self) -> Result<(), String> {
+        for i in 0..self.prototypes.len() {
+            let (earlier, rest) = self.prototypes.split_at_mut(i);
+            let objects: &mut Vec<Object> = Arc::make_mut(&mut rest[0].objects);
+            resolve_instances(objects, earlier)?;
+        }
+        resolve_instances(&mut self.objects, &self.prototypes)
+    }
+
+    // The classic Cornell box: a 2 x 2 x 2 room open towards the camera with a red left wall,
+    // a green right wall, white floor, ceiling and back, a light just under the ceiling and
+    // a tall and a short block turned towards each other. Colors are the usual reflectances
+    // (0.63, 0.065, 0.05), (0.14, 0.45, 0.091) and 0.73, encoded as sRGB. The point light
+    // lights it for direct shading, the glowing ceiling panel when path tracing.
+    pub fn cornell_box() -> Scene {
+        let red = Color::new(207, 72, 63);
+        let green = Color::new(105, 179, 85);
+        let white = Color::new(222, 222, 222);
+
+        let matte = |diffuse| Material { diffuse, ..Material::default() };
+        let slab = |center: Vec3, size: Vec3, diffuse| Object::Cube(Cube { center, size, material: matte(diffuse) });
+        // A block of the given size standing on the floor, turned about its vertical axis
+        let block = |x: f32, z: f32, size: Vec3, degrees: f32| {
+            let translate = Vec3::new(x, -1.0 + size.y * 0.5, z);
+            Object::Transformed {
+                // Translations and rotations are always invertible
+                transform: Transform::from_parts(translate, Vec3::new(0.0, degrees, 0.0), Vec3::repeat(1.0)).unwrap(),
+                object: Box::new(slab(Vec3::zeros(), size, white)),
+            }
+        };
+
+        let thickness = 0.02;
+        // Far enough back that the opening fills a 60° field of view
+        let mut scene = Scene::new(
+            Background::Solid(Color::black()),
+            Camera {
+                eye: Vec3::new(0.0, 0.0, -0.3),
+                center: Vec3::new(0.0, 0.0, -3.0),
+                up: Vec3::y(),
+                aperture: 0.0,
+                focus_distance: None,
+            },
+        );
+        scene.add_object(slab(Vec3::new(-1.0 - thickness * 0.5, 0.0, -3.0), Vec3::new(thickness, 2.0, 2.0), red));
+        scene.add_object(slab(Vec3::new(1.0 + thickness * 0.5, 0.0, -3.0), Vec3::new(thickness, 2.0, 2.0), green));
+        scene.add_object(slab(Vec3::new(0.0, -1.0 - thickness * 0.5, -3.0), Vec3::new(2.0, thickness, 2.0), white));
+        scene.add_object(slab(Vec3::new(0.0, 1.0 + thickness * 0.5, -3.0), Vec3::new(2.0, thickness, 2.0), white));
+        scene.add_object(slab(Vec3::new(0.0, 0.0, -4.0 - thickness * 0.5), Vec3::new(2.0, 2.0, thickness), white));
+        scene.add_object(block(-0.33, -3.35, Vec3::new(0.6, 1.2, 0.6), 17.0));
+        scene.add_object(block(0.33, -2.65, Vec3::new(0.6, 0.6, 0.6), -18.0));
+        scene.add_object(Object::Cube(Cube {
+            center: Vec3::new(0.0, 1.0 - thickness * 0.5, -3.0),
+            size: Vec3::new(0.5, thickness, 0.4),
+            material: Material {
+                emission: Color::new(255, 230, 190),
+                emission_strength: 12.0,
+                ..matte(white)
+            },
+        }));
+        scene.add_light(Light::point(Vec3::new(0.0, 0.95, -3.0)));
+        scene
+    }
+
+    // Writes the scene in the same format `load` reads, so load -> save -> load is lossless
+    pub fn save(&self, path: &str) -> Result<(), SceneError> {
+        let pretty = ron::ser::PrettyConfig::new()
+            .extensions(ron::extensions::Extensions::UNWRAP_VARIANT_NEWTYPES);
+        let source = ron_options().to_string_pretty(self, pretty).map_err(|source| SceneError::Serialize {
+            path: path.to_string(),
+            source,
+        })?;
+        fs::write(path, source).map_err(|source| SceneError::Io {
+            path: path.to_string(),
+            source,
+        })
+    }
+}
+
+// Polls a scene file's modification time so edits show up while the window is open
+pub struct SceneWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+    interval: Duration,
+}
+
+impl SceneWatcher {
+    pub fn new(path: &str) -> Self {
+        SceneWatcher {
+            path: path.to_string(),
+            modified: modified_time(path),
+            last_check: Instant::now(),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    // Returns true once each time the file's modification time moves, checking the
+    // file system at most once per interval
+    pub fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < self.interval {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            return true;
+        }
+        false
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn ron_options() -> ron::Options {
+    // Lets objects be written as `Sphere(center: ...)` instead of `Sphere((center: ...))`
+    ron::Options::default().with_default_extension(ron::extensions::Extensions::UNWRAP_VARIANT_NEWTYPES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::render::{render, RenderSettings};
+
+    // A file in the system's temporary directory, named after the test writing it
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("raytracing_animal_{}.ron", name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn save_and_load_give_back_the_scene() {
+        for (source, name) in [
+            (DEFAULT_SCENE, "scenes/teddy.ron"),
+            (include_str!("../scenes/teddy_glow.ron"), "scenes/teddy_glow.ron"),
+            (include_str!("../scenes/teddy_stage.ron"), "scenes/teddy_stage.ron"),
+            (include_str!("../scenes/csg_props.ron"), "scenes/csg_props.ron"),
+        ] {
+            let scene = Scene::parse(source, name).unwrap();
+            let path = temp_path("round_trip");
+            scene.save(&path).unwrap();
+            let loaded = Scene::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), scene, "{}", name);
+        }
+    }
+
+    #[test]
+    fn cornell_box_has_a_red_left_and_a_green_right_wall() {
+        let scene = Scene::cornell_box();
+        let mut framebuffer = Framebuffer::new(32, 32);
+        render(&mut framebuffer, &scene, &scene.camera, &RenderSettings::default());
+
+        let channels = |x, y| Color::from_u32(framebuffer.get_pixel(x, y).unwrap()).to_vec3();
+        for y in 12..20 {
+            let left = channels(1, y);
+            assert!(left.x > left.y * 1.5 && left.x > left.z * 1.5, "left wall at {}: {:?}", y, left);
+            let right = channels(30, y);
+            assert!(right.y > right.x * 1.5 && right.y > right.z * 1.5, "right wall at {}: {:?}", y, right);
+        }
+    }
+
+    fn ball(z: f32) -> Object {
+        Object::Sphere(Sphere { center: Vec3::new(0.0, 0.0, z), radius: 1.0, material: Material::default(), velocity: Vec3::zeros() })
+    }
+
+    fn scene_of(objects: Vec<Object>) -> Scene {
+        let mut scene = Scene::new(Background::Solid(Color::black()), Camera::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -5.0), Vec3::y()));
+        objects.into_iter().for_each(|object| scene.add_object(object));
+        scene
+    }
+
+    #[test]
+    fn pick_finds_the_nearest_of_overlapping_objects() {
+        let ahead = Ray::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0));
+        let block = || Object::Cube(Cube { center: Vec3::new(0.0, 0.0, -5.0), size: Vec3::repeat(1.0), material: Material::default() });
+
+        // Whichever order they were added in
+        let pick = scene_of(vec![ball(-10.0), block()]).pick(&ahead).unwrap();
+        assert_eq!((pick.index, pick.kind), (1, "cube"));
+        assert!((pick.intersect.distance - 4.5).abs() < 1e-5);
+        let pick = scene_of(vec![block(), ball(-10.0)]).pick(&ahead).unwrap();
+        assert_eq!((pick.index, pick.kind), (0, "cube"));
+
+        // Looking the other way, or past them, there's nothing to pick
+        assert!(scene_of(vec![ball(-10.0), block()]).pick(&Ray::new(Vec3::zeros(), Vec3::z())).is_none());
+    }
+
+    #[test]
+    fn pick_goes_into_groups_to_the_nearest_part() {
+        let head = Object::Group {
+            name: Some("head".to_string()),
+            transform: Transform::identity(),
+            velocity: Vec3::zeros(),
+            objects: vec![ball(-8.0), ball(-3.0), ball(-6.0)],
+        };
+        let pick = scene_of(vec![ball(-10.0), head]).pick(&Ray::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(pick.index, 1);
+        assert_eq!(pick.path, [1]);
+        assert_eq!(pick.groups, ["head"]);
+        assert_eq!(pick.kind, "sphere");
+        assert!((pick.intersect.distance - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn packets_give_the_same_hits_as_one_ray_at_a_time() {
+        let mut rng = crate::rng::Rng::new(7);
+        let teddy = Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap();
+        // Moving balls too, loose and in a group, hit at all times of the shutter
+        let mut moving = scene_of(vec![ball(-4.0), ball(-6.0)]);
+        if let Object::Sphere(sphere) = &mut moving.objects[0] {
+            sphere.velocity = Vec3::new(0.5, 0.2, 0.0);
+        }
+        moving.add_object(Object::Group {
+            name: None,
+            transform: Transform::from_parts(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 30.0, 0.0), Vec3::repeat(0.5)).unwrap(),
+            velocity: Vec3::new(0.0, 0.3, 0.0),
+            objects: vec![ball(-5.0), ball(-7.0)],
+        });
+
+        for scene in [teddy, moving] {
+            let mut hits = 0;
+            for _ in 0..50 {
+                let rays: Vec<Ray> = (0..64)
+                    .map(|_| {
+                        let mut random = || Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 2.0 - Vec3::repeat(1.0);
+                        let origin = scene.camera.eye + random() * 0.5;
+                        let direction = scene.camera.center - scene.camera.eye + random() * 3.0;
+                        Ray::new(origin, direction).with_time(rng.next_f32())
+                    })
+                    .collect();
+                let batch = scene.intersect_object_batch(&RayPacket::new(&rays));
+                for (ray, (batched, batched_index)) in rays.iter().zip(&batch) {
+                    let (single, single_index) = scene.intersect_object(ray);
+                    assert_eq!(*batched_index, single_index);
+                    assert_eq!(batched.is_intersecting, single.is_intersecting);
+                    assert_eq!(batched.distance.to_bits(), single.distance.to_bits());
+                    assert_eq!(batched.point, single.point);
+                    assert_eq!(batched.normal, single.normal);
+                    assert_eq!(batched.uv, single.uv);
+                    assert_eq!(batched.material, single.material);
+                    hits += single.is_intersecting as usize;
+                }
+            }
+            assert!(hits > 200 && hits < 50 * 64 - 200, "{} hits", hits);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use nalgebra_glm::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::ray_intersect::{Intersect, Material, Ray, RayIntersect};
+
+// A surface given by its signed distance field: the distance from any point to the
+// surface, negative inside. It may underestimate the distance, never overestimate it,
+// since sphere tracing steps along rays by it.
+pub trait Sdf {
+    fn distance(&self, point: &Vec3) -> f32;
+    // A box the whole surface is inside of
+    fn bounding_box(&self) -> Aabb;
+}
+
+// Sphere tracing gives up after this many steps, and counts a point this close to the
+// surface as on it
+const MAX_STEPS: u32 = 256;
+const EPSILON: f32 = 1e-4;
+
+// The first hit between `t_min` and `t_max` on the surface, found by stepping along the ray
+// by the distance to it until that gets within epsilon, with the normal from the field's
+// gradient. Only the stretch of the ray inside the bounding box is marched, rays that miss
+// it don't take a single step.
+pub fn sphere_trace(sdf: &impl Sdf, ray: &Ray, t_min: f32, t_max: f32, material: &Material) -> Intersect {
+    let Some((mut t, end)) = sdf.bounding_box().ray_interval(ray, t_min, t_max) else {
+        return Intersect::empty();
+    };
+
+    // Rays leaving the surface, like shadow rays, start on it. They only count a hit once
+    // they've been away from it, or they would hit where they start.
+    let mut away = t > t_min;
+    for _ in 0..MAX_STEPS {
+        // Like the analytic primitives, a surface right at `t_max` is past the range
+        if t > end || t >= t_max {
+            break;
+        }
+        let point = ray.at(t);
+        let distance = sdf.distance(&point);
+        if distance < EPSILON {
+            if away {
+                return Intersect::new(point, gradient(sdf, &point), t, material.clone());
+            }
+            t += distance.abs().max(EPSILON);
+        } else {
+            away = true;
+            t += distance;
+        }
+    }
+    Intersect::empty()
+}
+
+// The unit gradient of the field by central differences, which is the surface normal
+fn gradient(sdf: &impl Sdf, point: &Vec3) -> Vec3 {
+    let axis = |offset: Vec3| sdf.distance(&(point + offset)) - sdf.distance(&(point - offset));
+    let gradient = Vec3::new(axis(Vec3::x() * EPSILON), axis(Vec3::y() * EPSILON), axis(Vec3::z() * EPSILON));
+    gradient.try_normalize(1e-12).unwrap_or_else(Vec3::y)
+}
+
+// Polynomial smooth minimum: the plain minimum away from where `a` and `b` are within `k`
+// of each other, rounded off in between. It's at most k / 4 below the minimum.
+pub fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+// A shape built from primitives with set operations, for what analytic intersection can't
+// do, like rounded boxes or carved details. Scene files write it as the `shape` of an `Sdf`
+// object, e.g. `Subtract(from: Sphere(...), take: Cube(...))`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    // Edges rounded off with `rounding` as the radius, which keeps the size the same
+    Cube {
+        center: Vec3,
+        size: Vec3,
+        #[serde(default)]
+        rounding: f32,
+    },
+    // Lying flat, around the vertical axis through its center
+    Torus {
+        center: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Union(Vec<Shape>),
+    SmoothUnion {
+        shapes: Vec<Shape>,
+        smoothness: f32, // How far from where two shapes meet the blending reaches
+    },
+    Subtract {
+        from: Box<Shape>,
+        take: Box<Shape>,
+    },
+    Intersect(Vec<Shape>),
+}
+
+impl Sdf for Shape {
+    fn distance(&self, point: &Vec3) -> f32 {
+        let nearest = |shapes: &[Shape], join: &dyn Fn(f32, f32) -> f32| {
+            shapes.iter().map(|shape| shape.distance(point)).reduce(join).unwrap_or(f32::INFINITY)
+        };
+        match self {
+            Shape::Sphere { center, radius } => (point - center).magnitude() - radius,
+            Shape::Cube { center, size, rounding } => {
+                let rounding = rounding.clamp(0.0, size.min() * 0.5);
+                let q = (point - center).abs() - (size * 0.5 - Vec3::repeat(rounding));
+                q.sup(&Vec3::zeros()).magnitude() + q.max().min(0.0) - rounding
+            }
+            Shape::Torus { center, major_radius, minor_radius } => {
+                let p = point - center;
+                Vec2::new(Vec2::new(p.x, p.z).magnitude() - major_radius, p.y).magnitude() - minor_radius
+            }
+            Shape::Union(shapes) => nearest(shapes, &f32::min),
+            Shape::SmoothUnion { shapes, smoothness } => nearest(shapes, &|a, b| smooth_min(a, b, *smoothness)),
+            Shape::Subtract { from, take } => from.distance(point).max(-take.distance(point)),
+            // Inside all of them, so the farthest one counts
+            Shape::Intersect(shapes) => nearest(shapes, &f32::max),
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let union = |shapes: &[Shape]| shapes.iter().fold(Aabb::empty(), |bounds, shape| bounds.union(&shape.bounding_box()));
+        match self {
+            Shape::Sphere { center, radius } => Aabb::around(center, &Vec3::repeat(*radius)),
+            Shape::Cube { center, size, .. } => Aabb::around(center, &(size * 0.5)),
+            Shape::Torus { center, major_radius, minor_radius } => {
+                let outer = major_radius + minor_radius;
+                Aabb::around(center, &Vec3::new(outer, *minor_radius, outer))
+            }
+            Shape::Union(shapes) => union(shapes),
+            Shape::SmoothUnion { shapes, smoothness } => {
+                let bounds = union(shapes);
+                let margin = Vec3::repeat(smoothness.max(0.0) * 0.25);
+                Aabb { min: bounds.min - margin, max: bounds.max + margin }
+            }
+            Shape::Subtract { from, .. } => from.bounding_box(),
+            Shape::Intersect(shapes) => shapes
+                .iter()
+                .map(|shape| shape.bounding_box())
+                .reduce(|a, b| a.intersection(&b))
+                .unwrap_or_else(Aabb::empty),
+        }
+    }
+}
+
+// A shape given by a distance field, with one material all over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SdfObject {
+    pub shape: Shape,
+    pub material: Material,
+}
+
+impl SdfObject {
+    pub fn bounding_box(&self) -> Aabb {
+        self.shape.bounding_box()
+    }
+}
+
+impl RayIntersect for SdfObject {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        sphere_trace(&self.shape, ray, t_min, t_max, &self.material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use std::cell::Cell;
+
+    fn unit_ball() -> Shape {
+        Shape::Sphere { center: Vec3::new(0.0, 0.0, -5.0), radius: 1.0 }
+    }
+
+    fn trace(shape: &Shape, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        sphere_trace(shape, ray, t_min, t_max, &Material::default())
+    }
+
+    #[test]
+    fn sphere_field_is_hit_where_the_sphere_is() {
+        let sphere = Sphere { center: Vec3::new(0.0, 0.0, -5.0), radius: 1.0, material: Material::default(), velocity: Vec3::zeros() };
+        let mut hits = 0;
+        for i in 0..100 {
+            let (x, y) = ((i % 10) as f32 / 10.0 - 0.45, (i / 10) as f32 / 10.0 - 0.45);
+            let ray = Ray::new(Vec3::zeros(), Vec3::new(0.4 * x, 0.4 * y, -1.0));
+            let (analytic, traced) = (sphere.ray_intersect(&ray, 1e-4, f32::INFINITY), trace(&unit_ball(), &ray, 1e-4, f32::INFINITY));
+            assert_eq!(traced.is_intersecting, analytic.is_intersecting, "ray {}", i);
+            if analytic.is_intersecting {
+                hits += 1;
+                assert!((traced.distance - analytic.distance).abs() < 1e-3, "ray {}: {} and {}", i, traced.distance, analytic.distance);
+                assert!((traced.normal - analytic.normal).magnitude() < 1e-2, "ray {}", i);
+            }
+        }
+        assert!(hits > 50 && hits < 100, "{}", hits);
+    }
+
+    #[test]
+    fn surfaces_on_the_range_boundaries_are_left_out() {
+        let ray = Ray::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(trace(&unit_ball(), &ray, 0.0, f32::INFINITY).distance, 4.0);
+        // One at t_max is beyond the range, like a blocker exactly at a light
+        assert!(!trace(&unit_ball(), &ray, 0.0, 4.0).is_intersecting);
+        assert_eq!(trace(&unit_ball(), &ray, 0.0, 4.0001).distance, 4.0);
+    }
+
+    #[test]
+    fn rays_leaving_the_surface_dont_hit_it_again() {
+        // Two balls side by side, with shadow rays from a point on the first
+        let pair = Shape::Union(vec![unit_ball(), Shape::Sphere { center: Vec3::new(3.0, 0.0, -5.0), radius: 1.0 }]);
+        let on_surface = Vec3::new(1.0, 0.0, -5.0);
+        let hit = trace(&pair, &Ray::new(on_surface, Vec3::x()), 1e-4, f32::INFINITY);
+        assert!((hit.distance - 1.0).abs() < 1e-3, "{}", hit.distance);
+        // Straight out, or along the surface, there's nothing
+        assert!(!trace(&pair, &Ray::new(on_surface, Vec3::new(1.0, 1.0, 0.0)), 1e-4, f32::INFINITY).is_intersecting);
+        assert!(!trace(&pair, &Ray::new(on_surface, Vec3::y()), 1e-4, f32::INFINITY).is_intersecting);
+        // Up to just short of the second ball it's unshadowed
+        assert!(!trace(&pair, &Ray::new(on_surface, Vec3::x()), 1e-4, 0.99).is_intersecting);
+    }
+
+    // A field that counts how often it's asked for a distance
+    struct Counted<'a> {
+        shape: Shape,
+        steps: &'a Cell<u32>,
+    }
+
+    impl Sdf for Counted<'_> {
+        fn distance(&self, point: &Vec3) -> f32 {
+            self.steps.set(self.steps.get() + 1);
+            self.shape.distance(point)
+        }
+
+        fn bounding_box(&self) -> Aabb {
+            self.shape.bounding_box()
+        }
+    }
+
+    #[test]
+    fn rays_missing_the_bounds_take_no_steps() {
+        let steps = Cell::new(0);
+        let ball = Counted { shape: unit_ball(), steps: &steps };
+        let material = Material::default();
+        let past = Ray::new(Vec3::zeros(), Vec3::new(0.5, 0.0, -1.0));
+        assert!(!sphere_trace(&ball, &past, 1e-4, f32::INFINITY, &material).is_intersecting);
+        let short = Ray::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!sphere_trace(&ball, &short, 1e-4, 3.5, &material).is_intersecting);
+        assert_eq!(steps.get(), 0);
+        assert!(sphere_trace(&ball, &short, 1e-4, f32::INFINITY, &material).is_intersecting);
+        assert!(steps.get() > 0);
+    }
+}
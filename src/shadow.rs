@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+// Softens the shadow edges of every point and spot light, whether or not it has a radius of
+// its own, by aiming each point's shadow rays at spots up to `radius` away from where they
+// would go and averaging them. Scenes that set it pick the number of shadow rays per light
+// with it too, instead of the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShadowFilter {
+    pub samples: u32,
+    pub radius: f32,
+}
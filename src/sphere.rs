@@ -1,39 +1,351 @@
-use nalgebra_glm::{Vec3, dot};
-use crate::ray_intersect::{RayIntersect, Material, Intersect};
+use nalgebra_glm::Vec3;
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+use crate::ray_intersect::{RayIntersect, Material, Intersect, Ray, RayPacket, Span};
+use crate::aabb::Aabb;
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sphere {
-    pub center: Vec3,
+    pub center: Vec3, // Where the sphere is when the shutter opens
     pub radius: f32,
     pub material: Material,
+    // How far the center moves while the shutter is open, for motion blur
+    #[serde(default, skip_serializing_if = "is_still")]
+    pub velocity: Vec3,
+}
+
+pub fn is_still(velocity: &Vec3) -> bool {
+    *velocity == Vec3::zeros()
+}
+
+impl Sphere {
+    // The box around everywhere the sphere goes during the shutter interval
+    pub fn bounding_box(&self) -> Aabb {
+        let extent = Vec3::repeat(self.radius);
+        Aabb::around(&self.center, &extent).union(&Aabb::around(&(self.center + self.velocity), &extent))
+    }
+
+    // The center at a time between 0 (shutter opening) and 1 (closing)
+    pub fn center_at(&self, time: f32) -> Vec3 {
+        self.center + self.velocity * time
+    }
+}
+
+impl Sphere {
+    // The same hits as calling `ray_intersect` on each ray of the packet
+    pub fn ray_intersect_batch(&self, packet: &RayPacket, t_min: f32, t_max: f32) -> Vec<Intersect> {
+        let mut roots = vec![t_max; packet.len()];
+        self.nearest_roots(packet, t_min, &mut roots);
+        packet.rays.iter().zip(&roots).map(|(ray, &t)| self.hit_at(ray, if t < t_max { t } else { f32::INFINITY })).collect()
+    }
+
+    // For every ray of the packet, lowers `roots[i]` to the nearest root above `t_min` if
+    // there is one below it, so `roots` can start out as each ray's nearest hit so far. The
+    // loop runs over contiguous arrays of floats without branching, which the compiler can
+    // vectorize.
+    pub fn nearest_roots(&self, packet: &RayPacket, t_min: f32, roots: &mut [f32]) {
+        let [ox, oy, oz] = &packet.origins;
+        let [dx, dy, dz] = &packet.directions;
+        let (c, v) = (self.center, self.velocity);
+        for (i, root) in roots.iter_mut().enumerate() {
+            let time = packet.times[i];
+            let center = [c.x + v.x * time, c.y + v.y * time, c.z + v.z * time];
+            let t = self.nearest_root(center, [ox[i], oy[i], oz[i]], [dx[i], dy[i], dz[i]], t_min, *root);
+            *root = if t < *root { t } else { *root };
+        }
+    }
+
+    // The nearest root of the ray's quadratic strictly between `t_min` and `t_max`, which is
+    // the far side when the ray starts inside, or infinity for a miss. Both roots are always
+    // computed and picked between with selects so that loops over it vectorize.
+    #[inline(always)]
+    fn nearest_root(&self, center: [f32; 3], origin: [f32; 3], direction: [f32; 3], t_min: f32, t_max: f32) -> f32 {
+        let (near, far, discriminant) = self.roots(center, origin, direction);
+        let in_range = |t: f32| (t > t_min) & (t < t_max);
+        let t = if in_range(near) {
+            near
+        } else if in_range(far) {
+            far
+        } else {
+            f32::INFINITY
+        };
+        if discriminant > 0.0 { t } else { f32::INFINITY }
+    }
+
+    // Both roots of the ray's quadratic, nearest first, and its discriminant, which is
+    // positive when the line through the ray crosses the sphere. A line that only touches it
+    // counts as a miss. The roots are meaningless otherwise.
+    //
+    // The textbook b² - 4ac cancels out to nothing for small spheres far away, whose radius
+    // is lost next to the distance, so the discriminant is taken from how close the line
+    // passes to the center instead. Of the two roots, the one that would subtract nearly
+    // equal numbers is found from the other, as their product is c / a. It's all worked out
+    // in f64: on a sphere as big as a planet used for a floor, the offset from the center to
+    // a point on the surface has an f32 rounding error larger than the shadow bias, so rays
+    // leaving the surface would hit it again in rings.
+    #[inline(always)]
+    fn roots(&self, center: [f32; 3], origin: [f32; 3], direction: [f32; 3]) -> (f32, f32, f32) {
+        let wide = |v: [f32; 3]| [v[0] as f64, v[1] as f64, v[2] as f64];
+        let (center, origin, direction) = (wide(center), wide(origin), wide(direction));
+        let radius = self.radius as f64;
+        // Vector from the center of the sphere to the ray origin
+        let oc = [origin[0] - center[0], origin[1] - center[1], origin[2] - center[2]];
+        let dot = |p: [f64; 3], q: [f64; 3]| p[0] * q[0] + p[1] * q[1] + p[2] * q[2];
+
+        // Coefficients of a t² + 2 b t + c = 0
+        let a = dot(direction, direction);
+        let b = dot(oc, direction);
+        let c = dot(oc, oc) - radius * radius;
+
+        // From the center to the nearest point of the line, whose length against the radius
+        // is the discriminant divided by a
+        let f = b / a;
+        let closest = [oc[0] - direction[0] * f, oc[1] - direction[1] * f, oc[2] - direction[2] * f];
+        let discriminant = radius * radius - dot(closest, closest);
+
+        let root = (a * discriminant).max(0.0).sqrt();
+        let q = -(b + root.copysign(b));
+        let (t0, t1) = (c / q, q / a);
+        (t0.min(t1) as f32, t0.max(t1) as f32, discriminant as f32)
+    }
+
+    // The hit `t` along the ray, or none for an infinite `t`
+    pub fn hit_at(&self, ray: &Ray, t: f32) -> Intersect {
+        if t == f32::INFINITY {
+            return Intersect::empty();
+        }
+
+        // Compute intersection point, normal at the intersection, and distance from the ray origin
+        let point = ray.at(t);
+        let normal = (point - self.center_at(ray.time)).normalize();
+        let distance = t;
+
+        // Longitude wraps around the Y axis and latitude runs from the top pole (v = 0)
+        // to the bottom one, with u growing in the direction of the tangent
+        let u = 0.5 + normal.z.atan2(normal.x) / (2.0 * PI);
+        let v = 0.5 - normal.y.clamp(-1.0, 1.0).asin() / PI;
+        let tangent = Vec3::new(-normal.z, 0.0, normal.x);
+
+        Intersect::new(point, normal, distance, self.material.clone()).with_uv(u, v, tangent)
+    }
 }
 
 impl RayIntersect for Sphere {
-    fn ray_intersect(&self, ray_origin: &Vec3, ray_direction: &Vec3) -> Intersect {
-        // Vector from the ray origin to the center of the sphere
-        let oc = ray_origin - self.center;
-
-        // Coefficients for the quadratic equation
-        let a = dot(ray_direction, ray_direction);
-        let b = 2.0 * dot(&oc, ray_direction);
-        let c = dot(&oc, &oc) - self.radius * self.radius;
-
-        // Discriminant of the quadratic equation
-        let discriminant = b * b - 4.0 * a * c;
-
-        if discriminant > 0.0 {
-            // Calculate the nearest point of intersection
-            let t = (-b - discriminant.sqrt()) / (2.0 * a);
-            if t > 0.0 {
-                // Compute intersection point, normal at the intersection, and distance from the ray origin
-                let point = ray_origin + ray_direction * t;
-                let normal = (point - self.center).normalize();
-                let distance = t;
-
-                return Intersect::new(point, normal, distance, self.material);
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let (c, o, d) = (self.center_at(ray.time), ray.origin, ray.direction);
+        let t = self.nearest_root([c.x, c.y, c.z], [o.x, o.y, o.z], [d.x, d.y, d.z], t_min, t_max);
+        self.hit_at(ray, t)
+    }
+
+    // Between the two roots, when the line through the ray crosses the sphere
+    fn ray_spans(&self, ray: &Ray) -> Option<Vec<Span>> {
+        let (c, o, d) = (self.center_at(ray.time), ray.origin, ray.direction);
+        let (near, far, discriminant) = self.roots([c.x, c.y, c.z], [o.x, o.y, o.z], [d.x, d.y, d.z]);
+        if discriminant <= 0.0 {
+            return Some(Vec::new());
+        }
+        Some(vec![Span { enter: self.hit_at(ray, near), exit: self.hit_at(ray, far) }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_sphere() -> Sphere {
+        Sphere { center: Vec3::zeros(), radius: 1.0, material: Material::default(), velocity: Vec3::zeros() }
+    }
+
+    // Crosses the unit sphere at exactly t = 4 and t = 6
+    fn ray_along_z() -> Ray {
+        Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn roots_on_the_range_boundaries_are_left_out() {
+        let (sphere, ray) = (unit_sphere(), ray_along_z());
+        assert_eq!(sphere.ray_intersect(&ray, 0.0, f32::INFINITY).distance, 4.0);
+        // A near root at t_min is skipped for the far one
+        assert_eq!(sphere.ray_intersect(&ray, 4.0, f32::INFINITY).distance, 6.0);
+        // One at t_max is beyond the range, like a blocker exactly at a light
+        assert!(!sphere.ray_intersect(&ray, 0.0, 4.0).is_intersecting);
+        assert!(!sphere.ray_intersect(&ray, 4.0, 6.0).is_intersecting);
+        assert_eq!(sphere.ray_intersect(&ray, 0.0, 4.0001).distance, 4.0);
+        assert_eq!(sphere.ray_intersect(&ray, 4.0, 6.0001).distance, 6.0);
+    }
+
+    #[test]
+    fn batch_gives_the_same_hits_as_one_ray_at_a_time() {
+        let sphere = Sphere {
+            center: Vec3::new(0.5, -0.25, -4.0),
+            radius: 1.5,
+            material: Material::default(),
+            velocity: Vec3::new(0.3, 0.0, 0.1),
+        };
+        // A fan from outside, including misses and grazing rays, and a few from inside
+        let mut rays: Vec<Ray> = (0..100)
+            .map(|i| {
+                let (x, y) = ((i % 10) as f32 / 10.0 - 0.5, (i / 10) as f32 / 10.0 - 0.5);
+                Ray::new(Vec3::zeros(), Vec3::new(x, y, -1.0)).with_time(i as f32 / 100.0)
+            })
+            .collect();
+        rays.extend((0..5).map(|i| Ray::new(sphere.center, Vec3::new(1.0, i as f32, -1.0))));
+
+        for (t_min, t_max) in [(0.0, f32::INFINITY), (1e-4, 4.0), (3.0, 5.0)] {
+            let batch = sphere.ray_intersect_batch(&RayPacket::new(&rays), t_min, t_max);
+            assert_eq!(batch.len(), rays.len());
+            for (ray, batched) in rays.iter().zip(&batch) {
+                let single = sphere.ray_intersect(ray, t_min, t_max);
+                assert_eq!(batched.is_intersecting, single.is_intersecting);
+                assert_eq!(batched.distance.to_bits(), single.distance.to_bits());
+                assert_eq!(batched.point, single.point);
+                assert_eq!(batched.normal, single.normal);
             }
         }
+    }
+
+    #[test]
+    fn moving_sphere_is_hit_where_it_is_at_the_ray_time() {
+        // Moves from the origin to x = 4 while the shutter is open
+        let sphere = Sphere { velocity: Vec3::new(4.0, 0.0, 0.0), ..unit_sphere() };
+        for (time, x) in [(0.0, 0.0), (1.0, 4.0), (0.5, 2.0)] {
+            assert_eq!(sphere.center_at(time), Vec3::new(x, 0.0, 0.0));
+            let ray = Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).with_time(time);
+            let hit = sphere.ray_intersect(&ray, 0.0, f32::INFINITY);
+            assert!(hit.is_intersecting, "t = {}", time);
+            assert!((hit.distance - 4.0).abs() < 1e-5, "t = {}: {}", time, hit.distance);
+            assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+            // Where the sphere started it is gone by then, or not there yet
+            let start = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).with_time(time);
+            assert_eq!(sphere.ray_intersect(&start, 0.0, f32::INFINITY).is_intersecting, time < 0.5);
+        }
+    }
+
+    fn first_hit(sphere: &Sphere, origin: Vec3, direction: Vec3) -> Intersect {
+        sphere.ray_intersect(&Ray::new(origin, direction), 0.0, f32::INFINITY)
+    }
+
+    #[test]
+    fn ray_from_inside_hits_the_far_wall() {
+        let hit = first_hit(&unit_sphere(), Vec3::zeros(), Vec3::x());
+        assert!(hit.is_intersecting);
+        assert_eq!(hit.distance, 1.0);
+        assert_eq!(hit.point, Vec3::x());
+        // The normal still points out of the sphere, away from where the ray came from
+        assert_eq!(hit.normal, Vec3::x());
+
+        let off_center = first_hit(&unit_sphere(), Vec3::new(0.0, 0.5, 0.0), -Vec3::z());
+        assert!((off_center.distance - 0.75f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tangent_rays_only_touch() {
+        // Exactly along the top is a touch, which doesn't count; a hair lower crosses it
+        assert!(!first_hit(&unit_sphere(), Vec3::new(-5.0, 1.0, 0.0), Vec3::x()).is_intersecting);
+        assert!(!first_hit(&unit_sphere(), Vec3::new(-5.0, 1.0001, 0.0), Vec3::x()).is_intersecting);
+        let grazing = first_hit(&unit_sphere(), Vec3::new(-5.0, 0.9999, 0.0), Vec3::x());
+        assert!(grazing.is_intersecting);
+        assert!((grazing.distance - 5.0).abs() < 0.02 && (grazing.point.magnitude() - 1.0).abs() < 1e-5);
+    }
 
-        // If no intersection, return an empty intersect
-        Intersect::empty()
+    #[test]
+    fn ray_pointing_away_misses() {
+        // The line through it crosses the sphere, but behind where the ray starts
+        assert!(!first_hit(&unit_sphere(), Vec3::new(0.0, 0.0, 5.0), Vec3::z()).is_intersecting);
+        assert!(unit_sphere().ray_spans(&Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::z())).unwrap().len() == 1);
+    }
+
+    #[test]
+    fn hits_are_at_the_distance_to_the_surface() {
+        let far = Sphere { center: Vec3::new(0.0, 0.0, -10.0), ..unit_sphere() };
+        assert_eq!(first_hit(&far, Vec3::zeros(), -Vec3::z()).distance, 9.0);
+        // Rays are made with unit directions, so distances come out the same whatever length
+        // of direction they were given
+        assert_eq!(first_hit(&far, Vec3::zeros(), Vec3::new(0.0, 0.0, -3.0)).distance, 9.0);
+
+        // Starting on the surface, the root at 0 is left out: outwards nothing is hit,
+        // inwards the far side is
+        assert!(!first_hit(&unit_sphere(), Vec3::z(), Vec3::z()).is_intersecting);
+        assert_eq!(first_hit(&unit_sphere(), Vec3::z(), -Vec3::z()).distance, 2.0);
+    }
+
+    #[test]
+    fn huge_and_tiny_spheres_are_hit_on_their_surface() {
+        // A planet-sized floor just under the origin, and a grain of sand far away
+        let floor = Sphere { center: Vec3::new(0.0, -1e4, 0.0), radius: 1e4, ..unit_sphere() };
+        for x in [0.0, 3.0, -40.0] {
+            let hit = first_hit(&floor, Vec3::new(x, 1.0, 0.0), -Vec3::y());
+            assert!(hit.is_intersecting);
+            let expected = 1.0 + 1e4 - (1e8f64 - (x as f64) * (x as f64)).sqrt();
+            assert!((hit.distance as f64 - expected).abs() < 1e-4, "{} for {}", hit.distance, expected);
+            assert!((hit.normal - Vec3::new(x / 1e4, 1.0, 0.0).normalize()).magnitude() < 1e-4);
+        }
+        let grain = Sphere { center: Vec3::new(0.0, 0.0, -100.0), radius: 1e-3, ..unit_sphere() };
+        let hit = first_hit(&grain, Vec3::zeros(), -Vec3::z());
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - (100.0 - 1e-3)).abs() < 1e-5, "{}", hit.distance);
+        assert!((hit.normal - Vec3::z()).magnitude() < 1e-3);
+        // Passing twice its radius to the side misses it
+        assert!(!first_hit(&grain, Vec3::new(2e-3, 0.0, 0.0), -Vec3::z()).is_intersecting);
+    }
+
+    #[test]
+    fn random_hits_lie_on_the_sphere() {
+        let mut rng = crate::rng::Rng::new(11);
+        let mut random = || rng.next_f32();
+        let mut hits = 0;
+        for _ in 0..5000 {
+            let mut vector = |scale: f32| Vec3::new(random(), random(), random()).map(|c| (c - 0.5) * scale);
+            let radius = 10f32.powf(vector(8.0).x);
+            let sphere = Sphere { center: vector(100.0), radius, ..unit_sphere() };
+            let origin = sphere.center + vector(radius * 6.0);
+            // Mostly towards the sphere
+            let direction = sphere.center + vector(radius * 3.0) - origin;
+            let hit = first_hit(&sphere, origin, direction);
+            if hit.is_intersecting {
+                hits += 1;
+                let off = ((hit.point - sphere.center).magnitude() - radius).abs();
+                assert!(off <= radius * 1e-4 + 1e-4, "{} off a sphere of radius {}", off, radius);
+                assert!(hit.distance > 0.0);
+                assert!((hit.normal.magnitude() - 1.0).abs() < 1e-5);
+            }
+        }
+        assert!(hits > 1000, "{} hits", hits);
+    }
+
+    #[test]
+    fn planet_sized_floor_is_lit_without_speckles() {
+        use crate::background::Background;
+        use crate::camera::Camera;
+        use crate::color::Color;
+        use crate::framebuffer::Framebuffer;
+        use crate::light::Light;
+        use crate::render::{render, RenderSettings};
+        use crate::scene::{Object, Scene};
+
+        // The bear's view of a white floor one below the camera, lit from high above. Rays
+        // leaving a floor point for the light that hit the floor again would leave it dark.
+        let mut scene = Scene::new(Background::Solid(Color::black()), Camera::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -5.0), Vec3::y()));
+        scene.add_object(Object::Sphere(Sphere {
+            center: Vec3::new(0.0, -1.0 - 1e4, -5.0),
+            radius: 1e4,
+            material: Material { diffuse: Color::white(), ..Material::default() },
+            velocity: Vec3::zeros(),
+        }));
+        scene.add_light(Light::point(Vec3::new(0.0, 50.0, -5.0)));
+        let mut framebuffer = Framebuffer::new(64, 48);
+        render(&mut framebuffer, &scene, &scene.camera, &RenderSettings::default());
+
+        // The floor fills the lower half of the image, and every pixel of it is about as
+        // bright as the ones beside it
+        let at = |x: usize, y: usize| framebuffer.radiance[y * 64 + x].x;
+        for y in 26..48 {
+            for x in 1..63 {
+                let (left, here, right) = (at(x - 1, y), at(x, y), at(x + 1, y));
+                assert!(here > 0.01, "at {}, {}: {}", x, y, here);
+                assert!(here > 0.9 * left.min(right), "speckle at {}, {}: {} between {} and {}", x, y, here, left, right);
+            }
+        }
     }
 }
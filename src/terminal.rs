@@ -0,0 +1,190 @@
+use std::env;
+use std::f32::consts::PI;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use raytracing_animal::camera::Camera;
+use raytracing_animal::framebuffer::Framebuffer;
+use raytracing_animal::render::{render, RenderSettings};
+use raytracing_animal::scene::Scene;
+
+// How often the terminal view is drawn, and how fast it orbits: once around in 12 seconds
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const ORBIT_SPEED: f32 = 2.0 * PI / 12.0;
+
+// Terminal size used when it can't be found out, in characters
+const FALLBACK_COLUMNS: usize = 120;
+const FALLBACK_ROWS: usize = 31;
+
+// How many colors the terminal can show: any 24-bit color, or the 256 of the xterm palette
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    TrueColor,
+    Palette256,
+}
+
+impl ColorDepth {
+    // Terminals with 24-bit color say so in COLORTERM, the rest get the palette
+    pub fn detect() -> ColorDepth {
+        match env::var("COLORTERM").as_deref() {
+            Ok("truecolor" | "24bit") => ColorDepth::TrueColor,
+            _ => ColorDepth::Palette256,
+        }
+    }
+}
+
+// The image size that fills the terminal: a pixel per column, and two per row since each
+// character shows two pixels one above the other. The last row is left for the cursor.
+pub fn terminal_pixels() -> (usize, usize) {
+    let (columns, rows) = terminal_size().unwrap_or((FALLBACK_COLUMNS, FALLBACK_ROWS));
+    (columns.max(1), rows.saturating_sub(1).max(1) * 2)
+}
+
+// Columns and rows from the environment when the shell exports them, otherwise from
+// `stty`, which reads them off the terminal on stdin
+fn terminal_size() -> Option<(usize, usize)> {
+    let from_env = |name: &str| env::var(name).ok()?.trim().parse::<usize>().ok().filter(|&n| n > 0);
+    if let (Some(columns), Some(rows)) = (from_env("COLUMNS"), from_env("LINES")) {
+        return Some((columns, rows));
+    }
+    let output = Command::new("stty").arg("size").stdin(Stdio::inherit()).stderr(Stdio::null()).output().ok()?;
+    let text = String::from_utf8(output.stdout).ok()?;
+    let mut numbers = text.split_whitespace().map(|n| n.parse::<usize>().ok().filter(|&n| n > 0));
+    let rows = numbers.next()??;
+    let columns = numbers.next()??;
+    Some((columns, rows))
+}
+
+// Orbits the scene in the terminal, drawing over the same spot every frame, until the
+// program is interrupted
+pub fn run(framebuffer: &mut Framebuffer, scene: &Scene, camera: &Camera, settings: &RenderSettings) -> io::Result<()> {
+    let depth = ColorDepth::detect();
+    let mut camera = *camera;
+    let mut stdout = io::stdout().lock();
+    // Clear the screen once, after that every frame starts from the top left corner
+    write!(stdout, "\x1b[2J")?;
+
+    let mut last = Instant::now();
+    loop {
+        render(framebuffer, scene, &camera, settings);
+        write!(stdout, "\x1b[H{}", encode(framebuffer, depth))?;
+        stdout.flush()?;
+
+        let elapsed = last.elapsed();
+        if elapsed < FRAME_INTERVAL {
+            thread::sleep(FRAME_INTERVAL - elapsed);
+        }
+        let now = Instant::now();
+        camera.orbit(ORBIT_SPEED * (now - last).as_secs_f32(), 0.0);
+        last = now;
+    }
+}
+
+// The framebuffer as lines of upper half blocks, each colored with the pixel above as the
+// foreground and the one below as the background. An odd last row leaves the bottom halves
+// in the terminal's own background. Colors are only written when they change.
+pub fn encode(framebuffer: &Framebuffer, depth: ColorDepth) -> String {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut text = String::with_capacity(width * height.div_ceil(2) * 24);
+    for y in (0..height).step_by(2) {
+        let (mut foreground, mut background) = (None, None);
+        for x in 0..width {
+            let top = framebuffer.buffer[y * width + x];
+            let bottom = (y + 1 < height).then(|| framebuffer.buffer[(y + 1) * width + x]);
+            if foreground != Some(top) {
+                text += &color_code(top, 38, depth);
+                foreground = Some(top);
+            }
+            if background != Some(bottom) {
+                match bottom {
+                    Some(color) => text += &color_code(color, 48, depth),
+                    None => text += "\x1b[49m",
+                }
+                background = Some(bottom);
+            }
+            text.push('▀');
+        }
+        // Reset before the line break so the colors don't run on to the end of the line
+        text += "\x1b[0m\n";
+    }
+    text
+}
+
+// The escape sequence that sets the foreground (`layer` 38) or background (48) color to a
+// 0RGB pixel
+fn color_code(pixel: u32, layer: u8, depth: ColorDepth) -> String {
+    let (r, g, b) = ((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8);
+    let mut code = String::new();
+    match depth {
+        ColorDepth::TrueColor => write!(code, "\x1b[{};2;{};{};{}m", layer, r, g, b),
+        ColorDepth::Palette256 => write!(code, "\x1b[{};5;{}m", layer, palette_index(r, g, b)),
+    }
+    .expect("writing to a String can't fail");
+    code
+}
+
+// The closest of the xterm palette's 6 x 6 x 6 color cube (16-231) and its 24 grays
+// (232-255). The 16 system colors differ between terminals, so they're never picked.
+fn palette_index(r: u8, g: u8, b: u8) -> u8 {
+    // The levels of the cube's six steps on each channel
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level = |c: u8| (0..6).min_by_key(|&i| (LEVELS[i] as i32 - c as i32).abs()).unwrap_or(0);
+    let distance = |(pr, pg, pb): (u8, u8, u8)| {
+        let d = |p: u8, c: u8| (p as i32 - c as i32).pow(2);
+        d(pr, r) + d(pg, g) + d(pb, b)
+    };
+
+    let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube = (16 + 36 * ri + 6 * gi + bi) as u8;
+    let cube_color = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+
+    // Grays run from 8 to 238 in steps of 10
+    let average = (r as i32 + g as i32 + b as i32) / 3;
+    let gray_step = ((average - 8 + 5) / 10).clamp(0, 23);
+    let gray_level = (8 + gray_step * 10) as u8;
+    let gray = (232 + gray_step) as u8;
+
+    if distance((gray_level, gray_level, gray_level)) < distance(cube_color) {
+        gray
+    } else {
+        cube
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, pixels: &[u32]) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(width, pixels.len() / width);
+        framebuffer.buffer.copy_from_slice(pixels);
+        framebuffer
+    }
+
+    #[test]
+    fn two_rows_make_one_line_of_half_blocks() {
+        let text = encode(&image(2, &[0x8b4513, 0x8b4513, 0x000000, 0xffffff]), ColorDepth::TrueColor);
+        // The same foreground isn't written twice
+        assert_eq!(text, "\x1b[38;2;139;69;19m\x1b[48;2;0;0;0m▀\x1b[48;2;255;255;255m▀\x1b[0m\n");
+    }
+
+    #[test]
+    fn odd_last_row_keeps_the_terminal_background() {
+        let text = encode(&image(1, &[0x102030, 0x405060, 0x708090]), ColorDepth::TrueColor);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, ["\x1b[38;2;16;32;48m\x1b[48;2;64;80;96m▀\x1b[0m", "\x1b[38;2;112;128;144m\x1b[49m▀\x1b[0m"]);
+    }
+
+    #[test]
+    fn palette_colors_are_the_nearest_of_the_cube_and_grays() {
+        assert_eq!(palette_index(0, 0, 0), 16);
+        assert_eq!(palette_index(255, 255, 255), 231);
+        assert_eq!(palette_index(128, 128, 128), 244);
+        assert_eq!(palette_index(255, 0, 0), 196);
+        assert_eq!(palette_index(95, 135, 175), 67);
+        assert_eq!(encode(&image(1, &[0xff0000, 0x808080]), ColorDepth::Palette256), "\x1b[38;5;196m\x1b[48;5;244m▀\x1b[0m\n");
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use crate::color::Color;
+
+// An image loaded from a PNG file. Scene files refer to textures by their path, which is
+// resolved relative to the working directory. The pixels are shared between clones, so
+// materials and scenes holding a texture stay cheap to copy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Texture {
+    pub path: String,
+    pub width: usize,
+    pub height: usize,
+    pixels: Arc<[Color]>,
+}
+
+impl Texture {
+    pub fn load(path: &str) -> Result<Texture, String> {
+        let file = File::open(path).map_err(|err| format!("could not open texture '{}': {}", path, err))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        // Palette, grayscale and 16-bit images all come out as 8-bit channels
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let invalid = |err: png::DecodingError| format!("invalid texture '{}': {}", path, err);
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let mut data = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let info = reader.next_frame(&mut data).map_err(invalid)?;
+
+        let channels = info.color_type.samples();
+        let pixels = data[..info.line_size * info.height as usize]
+            .chunks(info.line_size)
+            .flat_map(|row| row.chunks(channels).take(info.width as usize))
+            .map(|pixel| match channels {
+                // Gray and gray + alpha
+                1 | 2 => Color::new(pixel[0], pixel[0], pixel[0]),
+                // RGB and RGBA, alpha is ignored
+                _ => Color::new(pixel[0], pixel[1], pixel[2]),
+            })
+            .collect();
+
+        Ok(Texture {
+            path: path.to_string(),
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+
+    // Nearest-texel lookup with (0, 0) at the top-left corner; coordinates outside
+    // 0..1 are clamped to the edge
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        let x = ((u.clamp(0.0, 1.0) * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v.clamp(0.0, 1.0) * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl TryFrom<String> for Texture {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        Texture::load(&path)
+    }
+}
+
+impl From<Texture> for String {
+    fn from(texture: Texture) -> Self {
+        texture.path
+    }
+}
@@ -0,0 +1,102 @@
+use nalgebra_glm::Vec3;
+
+// Maps unbounded linear radiance into the 0..1 display range before sRGB encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    None,     // Clip each channel at 1, which is what the 8-bit pipeline used to do
+    Reinhard, // c / (1 + c), bright values roll off towards white
+    Aces,     // Narkowicz's fit of the ACES filmic curve, with more contrast than Reinhard
+}
+
+impl ToneMap {
+    pub fn apply(self, radiance: Vec3) -> Vec3 {
+        match self {
+            ToneMap::None => radiance.map(|c| c.clamp(0.0, 1.0)),
+            ToneMap::Reinhard => radiance.map(|c| {
+                let c = c.max(0.0);
+                c / (1.0 + c)
+            }),
+            ToneMap::Aces => radiance.map(aces),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(ToneMap::None),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" => Some(ToneMap::Aces),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMap::None => "none",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Aces => "aces",
+        }
+    }
+
+    // Cycles through the operators, for switching between them at runtime
+    pub fn next(self) -> ToneMap {
+        match self {
+            ToneMap::None => ToneMap::Reinhard,
+            ToneMap::Reinhard => ToneMap::Aces,
+            ToneMap::Aces => ToneMap::None,
+        }
+    }
+}
+
+fn aces(c: f32) -> f32 {
+    // The curve reaches 1 a little past 7. Stopping at 8 keeps the squares finite for huge
+    // inputs, which would otherwise come out as infinity over infinity.
+    let c = c.clamp(0.0, 8.0);
+    ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aces_of(c: f32) -> f32 {
+        ToneMap::Aces.apply(Vec3::repeat(c)).x
+    }
+
+    #[test]
+    fn aces_keeps_black_black() {
+        assert_eq!(aces_of(0.0), 0.0);
+        assert_eq!(aces_of(-1.0), 0.0);
+    }
+
+    #[test]
+    fn aces_maps_middle_gray_in_between_with_more_contrast_than_reinhard() {
+        let gray = aces_of(0.18);
+        assert!(gray > 0.2 && gray < 0.35, "{}", gray);
+        assert!(gray > ToneMap::Reinhard.apply(Vec3::repeat(0.18)).x);
+        // Brighter in only gets brighter out
+        let steps: Vec<f32> = (0..=40).map(|i| aces_of(i as f32 * 0.25)).collect();
+        assert!(steps.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", steps);
+    }
+
+    #[test]
+    fn aces_rolls_large_inputs_off_to_white() {
+        assert!(aces_of(2.0) > 0.9 && aces_of(2.0) < 1.0, "{}", aces_of(2.0));
+        for c in [10.0, 1e3, 1e6, f32::MAX] {
+            assert_eq!(aces_of(c), 1.0, "{}", c);
+        }
+        // Each channel on its own
+        assert_eq!(ToneMap::Aces.apply(Vec3::new(0.0, 0.18, 1e3)), Vec3::new(0.0, aces_of(0.18), 1.0));
+    }
+
+    #[test]
+    fn names_read_back_and_next_visits_every_operator() {
+        let mut tone_map = ToneMap::None;
+        for _ in 0..3 {
+            assert_eq!(ToneMap::from_name(tone_map.name()), Some(tone_map));
+            assert_eq!(ToneMap::from_name(&tone_map.name().to_uppercase()), Some(tone_map));
+            tone_map = tone_map.next();
+        }
+        assert_eq!(tone_map, ToneMap::None);
+        assert_eq!(ToneMap::from_name("filmic"), None);
+    }
+}
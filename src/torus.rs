@@ -0,0 +1,154 @@
+use nalgebra_glm::{Vec3, dot};
+use serde::{Deserialize, Serialize};
+use crate::ray_intersect::{RayIntersect, Material, Intersect, Ray};
+use crate::aabb::Aabb;
+
+// Number of intervals the ray's path through the bounding sphere is split into when
+// looking for a sign change of the torus equation
+const ROOT_SEARCH_STEPS: usize = 128;
+const BISECTION_STEPS: usize = 40;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Torus {
+    pub center: Vec3,
+    pub axis: Vec3,         // Normal of the plane the ring lies in
+    pub major_radius: f32,  // Distance from the center to the middle of the tube
+    pub minor_radius: f32,  // Radius of the tube
+    pub material: Material,
+}
+
+impl Torus {
+    // The ring's circle, as for a disk, grown by the tube's radius
+    pub fn bounding_box(&self) -> Aabb {
+        let axis = self.axis.normalize();
+        let half_size = axis.map(|a| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius);
+        Aabb::around(&self.center, &half_size)
+    }
+
+    // Orthonormal frame with the torus axis as local y
+    fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let axis = self.axis.normalize();
+        let helper = if axis.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 0.0, 1.0) };
+        let u = axis.cross(&helper).normalize();
+        let w = u.cross(&axis);
+        (u, axis, w)
+    }
+
+    // Implicit torus equation in local coordinates, negative inside the tube:
+    // (|p|² + R² - r²)² - 4R²(x² + z²)
+    fn implicit(&self, p: &Vec3) -> f32 {
+        let major = self.major_radius * self.major_radius;
+        let minor = self.minor_radius * self.minor_radius;
+        let k = dot(p, p) + major - minor;
+        k * k - 4.0 * major * (p.x * p.x + p.z * p.z)
+    }
+}
+
+impl RayIntersect for Torus {
+    fn ray_intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Intersect {
+        let (u, v, w) = self.basis();
+        let to_local = |p: &Vec3| Vec3::new(dot(p, &u), dot(p, &v), dot(p, &w));
+        let origin = to_local(&(ray.origin - self.center));
+        let direction = to_local(&ray.direction).normalize();
+        let scale = ray.direction.magnitude();
+
+        // The torus equation is a quartic in t. Rather than solving it analytically, which is
+        // numerically fragile, step along the part of the ray inside the bounding sphere and
+        // refine the first sign change by bisection.
+        let bound = self.major_radius + self.minor_radius;
+        let b = dot(&origin, &direction);
+        let c = dot(&origin, &origin) - bound * bound;
+        let discriminant = b * b - c;
+        if discriminant <= 0.0 {
+            return Intersect::empty();
+        }
+        // Only the part of the ray inside both the bounding sphere and the range is searched,
+        // with the range scaled to the normalized direction
+        let t_enter = (-b - discriminant.sqrt()).max(t_min * scale);
+        let t_exit = (-b + discriminant.sqrt()).min(t_max * scale);
+        if t_exit <= t_enter {
+            return Intersect::empty();
+        }
+
+        let f = |t: f32| self.implicit(&(origin + direction * t));
+        let step = (t_exit - t_enter) / ROOT_SEARCH_STEPS as f32;
+        let mut t0 = t_enter;
+        let mut f0 = f(t0);
+
+        for i in 1..=ROOT_SEARCH_STEPS {
+            let t1 = t_enter + step * i as f32;
+            let f1 = f(t1);
+
+            if f0.signum() != f1.signum() {
+                let (mut low, mut high) = (t0, t1);
+                for _ in 0..BISECTION_STEPS {
+                    let mid = 0.5 * (low + high);
+                    if f(mid).signum() == f0.signum() {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                let t = 0.5 * (low + high);
+
+                // Gradient of the torus: away from the closest point on the tube's center circle
+                let local_point = origin + direction * t;
+                let ring = Vec3::new(local_point.x, 0.0, local_point.z);
+                let ring_point = if ring.magnitude() > 1e-6 { ring.normalize() * self.major_radius } else { ring };
+                let local_normal = (local_point - ring_point).normalize();
+                let normal = (u * local_normal.x + v * local_normal.y + w * local_normal.z).normalize();
+
+                // Distances are measured in units of the caller's direction vector
+                let distance = t / scale;
+                let point = ray.at(distance);
+                return Intersect::new(point, normal, distance, self.material.clone());
+            }
+
+            t0 = t1;
+            f0 = f1;
+        }
+
+        Intersect::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ring lying flat, with a hole of radius 0.75 around the vertical axis
+    fn ring() -> Torus {
+        Torus {
+            center: Vec3::zeros(),
+            axis: Vec3::y(),
+            major_radius: 1.0,
+            minor_radius: 0.25,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn ray_through_the_hole_misses() {
+        let ray = Ray::new(Vec3::new(0.1, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(!ring().ray_intersect(&ray, 0.0, f32::INFINITY).is_intersecting);
+    }
+
+    #[test]
+    fn ray_through_the_tube_hits_its_top() {
+        let ray = Ray::new(Vec3::new(1.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = ring().ray_intersect(&ray, 0.0, f32::INFINITY);
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - 2.75).abs() < 1e-4, "{}", hit.distance);
+        assert!((hit.normal - Vec3::y()).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn ray_along_the_ring_plane_hits_the_outside_first() {
+        let ray = Ray::new(Vec3::new(-3.0, 0.0, 0.0), Vec3::x());
+        let hit = ring().ray_intersect(&ray, 0.0, f32::INFINITY);
+        assert!(hit.is_intersecting);
+        assert!((hit.distance - 1.75).abs() < 1e-4, "{}", hit.distance);
+        assert!((hit.normal + Vec3::x()).magnitude() < 1e-3);
+    }
+}
@@ -0,0 +1,274 @@
+use nalgebra_glm::{self as glm, Mat4, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::ray_intersect::{Intersect, Ray, Span};
+
+// An affine object-to-world matrix together with its inverse, which is what intersection
+// actually needs. Scene files write either the matrix itself, column by column, or its
+// parts as e.g. `(translate: (0, 1, -4), rotate: (0, 45, 0), scale: 0.5)`; saving a scene
+// always writes the matrix.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "TransformDef", into = "Mat4")]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+    // Lets intersection skip moving rays in and out of a space that is the same as the world
+    pub is_identity: bool,
+}
+
+impl Transform {
+    // Returns None for singular matrices, e.g. a scale of zero
+    pub fn new(matrix: Mat4) -> Option<Transform> {
+        matrix.try_inverse().map(|inverse| Transform {
+            matrix,
+            inverse,
+            is_identity: matrix == Mat4::identity(),
+        })
+    }
+
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: Mat4::identity(),
+            inverse: Mat4::identity(),
+            is_identity: true,
+        }
+    }
+
+    // Scales first, then rotates about the X, Y and Z axes in that order by the given
+    // angles in degrees, then translates
+    pub fn from_parts(translate: Vec3, rotate: Vec3, scale: Vec3) -> Option<Transform> {
+        let rotation = glm::rotation(rotate.z.to_radians(), &Vec3::z())
+            * glm::rotation(rotate.y.to_radians(), &Vec3::y())
+            * glm::rotation(rotate.x.to_radians(), &Vec3::x());
+        Transform::new(glm::translation(&translate) * rotation * glm::scaling(&scale))
+    }
+
+    // The transform moved by `offset` and turned by `degrees` about the vertical axis
+    // through its own origin. Moving and turning keep an invertible matrix invertible.
+    pub fn moved(&self, offset: Vec3, degrees: f32) -> Transform {
+        let matrix = glm::translation(&offset) * self.matrix * glm::rotation(degrees.to_radians(), &Vec3::y());
+        Transform::new(matrix).unwrap_or_else(|| self.clone())
+    }
+
+    pub fn point_to_world(&self, point: &Vec3) -> Vec3 {
+        (self.matrix * Vec4::new(point.x, point.y, point.z, 1.0)).xyz()
+    }
+
+    pub fn point_to_local(&self, point: &Vec3) -> Vec3 {
+        (self.inverse * Vec4::new(point.x, point.y, point.z, 1.0)).xyz()
+    }
+
+    // Directions are deliberately not renormalized: a ray parameter t then names the same
+    // point in both spaces, so hit distances need no conversion
+    pub fn direction_to_local(&self, direction: &Vec3) -> Vec3 {
+        (self.inverse * Vec4::new(direction.x, direction.y, direction.z, 0.0)).xyz()
+    }
+
+    pub fn direction_to_world(&self, direction: &Vec3) -> Vec3 {
+        (self.matrix * Vec4::new(direction.x, direction.y, direction.z, 0.0)).xyz()
+    }
+
+    // Normals go through the inverse transpose to stay perpendicular under non-uniform scale
+    pub fn normal_to_world(&self, normal: &Vec3) -> Vec3 {
+        (self.inverse.transpose() * Vec4::new(normal.x, normal.y, normal.z, 0.0)).xyz().normalize()
+    }
+
+    // Intersects something in this transform's local space: the ray is moved into it,
+    // `intersect` runs on it, and the hit is moved back out
+    pub fn intersect(&self, ray: &Ray, intersect: impl FnOnce(&Ray) -> Intersect) -> Intersect {
+        if self.is_identity {
+            return intersect(ray);
+        }
+        self.hit_to_world(ray, intersect(&self.ray_to_local(ray)))
+    }
+
+    // The same for the spans of something in local space, see `RayIntersect::ray_spans`
+    pub fn spans(&self, ray: &Ray, spans: impl FnOnce(&Ray) -> Option<Vec<Span>>) -> Option<Vec<Span>> {
+        if self.is_identity {
+            return spans(ray);
+        }
+        let spans = spans(&self.ray_to_local(ray))?;
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Span {
+                    enter: self.hit_to_world(ray, span.enter),
+                    exit: self.hit_to_world(ray, span.exit),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn ray_to_local(&self, ray: &Ray) -> Ray {
+        // Built directly rather than with `Ray::new`, see `direction_to_local`
+        Ray {
+            origin: self.point_to_local(&ray.origin),
+            direction: self.direction_to_local(&ray.direction),
+            time: ray.time,
+        }
+    }
+
+    // A hit on the local ray moved back out onto the world `ray`
+    pub fn hit_to_world(&self, ray: &Ray, mut hit: Intersect) -> Intersect {
+        if hit.is_intersecting {
+            hit.point = ray.at(hit.distance);
+            hit.normal = self.normal_to_world(&hit.normal);
+            hit.tangent = self.direction_to_world(&hit.tangent);
+        }
+        hit
+    }
+
+    // The box around a local box once transformed, which may be looser than needed
+    pub fn bounds_to_world(&self, local: &Aabb) -> Aabb {
+        if local.is_empty() {
+            return *local;
+        }
+        local.corners().iter().fold(Aabb::empty(), |bounds, corner| {
+            let corner = self.point_to_world(corner);
+            bounds.union(&Aabb { min: corner, max: corner })
+        })
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+// Either way of writing a transform in a scene file. Each part may be left out.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TransformDef {
+    Matrix(Mat4),
+    Parts {
+        #[serde(default)]
+        translate: Vec3,
+        #[serde(default)]
+        rotate: Vec3,
+        #[serde(default)]
+        scale: ScaleDef,
+    },
+}
+
+// A scale factor for every axis, or one for all of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDef {
+    Uniform(f32),
+    PerAxis(Vec3),
+}
+
+impl Default for ScaleDef {
+    fn default() -> Self {
+        ScaleDef::Uniform(1.0)
+    }
+}
+
+impl TryFrom<TransformDef> for Transform {
+    type Error = String;
+
+    fn try_from(def: TransformDef) -> Result<Self, Self::Error> {
+        let transform = match def {
+            TransformDef::Matrix(matrix) => Transform::new(matrix),
+            TransformDef::Parts { translate, rotate, scale } => {
+                let scale = match scale {
+                    ScaleDef::Uniform(factor) => Vec3::repeat(factor),
+                    ScaleDef::PerAxis(factors) => factors,
+                };
+                Transform::from_parts(translate, rotate, scale)
+            }
+        };
+        transform.ok_or_else(|| "transform matrix is not invertible".to_string())
+    }
+}
+
+impl From<Transform> for Mat4 {
+    fn from(transform: Transform) -> Self {
+        transform.matrix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::ray_intersect::{Material, RayIntersect};
+    use crate::sphere::Sphere;
+
+    fn unit_cube() -> Cube {
+        Cube { center: Vec3::zeros(), size: Vec3::repeat(1.0), material: Material::default() }
+    }
+
+    #[test]
+    fn rotated_cube_is_hit_where_the_unrotated_one_misses() {
+        // Past the cube's side, but inside the corner that turning it brings forward
+        let ray = Ray::new(Vec3::new(0.6, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!unit_cube().ray_intersect(&ray, 0.0, f32::INFINITY).is_intersecting);
+
+        let transform = Transform::from_parts(Vec3::zeros(), Vec3::new(0.0, 45.0, 0.0), Vec3::repeat(1.0)).unwrap();
+        let hit = transform.intersect(&ray, |ray| unit_cube().ray_intersect(ray, 0.0, f32::INFINITY));
+        assert!(hit.is_intersecting);
+
+        // The turned faces meet |x| + |z| = √½, so the front one is crossed at z = √½ - 0.6
+        let z = 0.5f32.sqrt() - 0.6;
+        assert!((hit.distance - (5.0 - z)).abs() < 1e-5, "{}", hit.distance);
+        assert!((hit.point - Vec3::new(0.6, 0.0, z)).magnitude() < 1e-5);
+        assert!((hit.normal - Vec3::new(1.0, 0.0, 1.0).normalize()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Transform::from_parts(Vec3::zeros(), Vec3::new(0.0, 0.0, 45.0), Vec3::new(4.0, 1.0, 1.0)).unwrap();
+        // The local face normal (0, 1) is perpendicular to the face direction (1, 0)
+        let normal = transform.normal_to_world(&Vec3::y());
+        let along_face = transform.direction_to_world(&Vec3::x());
+        assert!(normal.dot(&along_face).abs() < 1e-5);
+        assert!((normal.magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    fn sphere(center: Vec3, radius: f32) -> Sphere {
+        Sphere { center, radius, material: Material::default(), velocity: Vec3::zeros() }
+    }
+
+    #[test]
+    fn doubled_unit_sphere_is_hit_like_a_sphere_of_radius_two() {
+        let center = Vec3::new(1.0, -0.5, -6.0);
+        let transform = Transform::from_parts(center, Vec3::zeros(), Vec3::repeat(2.0)).unwrap();
+        let unit = sphere(Vec3::zeros(), 1.0);
+        let big = sphere(center, 2.0);
+
+        for i in 0..50 {
+            let (x, y) = ((i % 10) as f32 * 0.12 - 0.6, (i / 10) as f32 * 0.12 - 0.3);
+            let ray = Ray::new(Vec3::zeros(), Vec3::new(x, y, -1.0));
+            let scaled = transform.intersect(&ray, |ray| unit.ray_intersect(ray, 0.0, f32::INFINITY));
+            let expected = big.ray_intersect(&ray, 0.0, f32::INFINITY);
+            assert_eq!(scaled.is_intersecting, expected.is_intersecting, "ray {}", i);
+            if expected.is_intersecting {
+                assert!((scaled.distance - expected.distance).abs() < 1e-4, "ray {}", i);
+                assert!((scaled.point - expected.point).magnitude() < 1e-4, "ray {}", i);
+                assert!((scaled.normal - expected.normal).magnitude() < 1e-4, "ray {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn squashed_sphere_normals_are_unit_and_perpendicular_to_it() {
+        // An ellipsoid with semi-axes 3, 1 and 0.5, whose surface is where
+        // (x / 3)² + y² + (z / 0.5)² = 1 and whose gradient there is the normal
+        let axes = Vec3::new(3.0, 1.0, 0.5);
+        let transform = Transform::from_parts(Vec3::zeros(), Vec3::zeros(), axes).unwrap();
+        let unit = sphere(Vec3::zeros(), 1.0);
+
+        for i in 0..20 {
+            let angle = i as f32 * 0.3;
+            let origin = Vec3::new(8.0 * angle.cos(), 0.4 * (i % 5) as f32 - 0.8, 8.0 * angle.sin());
+            let hit = transform.intersect(&Ray::new(origin, -origin), |ray| unit.ray_intersect(ray, 0.0, f32::INFINITY));
+            assert!(hit.is_intersecting, "ray {}", i);
+            assert!((hit.normal.magnitude() - 1.0).abs() < 1e-5);
+            let gradient = hit.point.component_div(&axes.component_mul(&axes)).normalize();
+            assert!((hit.normal - gradient).magnitude() < 1e-4, "ray {}: {:?} against {:?}", i, hit.normal, gradient);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::render::{render, RenderSettings};
+use crate::scene::Scene;
+
+// Renders a full 360° orbit around the scene camera's center into numbered PNGs
+// (frame_0000.png, frame_0001.png, ...) in `out_dir`, sized like the framebuffer
+pub fn render_turntable(
+    framebuffer: &mut Framebuffer,
+    scene: &Scene,
+    settings: &RenderSettings,
+    frames: u32,
+    out_dir: &str,
+) -> io::Result<()> {
+    fs::create_dir_all(out_dir)?;
+
+    let mut camera = scene.camera;
+    let step = 2.0 * PI / frames as f32;
+
+    for frame in 0..frames {
+        render(framebuffer, scene, &camera, settings);
+
+        let path = Path::new(out_dir).join(format!("frame_{:04}.png", frame));
+        framebuffer.save_png(&path.to_string_lossy())?;
+
+        // After the last step the camera is back where it started
+        camera.orbit(step, 0.0);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::DEFAULT_SCENE;
+
+    // Balls of different colours on three sides of the centre, so every quarter turn sees
+    // something else; the bear is too symmetric for that at this size
+    const LOPSIDED: &str = r#"Scene(
+        background: Solid((0, 0, 0)),
+        camera: (eye: (0.0, 0.0, 0.0), center: (0.0, 0.0, -5.0), up: (0.0, 1.0, 0.0)),
+        objects: [
+            Sphere(center: (1.5, 0.0, -5.0), radius: 1.0, material: (diffuse: (220, 40, 40))),
+            Sphere(center: (0.0, 0.0, -6.5), radius: 1.0, material: (diffuse: (40, 220, 40))),
+            Sphere(center: (-1.5, 0.0, -5.0), radius: 0.5, material: (diffuse: (40, 40, 220))),
+        ],
+        lights: [(position: (0.0, 5.0, -5.0), color: (255, 255, 255), intensity: 1.0)],
+    )"#;
+
+    #[test]
+    fn four_frames_give_four_distinct_files() {
+        let scene = Scene::parse(LOPSIDED, "lopsided.ron").unwrap();
+        let out_dir = std::env::temp_dir().join("raytracing_animal_turntable");
+        let _ = fs::remove_dir_all(&out_dir);
+
+        let mut framebuffer = Framebuffer::new(8, 8);
+        render_turntable(&mut framebuffer, &scene, &RenderSettings::default(), 4, &out_dir.to_string_lossy()).unwrap();
+
+        let mut frames: Vec<Vec<u8>> = (0..4)
+            .map(|frame| fs::read(out_dir.join(format!("frame_{:04}.png", frame))).unwrap())
+            .collect();
+        assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 4);
+        frames.sort();
+        frames.dedup();
+        assert_eq!(frames.len(), 4);
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn camera_returns_to_its_start_after_the_loop() {
+        let scene = Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap();
+        let mut camera = scene.camera;
+        for _ in 0..4 {
+            camera.orbit(2.0 * PI / 4.0, 0.0);
+        }
+        assert!((camera.eye - scene.camera.eye).magnitude() < 1e-4);
+        assert_eq!(camera.center, scene.camera.center);
+        assert_eq!(camera.up, scene.camera.up);
+    }
+}
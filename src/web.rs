@@ -0,0 +1,78 @@
+use std::cell::RefCell;
+
+use crate::animation::Animation;
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::render::{render, RenderSettings};
+use crate::scene::{Scene, DEFAULT_SCENE};
+
+// The browser front-end: web/index.html calls these exports of the .wasm and puts each
+// frame in a canvas. It shows the built-in bear with its animation playing, and the page's
+// arrow keys orbit the camera around it. Pages run on a single thread and show one viewer,
+// so it lives in a thread local.
+struct Viewer {
+    scene: Scene,
+    animation: Animation,
+    time: f32, // Seconds the animation has played for
+    camera: Camera,
+    settings: RenderSettings,
+    framebuffer: Framebuffer,
+    pixels: Vec<u8>, // The frame as RGBA bytes, the layout of a canvas's ImageData
+}
+
+thread_local! {
+    static VIEWER: RefCell<Option<Viewer>> = const { RefCell::new(None) };
+}
+
+// Sets up a `width` x `height` view of the bear, with the settings the native binary
+// starts with. False if the scene couldn't be read.
+#[no_mangle]
+pub extern "C" fn viewer_init(width: usize, height: usize) -> bool {
+    let Ok(mut scene) = Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron") else {
+        return false;
+    };
+    let settings = RenderSettings::default();
+    let animation = Animation::new(&mut scene, settings.seed);
+    let viewer = Viewer {
+        camera: scene.camera,
+        scene,
+        animation,
+        time: 0.0,
+        settings,
+        framebuffer: Framebuffer::new(width, height),
+        pixels: vec![0; width * height * 4],
+    };
+    VIEWER.with(|cell| *cell.borrow_mut() = Some(viewer));
+    true
+}
+
+// Turns the camera around the point it looks at, by angles in radians as `Camera::orbit`
+#[no_mangle]
+pub extern "C" fn viewer_orbit(delta_yaw: f32, delta_pitch: f32) {
+    VIEWER.with(|cell| {
+        if let Some(viewer) = cell.borrow_mut().as_mut() {
+            viewer.camera.orbit(delta_yaw, delta_pitch);
+        }
+    });
+}
+
+// Plays the animation `dt` seconds on and renders the frame, returning where its RGBA bytes
+// are in the module's memory: width x height x 4 of them, row by row from the top. Null
+// before `viewer_init`.
+#[no_mangle]
+pub extern "C" fn viewer_frame(dt: f32) -> *const u8 {
+    VIEWER.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let Some(viewer) = cell.as_mut() else {
+            return std::ptr::null();
+        };
+        viewer.time += dt;
+        viewer.animation.update(&mut viewer.scene, viewer.time, viewer.settings.shutter);
+        render(&mut viewer.framebuffer, &viewer.scene, &viewer.camera, &viewer.settings);
+
+        for (rgba, &pixel) in viewer.pixels.chunks_exact_mut(4).zip(&viewer.framebuffer.buffer) {
+            rgba.copy_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 255]);
+        }
+        viewer.pixels.as_ptr()
+    })
+}
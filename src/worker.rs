@@ -0,0 +1,233 @@
+use nalgebra_glm::Vec3;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use raytracing_animal::camera::Camera;
+use raytracing_animal::framebuffer::Framebuffer;
+use raytracing_animal::render::{render_tile, tiles, write_tile, RenderSettings, RenderStats, Tile};
+use raytracing_animal::scene::Scene;
+
+// Everything needed to render one frame, owned so it can be handed to the worker thread
+pub struct RenderJob {
+    pub scene: Arc<Scene>,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+    pub width: usize,
+    pub height: usize,
+    pub pass: u32, // See `render_tile`
+}
+
+// A finished tile, tagged with the job it belongs to so tiles of superseded frames can be told apart
+pub struct TileResult {
+    pub generation: u64,
+    pub tile: Tile,
+    pub radiance: Vec<Vec3>,
+    pub pass: u32,
+    // Work done for this tile, with the time counted from the start of the frame
+    pub stats: RenderStats,
+    pub last: bool, // The final tile of its frame
+}
+
+// Renders frames on a background thread and streams the tiles back as they finish, so the
+// window keeps handling events during long renders
+pub struct RenderWorker {
+    jobs: Option<Sender<(u64, RenderJob)>>,
+    results: Receiver<TileResult>,
+    handle: Option<JoinHandle<()>>,
+    generation: u64,
+    frame_stats: RenderStats, // Accumulated over the latest frame's tiles received so far
+    busy: bool,               // The latest frame hasn't come back complete yet
+}
+
+impl RenderWorker {
+    pub fn spawn() -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (result_sender, results) = mpsc::channel();
+        let handle = thread::spawn(move || run(job_receiver, result_sender));
+
+        RenderWorker {
+            jobs: Some(jobs),
+            results,
+            handle: Some(handle),
+            generation: 0,
+            frame_stats: RenderStats::default(),
+            busy: false,
+        }
+    }
+
+    // Starts rendering a new frame. A frame still in progress is abandoned after its
+    // current tile, and any of its tiles already sent are ignored by `poll`.
+    pub fn submit(&mut self, job: RenderJob) {
+        self.generation += 1;
+        self.frame_stats = RenderStats::default();
+        self.busy = true;
+        if let Some(jobs) = &self.jobs {
+            // Only fails when the worker has exited, leaving nothing to render with
+            let _ = jobs.send((self.generation, job));
+        }
+    }
+
+    // Copies every tile finished since the last call into the framebuffer, encoding them with
+    // the current display settings. Returns the frame's stats when the latest frame has just
+    // completed.
+    pub fn poll(&mut self, framebuffer: &mut Framebuffer, settings: &RenderSettings) -> Option<RenderStats> {
+        let mut finished = None;
+        while let Ok(result) = self.results.try_recv() {
+            if result.generation != self.generation {
+                continue;
+            }
+            write_tile(framebuffer, &result.tile, &result.radiance, result.pass, settings);
+            self.frame_stats.merge(&result.stats);
+            if result.last {
+                finished = Some(self.frame_stats);
+                self.busy = false;
+            }
+        }
+        finished
+    }
+
+    // Whether the latest submitted frame is still being rendered
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+
+    // Closes the job channel, which stops the worker after its current tile, and waits for it
+    pub fn shutdown(&mut self) {
+        self.jobs = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for RenderWorker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// The worker thread: renders the newest job tile by tile until the job channel closes or
+// the receiving end of the results goes away
+fn run(jobs: Receiver<(u64, RenderJob)>, results: Sender<TileResult>) {
+    let mut next = jobs.recv().ok();
+
+    while let Some(mut current) = next.take() {
+        // Jobs that piled up while rendering are stale except for the newest one
+        while let Ok(newer) = jobs.try_recv() {
+            current = newer;
+        }
+        let (generation, job) = current;
+        let start = Instant::now();
+
+        let tiles = tiles(job.width, job.height, job.settings.region);
+        for (i, tile) in tiles.iter().enumerate() {
+            match jobs.try_recv() {
+                Ok(newer) => {
+                    next = Some(newer);
+                    break;
+                }
+                Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let (radiance, mut stats) =
+                render_tile(job.width, job.height, tile, &job.scene, &job.camera, &job.settings, job.pass);
+            stats.millis = start.elapsed().as_secs_f64() * 1000.0;
+            let result = TileResult {
+                generation,
+                tile: *tile,
+                radiance,
+                pass: job.pass,
+                stats,
+                last: i + 1 == tiles.len(),
+            };
+            if results.send(result).is_err() {
+                return;
+            }
+        }
+
+        if next.is_none() {
+            next = jobs.recv().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytracing_animal::render::render;
+    use raytracing_animal::scene::DEFAULT_SCENE;
+    use std::time::Duration;
+
+    fn job(scene: &Arc<Scene>, camera: Camera) -> RenderJob {
+        RenderJob {
+            scene: Arc::clone(scene),
+            camera,
+            settings: RenderSettings::default(),
+            width: 48,
+            height: 40,
+            pass: 0,
+        }
+    }
+
+    // Polls until the latest frame is complete, failing after a while rather than hanging
+    fn wait(worker: &mut RenderWorker, framebuffer: &mut Framebuffer) -> RenderStats {
+        let start = Instant::now();
+        loop {
+            if let Some(stats) = worker.poll(framebuffer, &RenderSettings::default()) {
+                return stats;
+            }
+            assert!(start.elapsed() < Duration::from_secs(30), "the worker never finished the frame");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn tiles_from_the_worker_make_up_the_frame() {
+        let scene = Arc::new(Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap());
+        let mut expected = Framebuffer::new(48, 40);
+        render(&mut expected, &scene, &scene.camera, &RenderSettings::default());
+
+        let mut worker = RenderWorker::spawn();
+        assert!(!worker.is_busy());
+        worker.submit(job(&scene, scene.camera));
+        assert!(worker.is_busy());
+        let mut framebuffer = Framebuffer::new(48, 40);
+        let stats = wait(&mut worker, &mut framebuffer);
+        assert!(!worker.is_busy());
+        assert_eq!(framebuffer.buffer, expected.buffer);
+        assert_eq!(stats.primary_rays, 48 * 40);
+        worker.shutdown();
+    }
+
+    #[test]
+    fn only_the_latest_frame_is_shown() {
+        let scene = Arc::new(Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap());
+        let mut turned = scene.camera;
+        turned.orbit(1.0, 0.0);
+        let mut expected = Framebuffer::new(48, 40);
+        render(&mut expected, &scene, &turned, &RenderSettings::default());
+
+        // The first frame is superseded before it can finish, or right after; either way
+        // none of its tiles may end up in the image
+        let mut worker = RenderWorker::spawn();
+        worker.submit(job(&scene, scene.camera));
+        worker.submit(job(&scene, turned));
+        let mut framebuffer = Framebuffer::new(48, 40);
+        wait(&mut worker, &mut framebuffer);
+        assert_eq!(framebuffer.buffer, expected.buffer);
+    }
+
+    #[test]
+    fn shutdown_stops_an_idle_worker() {
+        let mut worker = RenderWorker::spawn();
+        worker.shutdown();
+        assert!(worker.handle.is_none());
+        // Jobs after that go nowhere instead of failing
+        let scene = Arc::new(Scene::parse(DEFAULT_SCENE, "scenes/teddy.ron").unwrap());
+        worker.submit(job(&scene, scene.camera));
+        assert_eq!(worker.poll(&mut Framebuffer::new(48, 40), &RenderSettings::default()), None);
+    }
+}